@@ -0,0 +1,116 @@
+use std::fs::{read_to_string, File};
+use std::io::{BufReader, ErrorKind};
+use serde::Deserialize;
+use serde_json::from_reader;
+use crate::error::{Error, Result};
+
+
+
+// one entry of accounts.json, e.g. {"label": "ISA", "key_path": "isa_key.txt", "currency": "GBP", "environment": "demo"}.
+// base_url replaces the environment's host, e.g. with a local stand-in for testing
+#[derive(Debug, Deserialize, Clone)]
+pub struct Account {
+    pub label: String,
+    pub key_path: String,
+
+    #[serde(default = "default_currency")]
+    pub currency: String,
+
+    #[serde(default)]
+    pub environment: Environment,
+
+    #[serde(default)]
+    pub base_url: Option<String>,
+
+    #[serde(skip)]
+    pub api_key: String
+}
+
+impl Account {
+    // where this account's API is, the environment's host unless accounts.json gives another
+    pub fn base_url(&self) -> &str {
+        self.base_url.as_deref().map_or(self.environment.base_url(), |url| url.trim_end_matches('/'))
+    }
+}
+
+fn default_currency() -> String {
+    String::from("GBP")
+}
+
+
+
+// live money or paper trading; both are the same API on different hosts, with separate API keys
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    #[default]
+    Live,
+    Demo
+}
+
+impl Environment {
+    pub fn base_url(&self) -> &'static str {
+        match self {
+            Environment::Live => "https://live.trading212.com",
+            Environment::Demo => "https://demo.trading212.com"
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Environment::Live => "live",
+            Environment::Demo => "demo"
+        }
+    }
+}
+
+
+
+// reads accounts.json if present, otherwise falls back to the single api_key.txt setup. a file that is there
+// but can't be read is an error, rather than a reason to quietly report on another account
+pub fn load_accounts(path: &str) -> Result<Vec<Account>> {
+
+    let mut accounts: Vec<Account> = match File::open(path) {
+        Ok(file) => from_reader(BufReader::new(file)).map_err(|e| Error::parse(path, e))?,
+        Err(e) if e.kind() != ErrorKind::NotFound => return Err(Error::io(path, e)),
+        Err(_) => vec![Account {
+            label: String::from("Invest"),
+            key_path: String::from("api_key.txt"),
+            currency: default_currency(),
+            environment: Environment::Live,
+            base_url: None,
+            api_key: String::new()
+        }]
+    };
+
+    if accounts.is_empty() {
+        return Err(Error::parse(path, "no accounts listed"))
+    }
+
+    let mut labels: Vec<String> = Vec::new();
+    for account in &mut accounts {
+        let lowered = account.label.to_ascii_lowercase();
+        if lowered == "all" || lowered.starts_with("all-") {
+            return Err(Error::parse(path, format!("account label {} is reserved for the consolidated views", account.label)))
+        }
+        if labels.contains(&account.label) {
+            return Err(Error::parse(path, format!("account label {} is used twice", account.label)))
+        }
+        labels.push(account.label.clone());
+        account.api_key = read_to_string(&account.key_path)
+            .map_err(|e| Error::io(format!("{} (API key of account {})", account.key_path, account.label), e))?
+            .split_whitespace()
+            .collect();
+    }
+
+    // the reporting currency is the first account's; FX and prices are converted into it once for everyone, so
+    // accounts in other currencies can't be reported from the same accounts.json
+    let base = accounts[0].currency.clone();
+    if accounts.iter().any(|a| a.currency != base) {
+        let listed: Vec<String> = accounts.iter().map(|a| format!("{} in {}", a.label, a.currency)).collect();
+        return Err(Error::parse(path, format!("accounts are in different currencies ({}). every account is reported in the first one's \
+            currency, so give the accounts in each currency their own accounts.json and run t212 from a folder per currency", listed.join(", "))))
+    }
+
+    Ok(accounts)
+}
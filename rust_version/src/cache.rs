@@ -0,0 +1,247 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, File};
+use std::io::BufReader;
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use serde_json::from_reader;
+use crate::error::{Error, Result};
+
+
+
+const RECENT_DAYS: i64 = 5;           // bars this close to the download date may still be revised (today's is intraday), so they are refetched
+
+
+
+// what one provider returned for one symbol. from..to is the range that has been asked for,
+// everything up to settled is final and never downloaded again
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CacheEntry {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub settled: NaiveDate,
+    pub created: NaiveDate,
+    pub used: NaiveDate,
+    pub closes: BTreeMap<NaiveDate, f64>
+}
+
+impl CacheEntry {
+
+    // date ranges still to download to answer start..end; earlier history, and anything after settled
+    pub fn missing(&self, start_date: NaiveDate, end_date: NaiveDate) -> Vec<(NaiveDate, NaiveDate)> {
+
+        let mut ranges: Vec<(NaiveDate, NaiveDate)> = Vec::new();
+        if start_date < self.from {
+            ranges.push((start_date, (self.from - Duration::days(1)).min(end_date)));
+        }
+        let refresh_from = (self.settled + Duration::days(1)).max(start_date);
+        if end_date >= refresh_from {
+            ranges.push((refresh_from, end_date));
+        }
+        ranges
+    }
+}
+
+
+
+// price_cache.json, closes by provider, then symbol, then date
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct PriceCache {
+    #[serde(skip)]
+    pub path: String,
+
+    pub providers: BTreeMap<String, BTreeMap<String, CacheEntry>>
+}
+
+impl PriceCache {
+
+    // a missing or unreadable cache is an empty one, it only costs a slower start
+    pub fn load(path: &str) -> PriceCache {
+        let mut cache: PriceCache = match File::open(path) {
+            Ok(file) => from_reader(BufReader::new(file)).unwrap_or_default(),
+            Err(_) => PriceCache::default()
+        };
+        cache.path = path.to_string();
+        cache
+    }
+
+    // written to a temporary file first, so an interrupted run can't leave half a cache behind
+    pub fn save(&self) -> Result<()> {
+        let temp = format!("{}.tmp", self.path);
+        let text = serde_json::to_string(self).map_err(|e| Error::parse(&self.path, e))?;
+        fs::write(&temp, text).map_err(|e| Error::io(&temp, e))?;
+        fs::rename(&temp, &self.path).map_err(|e| Error::io(&self.path, e))
+    }
+
+    pub fn get(&self, provider: &str, symbol: &str) -> Option<&CacheEntry> {
+        self.providers.get(provider).and_then(|symbols| symbols.get(symbol))
+    }
+
+    // merges a download of from..to made on today. a symbol the provider doesn't know (no bars, nothing cached yet) is
+    // not stored, so the chain can move on to the next provider next time too
+    pub fn store(&mut self, provider: &str, symbol: &str, from: NaiveDate, to: NaiveDate, closes: HashMap<NaiveDate, f64>, today: NaiveDate) {
+
+        let settled = to.min(today - Duration::days(RECENT_DAYS));
+        let symbols = self.providers.entry(provider.to_string()).or_default();
+
+        match symbols.get_mut(symbol) {
+            Some(entry) => {
+                entry.from = entry.from.min(from);
+                entry.to = entry.to.max(to);
+                // a refresh that starts after settled can't move it backwards
+                if from <= entry.settled + Duration::days(1) {
+                    entry.settled = entry.settled.max(settled);
+                }
+                entry.closes.extend(closes);
+            },
+            None if !closes.is_empty() => {
+                symbols.insert(symbol.to_string(), CacheEntry {
+                    from,
+                    to,
+                    settled,
+                    created: today,
+                    used: today,
+                    closes: closes.into_iter().collect()
+                });
+            },
+            None => {}
+        }
+    }
+
+    // cached closes for start..end (plus the week before, so a start on a weekend still has a Friday to fill from)
+    pub fn closes(&mut self, provider: &str, symbol: &str, start_date: NaiveDate, end_date: NaiveDate, today: NaiveDate) -> HashMap<NaiveDate, f64> {
+        match self.providers.get_mut(provider).and_then(|symbols| symbols.get_mut(symbol)) {
+            Some(entry) => {
+                entry.used = today;
+                entry.closes.range(start_date - Duration::days(7)..=end_date).map(|(d, p)| (*d, *p)).collect()
+            },
+            None => HashMap::new()
+        }
+    }
+
+    // yahoo's closes are split-adjusted, so history downloaded before a split is in pre-split terms and has to go
+    pub fn invalidate_split(&mut self, symbol: &str, split_date: NaiveDate) -> bool {
+        let mut dropped = false;
+        for symbols in self.providers.values_mut() {
+            if symbols.get(symbol).is_some_and(|entry| entry.created <= split_date) {
+                symbols.remove(symbol);
+                dropped = true;
+            }
+        }
+        dropped
+    }
+
+    // drops entries no run has used for max_age days, returns how many went
+    pub fn prune(&mut self, max_age: i64, today: NaiveDate) -> usize {
+        let mut pruned = 0;
+        for symbols in self.providers.values_mut() {
+            let before = symbols.len();
+            symbols.retain(|_, entry| (today - entry.used).num_days() <= max_age);
+            pruned += before - symbols.len();
+        }
+        self.providers.retain(|_, symbols| !symbols.is_empty());
+        pruned
+    }
+
+    // (provider, symbol, from, to) of every entry, or only those of symbol
+    pub fn ranges(&self, symbol: Option<&str>) -> Vec<(String, String, NaiveDate, NaiveDate)> {
+        self.providers.iter()
+            .flat_map(|(provider, symbols)| symbols.iter().map(move |(s, entry)| (provider.clone(), s.clone(), entry.from, entry.to)))
+            .filter(|(_, s, _, _)| symbol.map_or(true, |wanted| wanted == s))
+            .collect()
+    }
+
+    pub fn remove(&mut self, provider: &str, symbol: &str) {
+        if let Some(symbols) = self.providers.get_mut(provider) {
+            symbols.remove(symbol);
+        }
+    }
+
+    pub fn print(&self) {
+        let size = fs::metadata(&self.path).map(|m| m.len()).unwrap_or(0);
+        println!("\n  Price cache {} ({:.1} kB)", self.path, size as f64 / 1000.0);
+        println!("  ____________________________________________________________________________");
+        println!("  {0: <8} {1: <16} {2: >6} {3: >11} {4: >11} {5: >11} {6: >11}", "source", "symbol", "bars", "from", "to", "settled", "last used");
+        println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+        for (provider, symbols) in &self.providers {
+            for (symbol, entry) in symbols {
+                println!("  {0: <8} {1: <16} {2: >6} {3: >11} {4: >11} {5: >11} {6: >11}",
+                    provider, symbol, entry.closes.len(), entry.from.to_string(), entry.to.to_string(), entry.settled.to_string(), entry.used.to_string());
+            }
+        }
+        println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+    use std::str::FromStr;
+
+    fn bars(days: &[&str]) -> HashMap<NaiveDate, f64> {
+        days.iter().map(|d| (day(d), 1.0)).collect()
+    }
+
+    #[test]
+    fn closed_positions_are_never_refetched() {
+        let mut cache = PriceCache::default();
+        cache.store("yahoo", "AAPL", day("2023-01-02"), day("2023-06-30"), bars(&["2023-01-03", "2023-06-30"]), day("2024-01-10"));
+
+        let entry = cache.get("yahoo", "AAPL").unwrap();
+        assert!(entry.missing(day("2023-01-02"), day("2023-06-30")).is_empty());
+        assert!(entry.missing(day("2023-03-01"), day("2023-04-01")).is_empty());
+    }
+
+    #[test]
+    fn only_recent_bars_and_earlier_history_are_refetched() {
+        let mut cache = PriceCache::default();
+        cache.store("yahoo", "AAPL", day("2024-01-02"), day("2024-01-10"), bars(&["2024-01-02", "2024-01-10"]), day("2024-01-10"));
+
+        let entry = cache.get("yahoo", "AAPL").unwrap();
+        assert_eq!(entry.settled, day("2024-01-05"));
+        assert_eq!(entry.missing(day("2023-12-01"), day("2024-01-12")), vec![
+            (day("2023-12-01"), day("2024-01-01")),
+            (day("2024-01-06"), day("2024-01-12"))
+        ]);
+
+        // refreshing the recent part a week later settles it
+        cache.store("yahoo", "AAPL", day("2024-01-06"), day("2024-01-17"), bars(&["2024-01-11"]), day("2024-01-17"));
+        let entry = cache.get("yahoo", "AAPL").unwrap();
+        assert_eq!(entry.settled, day("2024-01-12"));
+        assert_eq!(entry.closes.len(), 3);
+    }
+
+    #[test]
+    fn unknown_symbols_are_not_cached() {
+        let mut cache = PriceCache::default();
+        cache.store("stooq", "VUAA.MI", day("2024-01-02"), day("2024-01-10"), HashMap::new(), day("2024-01-10"));
+        assert!(cache.get("stooq", "VUAA.MI").is_none());
+    }
+
+    #[test]
+    fn splits_after_download_invalidate_and_prune_drops_unused() {
+        let mut cache = PriceCache::default();
+        cache.store("yahoo", "NVDA", day("2024-01-02"), day("2024-03-01"), bars(&["2024-01-02"]), day("2024-03-01"));
+        cache.store("yahoo", "AAPL", day("2024-01-02"), day("2024-03-01"), bars(&["2024-01-02"]), day("2024-03-01"));
+
+        assert!(!cache.invalidate_split("NVDA", day("2021-07-20")));
+        assert!(cache.invalidate_split("NVDA", day("2024-06-10")));
+        assert!(cache.get("yahoo", "NVDA").is_none());
+
+        assert_eq!(cache.prune(90, day("2024-05-01")), 0);
+        assert_eq!(cache.prune(90, day("2024-07-01")), 1);
+        assert!(cache.providers.is_empty());
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let mut cache = PriceCache::default();
+        cache.store("yahoo", "AAPL", day("2024-01-02"), day("2024-01-10"), bars(&["2024-01-02"]), day("2024-01-10"));
+
+        let text = serde_json::to_string(&cache).unwrap();
+        let back: PriceCache = serde_json::from_str(&text).unwrap();
+        assert_eq!(back.get("yahoo", "AAPL").unwrap().closes[&day("2024-01-02")], 1.0);
+    }
+}
@@ -0,0 +1,213 @@
+use std::collections::BTreeMap;
+use chrono::{Datelike, NaiveDate};
+use crate::stats::xirr;
+
+
+
+// the calendar periods performance is tabled by, as discussed month by month
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Granularity {
+    Month,
+    Quarter,
+    Year
+}
+
+impl Granularity {
+
+    pub const ALL: [Granularity; 3] = [Granularity::Month, Granularity::Quarter, Granularity::Year];
+
+    pub fn parse(text: &str) -> Option<Granularity> {
+        match text.trim().to_lowercase().as_str() {
+            "m" | "month" | "monthly" => Some(Granularity::Month),
+            "q" | "quarter" | "quarterly" => Some(Granularity::Quarter),
+            "y" | "year" | "yearly" => Some(Granularity::Year),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Granularity::Month => "month",
+            Granularity::Quarter => "quarter",
+            Granularity::Year => "year"
+        }
+    }
+
+    // the period a day falls in, e.g. 2024-03, 2024-Q1 or 2024
+    pub fn label(&self, date: NaiveDate) -> String {
+        match self {
+            Granularity::Month => format!("{}-{:02}", date.year(), date.month()),
+            Granularity::Quarter => format!("{}-Q{}", date.year(), (date.month() - 1) / 3 + 1),
+            Granularity::Year => date.year().to_string()
+        }
+    }
+}
+
+
+
+// what happened to the account on one day, in account currency
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Day {
+    pub value: f64,               // cash plus market value of positions at the close
+    pub contributions: f64,       // deposits less withdrawals, taken to arrive before the close
+    pub realised: f64,
+    pub dividends: f64,
+    pub fees: f64                 // negative, as T212 reports them
+}
+
+// one line of a period table. the first and last period can be cut short by the report's window, and then cover
+// only its days in them
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodRow {
+    pub label: String,
+    pub start: NaiveDate,
+    pub end: NaiveDate,
+    pub twr: Option<f64>,             // %, daily returns net of contributions, chained
+    pub mwrr: Option<f64>,            // %, over the period and not annualised
+    pub realised: f64,
+    pub dividends: f64,
+    pub fees: f64,
+    pub net_contributions: f64,
+    pub ending_value: f64
+}
+
+// the account day by day over a report's window, and its value the evening before the window opens (None when
+// the window starts with the first order)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Calendar {
+    pub opening: Option<(NaiveDate, f64)>,
+    pub days: BTreeMap<NaiveDate, Day>
+}
+
+impl Calendar {
+
+    // one row per period, oldest first. each period opens with the close of the one before
+    pub fn rows(&self, granularity: Granularity) -> Vec<PeriodRow> {
+        let mut rows = Vec::new();
+        let mut before = self.opening;
+        let mut days = self.days.iter().map(|(date, day)| (*date, *day)).peekable();
+
+        while let Some((first, _)) = days.peek() {
+            let label = granularity.label(*first);
+            let mut period: Vec<(NaiveDate, Day)> = Vec::new();
+            while let Some(day) = days.next_if(|(date, _)| granularity.label(*date) == label) {
+                period.push(day);
+            }
+            rows.push(period_row(label, &period, before));
+            before = period.last().map(|(date, day)| (*date, day.value));
+        }
+        rows
+    }
+
+    // every table in one CSV, e.g. for a spreadsheet. rates in %, everything else in account currency
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("granularity,period,start,end,twr,mwrr,realised,dividends,fees,net_contributions,ending_value\n");
+        let rate = |value: Option<f64>| value.map_or(String::new(), |v| format!("{:.4}", v));
+        for granularity in Granularity::ALL {
+            for row in self.rows(granularity) {
+                csv.push_str(&format!("{},{},{},{},{},{},{:.2},{:.2},{:.2},{:.2},{:.2}\n",
+                    granularity.name(), row.label, row.start, row.end, rate(row.twr), rate(row.mwrr),
+                    row.realised, row.dividends, row.fees, row.net_contributions, row.ending_value));
+            }
+        }
+        csv
+    }
+}
+
+
+
+// period must not be empty; before is the last close ahead of it, if the account existed then
+fn period_row(label: String, period: &[(NaiveDate, Day)], before: Option<(NaiveDate, f64)>) -> PeriodRow {
+
+    // a day that starts from nothing, e.g. the first deposit, has no return of its own and is left out of the chain
+    let mut growth = 1.0;
+    let mut linked = false;
+    let mut previous = before.map_or(0.0, |(_, value)| value);
+    for (_, day) in period {
+        if previous > 0.0 {
+            growth *= (day.value - day.contributions) / previous;
+            linked = true;
+        }
+        previous = day.value;
+    }
+
+    // the opening value paid in the evening before, contributions as they came, and the closing value taken out
+    let (end, last) = *period.last().unwrap();
+    let mut flows: Vec<(NaiveDate, f64)> = before.filter(|(_, value)| *value != 0.0).map(|(date, value)| (date, -value)).into_iter().collect();
+    flows.extend(period.iter().filter(|(_, day)| day.contributions != 0.0).map(|(date, day)| (*date, -day.contributions)));
+    flows.push((end, last.value));
+
+    PeriodRow {
+        label,
+        start: period[0].0,
+        end,
+        twr: Some((growth - 1.0) * 100.0).filter(|_| linked),
+        mwrr: xirr(&flows, 0.1).cumulative().map(|r| r * 100.0),
+        realised: period.iter().map(|(_, day)| day.realised).sum(),
+        dividends: period.iter().map(|(_, day)| day.dividends).sum(),
+        fees: period.iter().map(|(_, day)| day.fees).sum(),
+        net_contributions: period.iter().map(|(_, day)| day.contributions).sum(),
+        ending_value: last.value
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+
+    fn value(value: f64) -> Day {
+        Day { value, ..Day::default() }
+    }
+
+    #[test]
+    fn periods_chain_returns_net_of_deposits() {
+        let mut days = BTreeMap::new();
+        // 1000 paid in and grown 10% by the end of March, then 1100 more on the first of April and flat since
+        days.insert(day("2024-03-28"), Day { value: 1000.0, contributions: 1000.0, fees: -1.0, ..Day::default() });
+        days.insert(day("2024-03-29"), Day { value: 1050.0, realised: 20.0, ..Day::default() });
+        days.insert(day("2024-03-31"), value(1100.0));
+        days.insert(day("2024-04-01"), Day { value: 2200.0, contributions: 1100.0, dividends: 5.0, ..Day::default() });
+        days.insert(day("2024-04-30"), value(2200.0));
+        let calendar = Calendar { opening: None, days };
+
+        let months = calendar.rows(Granularity::Month);
+        assert_eq!(months.iter().map(|row| row.label.as_str()).collect::<Vec<_>>(), vec!["2024-03", "2024-04"]);
+        assert_eq!((months[0].start, months[0].end), (day("2024-03-28"), day("2024-03-31")));
+        assert!((months[0].twr.unwrap() - 10.0).abs() < 1e-9);
+        assert_eq!((months[0].realised, months[0].fees, months[0].net_contributions, months[0].ending_value), (20.0, -1.0, 1000.0, 1100.0));
+        // April opens at March's 1100, so the deposit on its first day isn't a return
+        assert!(months[1].twr.unwrap().abs() < 1e-9);
+        assert!(months[1].mwrr.unwrap().abs() < 1e-9);
+        assert_eq!((months[1].dividends, months[1].net_contributions), (5.0, 1100.0));
+
+        // the quarters split where the months do; the year has both, with the same 10% as a time-weighted return,
+        // while the money-weighted one is diluted by the money that came in after the growth
+        let quarters = calendar.rows(Granularity::Quarter);
+        assert_eq!(quarters.iter().map(|row| row.label.as_str()).collect::<Vec<_>>(), vec!["2024-Q1", "2024-Q2"]);
+        let year = &calendar.rows(Granularity::Year)[0];
+        assert_eq!((year.label.as_str(), year.net_contributions, year.ending_value), ("2024", 2100.0, 2200.0));
+        assert!((year.twr.unwrap() - 10.0).abs() < 1e-9);
+        assert!(year.mwrr.unwrap() > 0.0 && year.mwrr.unwrap() < 10.0, "mwrr was {:?}", year.mwrr);
+
+        assert!(calendar.to_csv().lines().any(|line| line == "month,2024-04,2024-04-01,2024-04-30,0.0000,0.0000,0.00,5.00,0.00,1100.00,2200.00"));
+    }
+
+    #[test]
+    fn a_window_opens_with_the_value_before_it() {
+        let mut days = BTreeMap::new();
+        days.insert(day("2024-07-01"), value(1050.0));
+        days.insert(day("2024-07-02"), Day { value: 1155.0, contributions: -50.0, ..Day::default() });
+        let calendar = Calendar { opening: Some((day("2024-06-30"), 1000.0)), days };
+
+        let row = &calendar.rows(Granularity::Quarter)[0];
+        assert_eq!((row.label.as_str(), row.start), ("2024-Q3", day("2024-07-01")));
+        // 5% then (1155 + 50) / 1050, with the 50 taken out
+        assert!((row.twr.unwrap() - (1.05 * 1205.0 / 1050.0 - 1.0) * 100.0).abs() < 1e-9);
+        assert!(row.mwrr.is_some());
+        assert_eq!(Granularity::parse("Q"), Some(Granularity::Quarter));
+        assert_eq!(Granularity::parse("week"), None);
+    }
+}
@@ -0,0 +1,83 @@
+use chrono::NaiveDate;
+use std::collections::{BTreeMap, HashMap};
+use rust_decimal::Decimal;
+use crate::money::{Currency, Money};
+use crate::t212::{Dividend, Order, Transaction};
+
+
+
+// signed amount a transaction adds to cash. outflows are forced negative as the sign of
+// withdrawals and fees isn't something to rely on, transfers and interest carry their own sign
+pub fn transaction_amount(transaction: &Transaction) -> Decimal {
+    match transaction.kind.as_str() {
+        "DEPOSIT" => transaction.amount.abs(),
+        "WITHDRAW" | "FEE" => -transaction.amount.abs(),
+        _ => transaction.amount
+    }
+}
+
+
+
+// money crossing the account boundary; interest and fees are part of the account's own return
+pub fn is_external(transaction: &Transaction) -> bool {
+    matches!(transaction.kind.as_str(), "DEPOSIT" | "WITHDRAW" | "TRANSFER")
+}
+
+
+
+// daily cash balance over time_range, from deposits, withdrawals, trade settlements, fees, dividends and interest.
+// anything dated before the first day (usually the first deposit) becomes the opening balance.
+// orders must be fx adjusted, as settlements are taken at fill price times quantity in account currency, each
+// rounded to the penny like the broker settles it. everything else is exact, so the balance doesn't drift
+pub fn build_ledger(
+    currency: Currency,
+    time_range: &Vec<NaiveDate>,
+    orders: &Vec<Order>,
+    dividends: &Vec<Dividend>,
+    transactions: &Vec<Transaction>) -> BTreeMap<NaiveDate, Money> {
+
+    let mut deltas: HashMap<NaiveDate, Money> = HashMap::new();
+
+    for order in orders {
+        if order.status != "FILLED" {
+            continue
+        }
+        let date = order.date;
+        let settlement = Money::new(order.filledQuantity * order.fillPrice, currency).round();
+        let fees = Money::total(currency, order.taxes.iter().map(|fee| Money::new(fee.quantity, currency)));    // fees arrive negative
+        *deltas.entry(date).or_insert(Money::zero(currency)) += fees - settlement;
+    }
+
+    for dividend in dividends {
+        *deltas.entry(dividend.date).or_insert(Money::zero(currency)) += Money::new(dividend.amount, currency);
+    }
+
+    for transaction in transactions {
+        *deltas.entry(transaction.date).or_insert(Money::zero(currency)) += Money::new(transaction_amount(transaction), currency);
+    }
+
+    let first_day = *time_range.first().unwrap();
+    let mut balance = Money::total(currency, deltas.iter().filter(|(date, _)| **date < first_day).map(|(_, v)| *v));
+    let mut ledger: BTreeMap<NaiveDate, Money> = BTreeMap::new();
+
+    for date in time_range {
+        if let Some(delta) = deltas.get(date) {
+            balance += *delta;
+        }
+        ledger.insert(*date, balance);
+    }
+    ledger
+}
+
+
+
+// deposits, withdrawals and transfers as investor cash flows, i.e. money paid in is negative, for account-level MWRR
+pub fn external_flows(currency: Currency, transactions: &Vec<Transaction>) -> BTreeMap<NaiveDate, Money> {
+
+    let mut flows: BTreeMap<NaiveDate, Money> = BTreeMap::new();
+
+    for transaction in transactions.iter().filter(|t| is_external(t)) {
+        *flows.entry(transaction.date).or_insert(Money::zero(currency)) -= Money::new(transaction_amount(transaction), currency);
+    }
+    flows
+}
@@ -0,0 +1,226 @@
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use serde::Deserialize;
+use serde_json::from_reader;
+use crate::error::{Error, Result};
+use crate::t212::Order;
+use crate::money::{Quantity, Ratio};
+use rust_decimal::Decimal;
+
+
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ActionKind {
+    Split,      // ratio new shares per old share, e.g. 4 or "4:1" for a 4:1 split, "1:10" for a 1:10 reverse split
+    Rename,     // ticker becomes new_ticker, history included
+    Merger,     // each share of ticker becomes ratio shares of new_ticker, cost basis carried over
+    Spinoff     // each share of ticker also brings ratio shares of new_ticker, taking cost_fraction of the cost basis
+}
+
+// one entry of corporate_actions.json, tickers in yahoo format, e.g.
+// {"date": "2022-06-09", "action": "rename", "ticker": "FB", "new_ticker": "META"}
+#[derive(Debug, Deserialize, Clone)]
+pub struct CorporateAction {
+    pub date: NaiveDate,
+    pub action: ActionKind,
+    pub ticker: String,
+
+    #[serde(default)]
+    pub new_ticker: Option<String>,
+
+    #[serde(default = "default_ratio")]
+    pub ratio: Ratio,
+
+    #[serde(default)]
+    pub cost_fraction: Decimal
+}
+
+fn default_ratio() -> Ratio {
+    Ratio::ONE
+}
+
+
+
+// user-supplied actions; a missing file just means there are none. dates (YYYY-MM-DD) and ratios are parsed
+// here, and a rename, merger or spin-off without a new_ticker is rejected rather than skipped later
+pub fn load_actions(path: &str) -> Result<Vec<CorporateAction>> {
+
+    let mut actions: Vec<CorporateAction> = match File::open(path) {
+        Ok(file) => from_reader(BufReader::new(file)).map_err(|e| Error::parse(path, e))?,
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(Error::io(path, e)),
+        Err(_) => return Ok(Vec::new())
+    };
+
+    for action in &actions {
+        if action.action != ActionKind::Split && action.new_ticker.as_deref().map_or(true, |t| t.trim().is_empty()) {
+            return Err(Error::parse(path, format!("{:?} of {} on {} needs a new_ticker", action.action, action.ticker, action.date)))
+        }
+    }
+    actions.sort_by_key(|a| a.date);
+    Ok(actions)
+}
+
+
+
+// adds splits detected at yahoo, unless the user file already has a split for that ticker and day
+pub fn add_detected_splits(actions: &mut Vec<CorporateAction>, detected: HashMap<String, Vec<(NaiveDate, Ratio)>>) {
+
+    for (ticker, splits) in detected {
+        for (date, ratio) in splits {
+            let known = actions.iter().any(|a| a.action == ActionKind::Split && a.ticker == ticker && a.date == date);
+            if !known {
+                actions.push(CorporateAction {
+                    date,
+                    action: ActionKind::Split,
+                    ticker: ticker.clone(),
+                    new_ticker: None,
+                    ratio,
+                    cost_fraction: Decimal::ZERO
+                });
+            }
+        }
+    }
+    actions.sort_by_key(|a| a.date);
+}
+
+
+
+// the ticker an instrument ends up with after all renames, which chain in date order, e.g. A -> B -> C
+pub fn renamed(actions: &Vec<CorporateAction>, ticker: &str) -> String {
+    actions.iter()
+        .filter(|a| a.action == ActionKind::Rename)
+        .fold(ticker.to_string(), |current, a| match &a.new_ticker {
+            Some(new_ticker) if a.ticker == current => new_ticker.clone(),
+            _ => current
+        })
+}
+
+
+
+// product of all split ratios of ticker after date, i.e. how many of today's shares one share on date is.
+// a split counts under any name of the instrument, so one recorded as META also restates orders placed as FB
+pub fn split_factor(actions: &Vec<CorporateAction>, ticker: &str, date: NaiveDate) -> Ratio {
+    let instrument = renamed(actions, ticker);
+    actions.iter()
+        .filter(|a| a.action == ActionKind::Split && a.date > date && renamed(actions, &a.ticker) == instrument)
+        .map(|a| a.ratio)
+        .product()
+}
+
+
+
+// splits and renames are applied to the whole history rather than on the day. yahoo's closes are split-adjusted
+// back in time, so restating earlier fills in post-split shares (quantity times ratio, price divided by it) is what
+// keeps pre-split days comparable; the value of every fill, and so cost basis and cash flows, stays the same
+pub fn restate_orders(orders: &mut Vec<Order>, actions: &Vec<CorporateAction>) {
+
+    for order in orders.iter_mut() {
+        let factor = split_factor(actions, &order.ticker, order.date);
+        if !factor.is_one() {
+            order.filledQuantity = order.filledQuantity.scale(factor);
+            order.fillPrice = factor.per_new_share(order.fillPrice);
+        }
+        order.ticker = renamed(actions, &order.ticker);
+    }
+}
+
+
+
+// mergers and spin-offs change what is held from their date on, so they are applied to portfolio_t (quantity and
+// cost basis by ticker) while orders are processed. splits and renames are left to restate_orders
+pub fn apply_action(
+    action: &CorporateAction,
+    portfolio_t: &mut HashMap<String, (Quantity, Decimal)>,
+    ticker_history: &mut HashMap<String, (NaiveDate, NaiveDate)>,
+    last_date: NaiveDate) {
+
+    let date = action.date;
+    let new_ticker = match (&action.action, &action.new_ticker) {
+        (ActionKind::Merger, Some(v)) | (ActionKind::Spinoff, Some(v)) => v.clone(),
+        _ => return
+    };
+
+    let (q_0, cost_0) = match portfolio_t.get(&action.ticker) {
+        Some(v) => *v,
+        None => return        // not held on the day, nothing to do
+    };
+
+    let (q_new, cost_new) = if action.action == ActionKind::Merger {
+        portfolio_t.remove(&action.ticker);
+        ticker_history.entry(action.ticker.clone()).and_modify(|e| e.1 = date);
+        (q_0.scale(action.ratio), cost_0)
+    } else {
+        let carried = cost_0 * action.cost_fraction;
+        portfolio_t.insert(action.ticker.clone(), (q_0, cost_0 - carried));
+        (q_0.scale(action.ratio), carried)
+    };
+
+    portfolio_t.entry(new_ticker.clone())
+        .and_modify(|(q, cost)| {
+            *cost += cost_new;
+            *q += q_new;
+        })
+        .or_insert((q_new, cost_new));
+
+    ticker_history.entry(new_ticker)
+        .and_modify(|e| e.1 = last_date)
+        .or_insert((date, last_date));
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{day, order};
+    use std::str::FromStr;
+
+    #[test]
+    fn splits_follow_renames() {
+        let actions: Vec<CorporateAction> = serde_json::from_str(r#"[
+            {"date": "2022-06-09", "action": "rename", "ticker": "FB", "new_ticker": "META"},
+            {"date": "2024-01-10", "action": "split", "ticker": "META", "ratio": "1:3"}
+        ]"#).unwrap();
+
+        // bought as FB, split as META, sold as META after the split
+        let mut orders = vec![order("FB", "2022-01-05", "3", "30"), order("META", "2024-02-01", "-1", "30")];
+        restate_orders(&mut orders, &actions);
+        assert_eq!(orders[0].ticker, "META");
+        assert_eq!(orders[0].fillPrice, Decimal::from(90));
+        assert!((orders[0].filledQuantity + orders[1].filledQuantity).is_zero());
+    }
+
+    #[test]
+    fn dates_and_targets_are_checked_on_loading() {
+        let path = std::env::temp_dir().join(format!("t212_actions_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        std::fs::write(path, r#"[{"date": "2023-10-13", "action": "merger", "ticker": "ATVI", "ratio": 0.95}]"#).unwrap();
+        assert!(load_actions(path).unwrap_err().to_string().contains("needs a new_ticker"));
+
+        std::fs::write(path, r#"[{"date": "13/10/2023", "action": "split", "ticker": "NVDA", "ratio": 10}]"#).unwrap();
+        assert!(matches!(load_actions(path), Err(Error::Parse { .. })));
+
+        std::fs::write(path, r#"[{"date": "2024-06-10", "action": "split", "ticker": "NVDA", "ratio": 10}]"#).unwrap();
+        assert_eq!(load_actions(path).unwrap()[0].date, day("2024-06-10"));
+        std::fs::remove_file(path).unwrap();
+        assert!(load_actions(path).unwrap().is_empty());
+    }
+
+    #[test]
+    fn spinoff_shares_out_the_cost_basis() {
+        let actions: Vec<CorporateAction> = serde_json::from_str(r#"[
+            {"date": "2023-01-04", "action": "spinoff", "ticker": "GE", "new_ticker": "GEHC", "ratio": "1:3", "cost_fraction": 0.14}
+        ]"#).unwrap();
+        let mut portfolio_t = HashMap::from([(String::from("GE"), (Quantity(Decimal::from(9)), Decimal::from(1000)))]);
+        let mut ticker_history = HashMap::from([(String::from("GE"), (day("2022-05-02"), day("2024-01-02")))]);
+
+        apply_action(&actions[0], &mut portfolio_t, &mut ticker_history, day("2024-01-02"));
+        assert_eq!(portfolio_t["GE"], (Quantity(Decimal::from(9)), Decimal::from(860)));
+        assert_eq!(portfolio_t["GEHC"], (Quantity(Decimal::from(3)), Decimal::from(140)));
+        assert_eq!(ticker_history["GEHC"], (day("2023-01-04"), day("2024-01-02")));
+    }
+}
@@ -0,0 +1,440 @@
+use chrono::{Duration, NaiveDate, Utc};
+use std::collections::{BTreeSet, HashMap};
+use std::str::FromStr;
+use crate::accounts::Environment;
+use crate::calendar::{Granularity, PeriodRow};
+use crate::corporate::{self, CorporateAction};
+use crate::error::{Error, Result};
+use crate::money::{Currency, Money, Quantity};
+use crate::portfolio::{aggregate_fills, average_price, build_book, build_report, fill_value_quantity, Book, History, Report};
+use crate::quotes::QuoteUnits;
+use crate::stats::{self, Xirr};
+use crate::t212::{Dividend, Order, Transaction};
+use crate::unpriced::Fallback;
+use crate::yahoo;
+use rust_decimal::Decimal;
+
+
+
+// one open position today. quantity and average price are in post-split shares, the average price and market
+// value in account currency; market value is None when the ticker has no price at all
+#[derive(Debug, Clone, PartialEq)]
+pub struct Holding {
+    pub ticker: String,
+    pub quantity: Quantity,
+    pub average_price: Decimal,
+    pub market_value: Option<f64>
+}
+
+// the headline figures of the /s view, all in %
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub current_return: f32,           // unrealised, dividends included
+    pub annual_return: Option<f32>,    // None over a single day, as is the yield
+    pub mean: f32,                     // daily, over trading days only
+    pub sd: f32,
+    pub sharpe: f32,
+    pub dividend_yield: Option<f64>    // annual, on cost
+}
+
+
+
+// the analysis of one account, or of several taken as one, e.g.
+//
+//     let portfolio = Portfolio::builder("ISA", "GBP")
+//         .orders(orders)
+//         .dividends(dividends)
+//         .fx(fx_history)
+//         .prices(closes)
+//         .build()?;
+//     for holding in portfolio.holdings() { ... }
+pub struct Portfolio {
+    report: Report
+}
+
+impl Portfolio {
+
+    pub fn builder(label: &str, currency: &str) -> PortfolioBuilder {
+        PortfolioBuilder {
+            label: label.to_string(),
+            environment: Environment::default(),
+            currency: currency.to_string(),
+            orders: Vec::new(),
+            dividends: Vec::new(),
+            transactions: Vec::new(),
+            actions: Vec::new(),
+            units: QuoteUnits::default(),
+            fx_history: HashMap::new(),
+            prices: HashMap::new(),
+            unpriced: HashMap::new(),
+            start_date: None,
+            end_date: None
+        }
+    }
+
+    // for a book that was changed after building, e.g. by reconcile::trust_broker. prices must already be weekend
+    // filled, with the filled days in filled (see stats::forward_fill_all)
+    pub fn from_book(
+        book: Book,
+        prices: &HashMap<String, HashMap<NaiveDate, f64>>,
+        filled: &HashMap<String, BTreeSet<NaiveDate>>,
+        unpriced: &HashMap<String, Fallback>) -> Portfolio {
+
+        Portfolio { report: build_report(book, prices, filled, unpriced) }
+    }
+
+    // everything at once, as the console views use it
+    pub fn report(&self) -> &Report {
+        &self.report
+    }
+
+    // open positions, largest market value first
+    pub fn holdings(&self) -> Vec<Holding> {
+        let mut holdings: Vec<Holding> = self.report.holdings.iter()
+            .map(|(ticker, (quantity, cost))| Holding {
+                ticker: ticker.clone(),
+                quantity: *quantity,
+                average_price: average_price(*quantity, *cost),
+                market_value: self.report.allocation.get(ticker).copied()
+            })
+            .collect();
+        holdings.sort_by(|a, b| b.market_value.unwrap_or(0.0).total_cmp(&a.market_value.unwrap_or(0.0)).then(a.ticker.cmp(&b.ticker)));
+        holdings
+    }
+
+    // unrealised return in % for every day
+    pub fn return_series(&self) -> &Vec<(NaiveDate, f32)> {
+        &self.report.return_history
+    }
+
+    // cash plus market value of positions for every day
+    pub fn account_value(&self) -> &Vec<(NaiveDate, f32)> {
+        &self.report.account_value
+    }
+
+    // cumulative realised profit or loss in account currency for every day
+    pub fn realised_pnl(&self) -> &Vec<(NaiveDate, f32)> {
+        &self.report.real_returns_abs
+    }
+
+    // money-weighted return of the positions from the first order up to each day, in %. days whose
+    // flows have no rate are left out, see mwrr_unsolved
+    pub fn mwrr(&self) -> &Vec<(NaiveDate, f32)> {
+        &self.report.mwrr_returns
+    }
+
+    // the same rates a year, in %. large over the first weeks, when a little return annualises to a lot
+    pub fn mwrr_annualised(&self) -> &Vec<(NaiveDate, f32)> {
+        &self.report.mwrr_annualised
+    }
+
+    pub fn mwrr_unsolved(&self) -> &Vec<NaiveDate> {
+        &self.report.mwrr_unsolved
+    }
+
+    // money-weighted return over deposits and withdrawals with cash included, annual and cumulative, and how the
+    // solver got there. no rate without transactions
+    pub fn account_mwrr(&self) -> Xirr {
+        self.report.account_mwrr
+    }
+
+    // TWR, MWRR, realised P&L, dividends, fees, net contributions and ending value for every calendar month,
+    // quarter or year of the report
+    pub fn periods(&self, granularity: Granularity) -> Vec<PeriodRow> {
+        self.report.calendar.rows(granularity)
+    }
+
+    // fees and taxes by name, negative as T212 reports them
+    pub fn fees(&self) -> &HashMap<String, Money> {
+        &self.report.fees_and_taxes
+    }
+
+    pub fn total_fees(&self) -> Money {
+        Money::total(self.report.currency, self.report.fees_and_taxes.values().copied())
+    }
+
+    // dividends received per ticker, T212 tickers
+    pub fn dividends(&self) -> &HashMap<String, Money> {
+        &self.report.dividend_library
+    }
+
+    pub fn summary(&self) -> Summary {
+        Summary {
+            current_return: self.report.current_return,
+            annual_return: self.report.annual_return,
+            mean: self.report.mean,
+            sd: self.report.sd,
+            sharpe: self.report.sharpe,
+            dividend_yield: self.report.dividend_yield
+        }
+    }
+}
+
+
+
+// orders, dividends and transactions are given oldest first with their date set, i.e. as t212::get_orders and
+// dividends::get_dividends return them but reversed. one call per account for a consolidated view, since order
+// ids are only unique within one account. orders are raw: T212 tickers, instrument currency, one record per fill.
+// prices are closes in account currency by yahoo ticker
+pub struct PortfolioBuilder {
+    label: String,
+    environment: Environment,
+    currency: String,
+    orders: Vec<Vec<Order>>,
+    dividends: Vec<Vec<Dividend>>,
+    transactions: Vec<Vec<Transaction>>,
+    actions: Vec<CorporateAction>,
+    units: QuoteUnits,
+    fx_history: HashMap<String, HashMap<NaiveDate, f64>>,
+    prices: HashMap<String, HashMap<NaiveDate, f64>>,
+    unpriced: HashMap<String, Fallback>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>
+}
+
+impl PortfolioBuilder {
+
+    pub fn environment(mut self, environment: Environment) -> PortfolioBuilder {
+        self.environment = environment;
+        self
+    }
+
+    pub fn orders(mut self, orders: Vec<Order>) -> PortfolioBuilder {
+        self.orders.push(orders);
+        self
+    }
+
+    pub fn dividends(mut self, dividends: Vec<Dividend>) -> PortfolioBuilder {
+        self.dividends.push(dividends);
+        self
+    }
+
+    // deposits, withdrawals and other cash movements, for the cash ledger
+    pub fn transactions(mut self, transactions: Vec<Transaction>) -> PortfolioBuilder {
+        self.transactions.push(transactions);
+        self
+    }
+
+    // splits, renames, mergers and spin-offs, yahoo tickers
+    pub fn actions(mut self, actions: Vec<CorporateAction>) -> PortfolioBuilder {
+        self.actions = actions;
+        self
+    }
+
+    // what fills are quoted in; without them every instrument is guessed from its exchange
+    pub fn quote_units(mut self, units: QuoteUnits) -> PortfolioBuilder {
+        self.units = units;
+        self
+    }
+
+    // by pair, account currency first, e.g. "GBPUSD", with a rate for every day orders fall on
+    pub fn fx(mut self, fx_history: HashMap<String, HashMap<NaiveDate, f64>>) -> PortfolioBuilder {
+        self.fx_history = fx_history;
+        self
+    }
+
+    pub fn prices(mut self, prices: HashMap<String, HashMap<NaiveDate, f64>>) -> PortfolioBuilder {
+        self.prices = prices;
+        self
+    }
+
+    // tickers whose prices are a fallback series rather than market data, see unpriced::Fallback
+    pub fn unpriced(mut self, unpriced: HashMap<String, Fallback>) -> PortfolioBuilder {
+        self.unpriced = unpriced;
+        self
+    }
+
+    // the first day returns, MWRR, realised P&L, dividends and fees are counted from, the first order if not given.
+    // what happened before only sets the positions and cash the period opens with
+    pub fn start_date(mut self, start_date: NaiveDate) -> PortfolioBuilder {
+        self.start_date = Some(start_date);
+        self
+    }
+
+    // the last day of the analysis, today if not given. orders, dividends and transactions after it are left out,
+    // so the report is as of that day
+    pub fn end_date(mut self, end_date: NaiveDate) -> PortfolioBuilder {
+        self.end_date = Some(end_date);
+        self
+    }
+
+    // each account's fills combined into orders, value orders given quantities
+    fn aggregated(&self) -> Vec<Vec<Order>> {
+        self.orders.iter()
+            .map(|orders| {
+                let mut orders = orders.clone();
+                for order in orders.iter_mut() {
+                    fill_value_quantity(order, &self.units.fill_unit(&order.ticker));
+                }
+                aggregate_fills(orders)
+            })
+            .collect()
+    }
+
+    // what the book is built from: one order per fill set, yahoo tickers, prices in account currency and
+    // corporate actions applied. fails on a ticker without a yahoo symbol or a fill without an fx rate,
+    // rather than leave the order out
+    pub fn prepared_orders(&self) -> Result<Vec<Order>> {
+        let mut orders = merge_by_date(self.aggregated(), |o: &Order| o.date);
+
+        for order in orders.iter_mut() {
+            // fill prices are in the unit of T212's instrument, e.g. pence
+            let unit = self.units.fill_unit(&order.ticker);
+            order.ticker = yahoo::convert_to_yahoo_ticker(order.ticker.clone())?;
+            unit.exact_to_account(&self.currency, order.date, &mut order.fillPrice, &self.fx_history)?;
+        }
+        corporate::restate_orders(&mut orders, &self.actions);
+        Ok(orders)
+    }
+
+    // orders kept in T212 tickers and instrument currency, splits applied, to compare against the broker's positions
+    pub fn instrument_orders(&self) -> Vec<Order> {
+        let mut orders = merge_by_date(self.aggregated(), |o: &Order| o.date);
+
+        for order in orders.iter_mut() {
+            let ticker = yahoo::convert_to_yahoo_ticker(order.ticker.clone()).unwrap_or_else(|_| order.ticker.clone());
+            let factor = corporate::split_factor(&self.actions, &ticker, order.date);
+            order.filledQuantity = order.filledQuantity.scale(factor);
+            order.fillPrice = factor.per_new_share(order.fillPrice);
+        }
+        orders
+    }
+
+    // the part of the analysis that needs no prices, e.g. to find out which tickers to download
+    pub fn book(&self) -> Result<Book> {
+        let currency = Currency::from_str(&self.currency).map_err(|e| Error::parse("account currency", e))?;
+        let end_date = self.end_date.unwrap_or(Utc::now().date_naive());
+        let mut orders = self.prepared_orders()?;
+        orders.retain(|o| o.date <= end_date);
+        let range = time_range(&orders, end_date)?;
+        let mut dividends = merge_by_date(self.dividends.clone(), |d: &Dividend| d.date);
+        dividends.retain(|d| d.date <= end_date);
+        let mut transactions = merge_by_date(self.transactions.clone(), |t: &Transaction| t.date);
+        transactions.retain(|t| t.date <= end_date);
+
+        let history = History { orders: &orders, dividends: &dividends, transactions: &transactions, actions: &self.actions };
+        let mut book = build_book(&self.label, self.environment, currency, history, range)?;
+        if let Some(start_date) = self.start_date {
+            if start_date > end_date {
+                return Err(Error::parse("period", format!("it starts on {}, after the report's last day {}", start_date, end_date)))
+            }
+            book.window_start = book.window_start.max(start_date);
+        }
+        Ok(book)
+    }
+
+    pub fn build(self) -> Result<Portfolio> {
+        let book = self.book()?;
+        let mut prices = self.prices;
+        let filled = stats::forward_fill_all(&mut prices, *book.time_range.last().unwrap());
+        Ok(Portfolio::from_book(book, &prices, &filled, &self.unpriced))
+    }
+}
+
+
+
+// every day from the first order to end_date
+pub fn time_range(orders: &Vec<Order>, end_date: NaiveDate) -> Result<Vec<NaiveDate>> {
+
+    let mut date = orders.first().ok_or(Error::parse("order history", "there are no orders to start from"))?.date;
+    let mut time_range = Vec::new();
+
+    while date <= end_date {
+        time_range.push(date);
+        date += Duration::days(1);
+    }
+    if time_range.is_empty() {
+        return Err(Error::parse("order history", format!("first order on {} is after {}", date, end_date)))
+    }
+    Ok(time_range)
+}
+
+
+
+// merges several date-ascending histories into one, keeping each history's own order for equal dates
+pub fn merge_by_date<T, K: Ord, F: Fn(&T) -> K>(histories: Vec<Vec<T>>, date_of: F) -> Vec<T> {
+    let mut merged: Vec<T> = histories.into_iter().flatten().collect();
+    merged.sort_by_key(|item| date_of(item));    // stable sort, so same-day orders stay in sequence
+    merged
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+    use crate::exchanges;
+    use crate::portfolio::final_holdings;
+    use crate::t212::{Instrument, Items};
+
+    fn orders() -> Vec<Order> {
+        let page: Items = serde_json::from_str(include_str!("../tests/fixtures/partial_fills.json")).unwrap();
+        let mut orders = page.items;
+        orders.reverse();
+        for order in orders.iter_mut() {
+            order.date = exchanges::trading_date(&order.dateModified, exchanges::time_zone(&order.ticker)).unwrap();
+        }
+        orders
+    }
+
+    fn usd_units() -> QuoteUnits {
+        let mut units = QuoteUnits::default();
+        let instruments: Vec<Instrument> = ["AAPL_US_EQ", "MSFT_US_EQ", "SAPd_EQ"].iter()
+            .map(|ticker| Instrument { ticker: ticker.to_string(), currencyCode: Some(String::from("USD")) })
+            .collect();
+        units.add_instruments(&instruments);
+        units
+    }
+
+    // one close a weekday from the 1st to the 8th of March 2024
+    fn closes(first: f64, step: f64) -> HashMap<NaiveDate, f64> {
+        (0..8).map(|i| day("2024-03-01") + Duration::days(i))
+            .filter(|d| !matches!(d.format("%a").to_string().as_str(), "Sat" | "Sun"))
+            .enumerate()
+            .map(|(i, d)| (d, first + step*i as f64))
+            .collect()
+    }
+
+    #[test]
+    fn builds_holdings_from_raw_fills() {
+        let prices = HashMap::from([
+            (String::from("AAPL"), closes(170.0, 1.0)),
+            (String::from("MSFT"), closes(389.5, 0.0)),
+            (String::from("SAP.DE"), closes(180.0, 0.0))
+        ]);
+        let portfolio = Portfolio::builder("ISA", "USD")
+            .orders(orders())
+            .quote_units(usd_units())
+            .prices(prices)
+            .end_date(day("2024-03-08"))
+            .build()
+            .unwrap();
+
+        let holdings = portfolio.holdings();
+        let aapl = holdings.iter().find(|h| h.ticker == "AAPL").unwrap();
+        assert_eq!(aapl.quantity, Quantity::from_f64(10.0));
+        assert_eq!(aapl.average_price, Decimal::from_str("170.2").unwrap());
+        assert_eq!(aapl.market_value, Some(10.0*175.0));      // last close, on the 8th
+        assert_eq!(holdings[0].ticker, "AAPL");                // largest first
+
+        assert_eq!(portfolio.return_series().first().unwrap().0, day("2024-03-01"));
+        assert_eq!(portfolio.return_series().last().unwrap().0, day("2024-03-08"));
+        assert!(portfolio.summary().current_return > 0.0);
+        assert_eq!(portfolio.total_fees().to_string(), "-1.27 USD");
+    }
+
+    #[test]
+    fn accounts_are_aggregated_before_they_are_merged() {
+        // the same fixture as two accounts: equal ids in different accounts are different orders
+        let builder = Portfolio::builder("all", "USD")
+            .orders(orders())
+            .orders(orders())
+            .quote_units(usd_units());
+
+        let orders = builder.prepared_orders().unwrap();
+        assert_eq!(orders.len(), 6);
+        assert!(orders.windows(2).all(|pair| pair[0].date <= pair[1].date));
+        assert_eq!(final_holdings(&builder.instrument_orders())["AAPL_US_EQ"].0, Quantity::from_f64(20.0));
+    }
+}
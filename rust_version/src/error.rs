@@ -0,0 +1,105 @@
+use std::fmt;
+use std::io;
+use chrono::NaiveDate;
+
+
+
+// everything that can go wrong between the broker, the price sources, the files next to the binary and the numbers.
+// each message says what to do about it, as the CLI prints them as they are
+#[derive(Debug)]
+pub enum Error {
+    Api { endpoint: String, message: String },          // the request didn't get through, or the server answered with an error
+    RateLimit { endpoint: String },                     // still 429 after waiting out every retry
+    Auth { endpoint: String },                          // 401 or 403: the API key is wrong, revoked or lacks a permission
+    Parse { what: String, message: String },            // a response, file or value that isn't what we expect
+    MissingPrice { symbol: String, message: String },   // every price source failed for a symbol
+    MissingFx { pair: String, date: NaiveDate },        // no rate on or before a day a conversion needs
+    Mapping { ticker: String, message: String },        // a T212 ticker without a yahoo symbol
+    Io { path: String, source: io::Error }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+
+    pub fn parse(what: impl Into<String>, message: impl fmt::Display) -> Error {
+        Error::Parse { what: what.into(), message: message.to_string() }
+    }
+
+    pub fn io(path: impl Into<String>, source: io::Error) -> Error {
+        Error::Io { path: path.into(), source }
+    }
+
+    // what T212 (or anyone else) answered with status, for endpoint
+    pub fn from_status(endpoint: &str, status: reqwest::StatusCode) -> Error {
+        match status.as_u16() {
+            401 | 403 => Error::Auth { endpoint: endpoint.to_string() },
+            429 => Error::RateLimit { endpoint: endpoint.to_string() },
+            _ => Error::Api { endpoint: endpoint.to_string(), message: status.to_string() }
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Api { endpoint, message } =>
+                write!(f, "{} failed: {}. check the connection and try again", endpoint, message),
+            Error::RateLimit { endpoint } =>
+                write!(f, "{} is still rate limited after retrying, wait a few minutes before running again", endpoint),
+            Error::Auth { endpoint } =>
+                write!(f, "{} rejected the API key, check the key file in accounts.json, its permissions and that it is for this environment (live or demo)", endpoint),
+            Error::Parse { what, message } =>
+                write!(f, "could not parse {}: {}", what, message),
+            Error::MissingPrice { symbol, message } =>
+                write!(f, "no prices for {} ({}), try again later or price it from another symbol with `t212 tickers add`", symbol, message),
+            Error::MissingFx { pair, date } =>
+                write!(f, "no {} rate on or before {}, add a {}=X override in custom_tickers.json or a prices/{}=X.csv", pair, date, pair, pair),
+            Error::Mapping { ticker, message } =>
+                write!(f, "can't map {} to a yahoo symbol ({}), its exchange isn't supported yet, please report the ticker", ticker, message),
+            Error::Io { path, source } =>
+                write!(f, "{}: {}", path, source)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+// anything reqwest reports happened before a status came back, or while reading the body
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Error {
+        let endpoint = e.url().map_or(String::from("request"), |url| format!("{}{}", url.host_str().unwrap_or(""), url.path()));
+        match e.status() {
+            Some(status) => Error::from_status(&endpoint, status),
+            None if e.is_decode() => Error::parse(format!("response from {}", endpoint), e),
+            None => Error::Api { endpoint, message: e.to_string() }
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::StatusCode;
+
+    #[test]
+    fn statuses_map_to_what_the_user_can_do() {
+        assert!(matches!(Error::from_status("/orders", StatusCode::UNAUTHORIZED), Error::Auth { .. }));
+        assert!(matches!(Error::from_status("/orders", StatusCode::FORBIDDEN), Error::Auth { .. }));
+        assert!(matches!(Error::from_status("/orders", StatusCode::TOO_MANY_REQUESTS), Error::RateLimit { .. }));
+        match Error::from_status("/orders", StatusCode::BAD_GATEWAY) {
+            Error::Api { message, .. } => assert_eq!(message, "502 Bad Gateway"),
+            other => panic!("expected an api error, got {:?}", other)
+        }
+        assert!(Error::from_status("/orders", StatusCode::UNAUTHORIZED).to_string().contains("accounts.json"));
+    }
+}
@@ -0,0 +1,114 @@
+use std::str::FromStr;
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
+use crate::error::{Error, Result};
+
+
+
+// the time zone of the exchange a T212 ticker trades on, e.g. VUSAl_EQ (London) or AAPL_US_EQ (New York).
+// UTC for anything unknown, which keeps the date T212 sends
+pub fn time_zone(t212_ticker: &str) -> Tz {
+
+    let before_eq = match t212_ticker.strip_suffix("_EQ") {
+        Some(v) => v,
+        None => return Tz::UTC
+    };
+
+    match before_eq.rsplit_once('_') {
+        Some((_, "US")) => Tz::America__New_York,
+        Some((_, "CA")) => Tz::America__Toronto,
+        Some((_, "PT")) => Tz::Europe__Lisbon,
+        Some((_, "AT")) => Tz::Europe__Vienna,
+        Some((_, "BE")) => Tz::Europe__Brussels,
+        Some(_) => Tz::UTC,
+        None => match before_eq.chars().last() {       // exchange codes, as in yahoo::convert_to_yahoo_ticker
+            Some('l') => Tz::Europe__London,
+            Some('d') => Tz::Europe__Berlin,
+            Some('a') => Tz::Europe__Amsterdam,
+            Some('e') => Tz::Europe__Madrid,
+            Some('p') => Tz::Europe__Paris,
+            Some('s') => Tz::Europe__Zurich,
+            Some('m') => Tz::Europe__Rome,
+            _ => Tz::UTC
+        }
+    }
+}
+
+
+
+// deposits, withdrawals and interest trade on no exchange; they are dated on the day in the UK, where T212 keeps
+// its accounts, like a late-evening deposit shows in the app
+pub const ACCOUNT_TIME_ZONE: Tz = Tz::Europe__London;
+
+
+
+// an RFC 3339 timestamp as the time at the exchange; None for a bare date, which has no time to convert
+pub fn exchange_time(timestamp: &str, tz: Tz) -> Option<DateTime<Tz>> {
+    DateTime::parse_from_rfc3339(timestamp).ok().map(|t| t.with_timezone(&tz))
+}
+
+
+
+// the day at the exchange a timestamp falls on, so a trade at 20:30 in New York stays on that day
+// rather than moving to the next UK (or UTC) one. bare dates are taken as they are
+pub fn trading_date(timestamp: &str, tz: Tz) -> Result<NaiveDate> {
+    match exchange_time(timestamp, tz) {
+        Some(time) => Ok(time.date_naive()),
+        None => NaiveDate::from_str(timestamp.get(..10).unwrap_or(timestamp)).map_err(|e| Error::parse(format!("timestamp {}", timestamp), e))
+    }
+}
+
+
+
+// e.g. "2024-03-05 20:31 EST", or just the date when the time isn't known
+pub fn format_time(time: &Option<DateTime<Tz>>, date: NaiveDate) -> String {
+    match time {
+        Some(t) => t.format("%Y-%m-%d %H:%M %Z").to_string(),
+        None => date.to_string()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+
+    #[test]
+    fn exchanges_from_t212_tickers() {
+        assert_eq!(time_zone("AAPL_US_EQ"), Tz::America__New_York);
+        assert_eq!(time_zone("BRK_B_US_EQ"), Tz::America__New_York);
+        assert_eq!(time_zone("VUSAl_EQ"), Tz::Europe__London);
+        assert_eq!(time_zone("SAPd_EQ"), Tz::Europe__Berlin);
+        assert_eq!(time_zone("SHOP_CA_EQ"), Tz::America__Toronto);
+        assert_eq!(time_zone("SOMETHING"), Tz::UTC);
+    }
+
+    #[test]
+    fn late_us_trades_stay_on_the_new_york_day() {
+        // 20:30 in New York is already the next day in UTC (and London)
+        let timestamp = "2024-03-06T01:30:00.000Z";
+        assert_eq!(trading_date(timestamp, time_zone("AAPL_US_EQ")).unwrap(), day("2024-03-05"));
+        assert_eq!(trading_date(timestamp, time_zone("VUSAl_EQ")).unwrap(), day("2024-03-06"));
+
+        let time = exchange_time(timestamp, Tz::America__New_York);
+        assert_eq!(format_time(&time, day("2024-03-05")), "2024-03-05 20:30 EST");
+    }
+
+    #[test]
+    fn cash_movements_on_the_uk_day() {
+        // 23:30 UTC in summer is already the next day in London, 23:30 in winter isn't
+        assert_eq!(trading_date("2024-07-01T23:30:00.000Z", ACCOUNT_TIME_ZONE).unwrap(), day("2024-07-02"));
+        assert_eq!(trading_date("2024-01-15T23:30:00.000Z", ACCOUNT_TIME_ZONE).unwrap(), day("2024-01-15"));
+        assert_eq!(trading_date("2024-01-16T00:30:00+02:00", ACCOUNT_TIME_ZONE).unwrap(), day("2024-01-15"));
+    }
+
+    #[test]
+    fn offsets_and_bare_dates() {
+        assert_eq!(trading_date("2024-07-01T23:10:00+02:00", Tz::Europe__Berlin).unwrap(), day("2024-07-01"));
+        assert_eq!(trading_date("2024-07-01", Tz::America__New_York).unwrap(), day("2024-07-01"));
+        assert!(exchange_time("2024-07-01", Tz::UTC).is_none());
+        assert!(trading_date("yesterday", Tz::UTC).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+// the portfolio engine behind the t212 command, for use from other tools. Portfolio::builder takes orders,
+// dividends, FX and prices and gives holdings, return series, realised P&L, MWRR and fees. prices::fx_rates and
+// prices::market_data download and convert the FX and prices it needs; the modules below are the pieces it is made
+// of, from the T212 and price downloads to the console plots
+pub mod t212;
+pub mod yahoo;
+pub mod stats;
+pub mod dividends;
+pub mod plotter;
+pub mod accounts;
+pub mod portfolio;
+pub mod reconcile;
+pub mod cash;
+pub mod transactions;
+pub mod corporate;
+pub mod prices;
+pub mod stooq;
+pub mod cache;
+pub mod resolver;
+pub mod overrides;
+pub mod unpriced;
+pub mod quotes;
+pub mod exchanges;
+pub mod engine;
+pub mod money;
+pub mod error;
+pub mod ratelimit;
+pub mod period;
+pub mod calendar;
+#[cfg(test)]
+mod test_util;
+
+pub use engine::{Holding, Portfolio, PortfolioBuilder, Summary};
+pub use money::{Currency, Money, Quantity, Ratio};
+pub use error::Error;
+pub use stats::{Convergence, Xirr};
+pub use period::Period;
+pub use calendar::{Granularity, PeriodRow};
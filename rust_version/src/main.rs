@@ -1,33 +1,58 @@
-mod t212;
-mod yahoo;
-mod stats;
-mod dividends;
-mod plotter;
 use rgb::RGB8;
 use chrono::{Datelike, Days, Duration, NaiveDate, Utc};
-use std::{collections::{hash_map::Entry, BTreeMap, HashMap}, default, error::Error, fs::File, process, str::FromStr};
-use std::collections::HashSet;
-use crate::{stats::{hashmap_to_btree, hashmap_to_sorted_vec, interpolate, mwrr}, t212::{Dividend, Order}};
-use std::io::{self, Write, BufReader};
+use std::{collections::HashMap, default, fs::{self, File}, process, str::FromStr};
+use ::t212::{accounts, corporate, dividends, engine, exchanges, overrides, prices, quotes, ratelimit, reconcile, resolver, stats, t212, transactions, yahoo};
+use ::t212::{accounts::Environment, portfolio::{final_holdings, merge_ticker_histories, Book, Report}, t212::{Dividend, Order, Transaction}};
+use ::t212::{Error, Granularity, Portfolio, PortfolioBuilder, Quantity, Ratio};
+use std::io::{self, IsTerminal, Write, BufReader};
 use std::process::Command;
-use std::fs::{OpenOptions, read_to_string};
-use plotter::*;
-use serde_json::{from_reader, to_writer};
-
+use ::t212::plotter::*;
+use futures::stream::{self, StreamExt};
+use rust_decimal::Decimal;
+
+
+// one runtime for the whole run; downloads that don't depend on each other run concurrently in it
+#[tokio::main]
+async fn main() {
+
+    // --trust-broker replaces today's computed holdings with the broker's open positions
+    let trust_broker = std::env::args().any(|arg| arg == "--trust-broker");
+    // scripted runs never wait for input: unknown tickers are resolved automatically or reported
+    let interactive = io::stdin().is_terminal() && !std::env::args().any(|arg| arg == "--non-interactive");
+    let mut resolver = resolver::Resolver::new(interactive);
+    // --max-retries=N: how often a failed T212 request is tried again before the run stops
+    if let Some(value) = std::env::args().find_map(|arg| arg.strip_prefix("--max-retries=").map(|v| v.to_string())) {
+        match value.parse() {
+            Ok(max_retries) => ratelimit::set_retry_policy(ratelimit::RetryPolicy { max_retries, ..ratelimit::RetryPolicy::DEFAULT }),
+            Err(_) => {
+                eprintln!("Error: --max-retries takes a whole number, not {}", value);
+                process::exit(1)
+            }
+        }
+    }
 
-fn main() {
+    // --as-of=YYYY-MM-DD reports the portfolio as it was at that day's close, leaving out everything after it.
+    // --from=ytd|1y|3y|YYYY-MM-DD re-bases returns, MWRR, realised P&L, dividends and fees to start that day
+    let today = Utc::now().date_naive();
+    let as_of = std::env::args().find_map(|arg| arg.strip_prefix("--as-of=").map(|v| v.to_string())).map(|value| {
+        match NaiveDate::from_str(&value) {
+            Ok(date) if date <= today => date,
+            Ok(_) => exit_with("--as-of", Error::parse("--as-of", format!("{} is in the future", value))),
+            Err(e) => exit_with("--as-of", Error::parse("--as-of", format!("{} ({}), use YYYY-MM-DD", value, e)))
+        }
+    });
+    let period = match std::env::args().find_map(|arg| arg.strip_prefix("--from=").map(|v| v.to_string())) {
+        Some(value) => ::t212::Period::parse(&value).unwrap_or_else(|e| exit_with("--from", e)),
+        None => ::t212::Period::All
+    };
 
     // READING JSON WITH CUSTOM TICKERS #########################
-    let path = "custom_tickers.json";
-    let file = OpenOptions::new()
-    .read(true)
-    .write(true)
-    .create(true)
-    .open(path).expect("json reader fail");
-
-    let mut custom_tickers: HashMap<String, String> = match from_reader(&file) {
+    let mut overrides = match overrides::TickerOverrides::load("custom_tickers.json") {
         Ok(v) => v,
-        Err(_) => HashMap::new()
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1)
+        }
     };
     // ########################################################
 
@@ -35,408 +60,551 @@ fn main() {
 
 
 
-    // GETTING ORDERS AND ACTIVE TIME RANGE ###################
-    let api_key: String = read_to_string("api_key.txt").expect("could not find api_key.txt").split_whitespace().collect();
-
-    let mut data = match t212::get_orders(&api_key) {
-        Ok(v) => {
-            if v.is_empty(){
-                
-                eprintln!("Error: invalid API key or new account with 0 orders");
-                process::exit(1)
-            } else {
-                println!("\nOrder import from Trading212: complete");
-                println!("fetched a total of {} orders \n ", v.len());
-                v
-            }
-        },
+    // READING PRICE SOURCES ##################################
+    let price_chain = match prices::PriceChain::from_config("price_sources.json") {
+        Ok(v) => v,
         Err(e) => {
-            println!("Order import from t212 failed with error code: {}", e);
+            eprintln!("Error: {}", e);
             process::exit(1)
         }
-};
-    // REVERSE IS IMPORTANT, as transactions arrive in inverse order
-    // after this reverse(), time is aligned with vector index (ascending)
-    data.reverse();
-    // println!("{:#?}", data.get(0..100));
-    // duplicates occur from T212 treating partially filled orders as fully filled
-    // so we just remove them. this introduces price incorrection but partial fills are rare at T212
-    remove_duplicates(&mut data);    
-    
-    // initialize the whole time period
-    let time_range = get_time_range(&data).expect("Failed to get time range: ");
-    
-    let start_date = *time_range.first().unwrap();
-    let end_date = *time_range.last().unwrap();
-    //#########################################################
+    };
+    resolver.yahoo_url = price_chain.yahoo_url.clone();
+
+    // `t212 cache [inspect | prune <days> | rebuild <symbol>]` only touches the price cache and
+    // `t212 tickers [...]` only the ticker overrides, neither needs an API key
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(|arg| arg.as_str()) {
+        Some("cache") => {
+            cache_command(&args[2..], &price_chain).await;
+            return
+        },
+        Some("tickers") => {
+            if let Err(e) = tickers_command(&args[2..], &mut overrides, &price_chain).await {
+                eprintln!("Error: {}", e);
+                process::exit(1)
+            }
+            return
+        },
+        _ => {}
+    }
+    // ########################################################
 
 
 
 
 
-    // GETTING FX RATES #######################################
-    let account_currency = String::from("GBP");
+    // READING ACCOUNTS #######################################
+    let accounts = match accounts::load_accounts("accounts.json") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1)
+        }
+    };
+    let account_currency = accounts[0].currency.clone();
+    // ########################################################
 
-    let fx_list: Vec<String> = vec![
-        String::from("GBPUSD"), 
-        String::from("GBPEUR"), 
-        String::from("GBPCAD")];
 
-    let mut fx_history: HashMap<String, HashMap<NaiveDate, f64>> = HashMap::new();
 
-    for fx in fx_list {
-        let temp_history: HashMap<NaiveDate, f64> = match yahoo::get_prices(format!("{}=X", fx).as_str(), start_date - Duration::days(2), end_date, &mut custom_tickers) {
-            Ok(res) => res,
-            Err(e) => panic!("FX import from yahoo failed: {e}")
-        };
-        fx_history.insert(fx, temp_history);
 
+
+    // GETTING INSTRUMENTS ####################################
+    // the currency every instrument is quoted in, once per environment. without it LSE lines are guessed to be in pence
+    let mut units = quotes::QuoteUnits::default();
+    let mut listed: Vec<Environment> = Vec::new();
+    for account in &accounts {
+        if listed.contains(&account.environment) {
+            continue
+        }
+        listed.push(account.environment);
+        match t212::get_instruments(&account.api_key, account.base_url()).await {
+            Ok(v) => units.add_instruments(&v),
+            Err(e) => println!("Could not fetch the instrument list ({}), quote currencies are guessed from exchanges: {}", account.environment.name(), e)
+        }
     }
-    // yahoo returns no prices for weekends, so I interpolate using Friday's fx rate
-    stats::interpolate_weekends(&mut fx_history);
-    //##########################################################
+    // ########################################################
 
 
 
-    
 
-    // INITIALIZING PORTFOLIO AND RETURN VARIABLES #############
-    let mut portfolio_history: Vec<(NaiveDate, HashMap<String, (f64, f64)>)> = time_range.clone()
-    .into_iter()
-    .map(|d| (d, HashMap::new()))    // create empty portfolio hashmap for every date
-    .collect();
 
-    // initialize where we store cash flows (only for use in mwrr calculations)
-    let mut cash_flows: HashMap<NaiveDate, f64> = HashMap::new();
+    // GETTING ORDERS AND ACTIVE TIME RANGE ###################
+    let mut account_orders: Vec<(String, Vec<Order>)> = Vec::new();
+
+    for account in &accounts {
+        let mut data = match t212::get_orders(&account.api_key, account.base_url()).await {
+            Ok(v) => {
+                if v.is_empty(){
+
+                    eprintln!("Error: account {} has no orders yet, remove it from accounts.json until it has", account.label);
+                    process::exit(1)
+                } else {
+                    println!("\nOrder import from Trading212 ({} [{}]): complete", account.label, account.environment.name());
+                    println!("fetched a total of {} orders \n ", v.len());
+                    v
+                }
+            },
+            Err(e) => exit_with(&format!("order import for {}", account.label), e)
+        };
+        // REVERSE IS IMPORTANT, as transactions arrive in inverse order
+        // after this reverse(), time is aligned with vector index (ascending).
+        // fills are combined into orders later, by the portfolio builder
+        data.reverse();
+        account_orders.push((account.label.clone(), data));
+    }
 
-    // initialize where we store dates for which certain tickers wiere present in portfolio
-    let mut ticker_history: HashMap<String, (NaiveDate, NaiveDate)> = HashMap::new();
+    // initialize the whole time period, starting from the earliest order of any account
+    let all_orders: Vec<Order> = engine::merge_by_date(account_orders.iter().map(|(_, v)| v.clone()).collect(), |o: &Order| o.date);
+    let time_range = engine::time_range(&all_orders, as_of.unwrap_or(today)).unwrap_or_else(|e| exit_with("time range", e));
 
-    // initialize portfolio "holder/folder" at time t
-    let mut portfolio_t: HashMap<String, (f64, f64)> = HashMap::new();
-    
-    // initialize where we store realized returns
-    let mut real_returns: HashMap<NaiveDate, (f64, f64)> = HashMap::new();
+    let (start_date, end_date) = match (time_range.first(), time_range.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => exit_with("time range", Error::parse("order history", "there are no days to report on"))
+    };
+    //#########################################################
 
-    // initialize stock prices
-    let mut complete_prices: HashMap<String, HashMap<NaiveDate, f64>> = HashMap::new();
 
-    // get dividends to be passed into return calculation
-    let mut dividend_history = dividends::get_dividends(&api_key).expect("could not fetch dividends");
-    dividend_history.reverse();
 
-    // initialize storage of each ticker's total dividends
-    let mut dividend_library: HashMap<String, f64> = HashMap::new();
 
-    // initialize storage of total fees
-    let mut fees_and_taxes: HashMap<String, f32> = HashMap::new();
-    // #########################################################
 
+    // LISTINGS ###############################################
+    // yahoo's chart of every ticker ever traded, for the splits it knows about and the currency the listing is quoted in.
+    // looked up under each ticker's override, and until yahoo says otherwise a listing is quoted like T212's instrument
+    let mut traded: HashMap<String, NaiveDate> = HashMap::new();
+    for (label, data) in &account_orders {
+        for order in data {
+            let ticker = yahoo::convert_to_yahoo_ticker(order.ticker.clone()).unwrap_or_else(|e| exit_with(label, e));
+            let date = order.date;
+            units.set_listing(&ticker, units.fill_unit(&order.ticker));
+            traded.entry(ticker).and_modify(|d| *d = (*d).min(date)).or_insert(date);
+        }
+    }
+    let mut detected: HashMap<String, Vec<(NaiveDate, Ratio)>> = HashMap::new();
+    let total = traded.len();
+    let mut done = 0;
+    let lookups: Vec<(String, String, NaiveDate)> = traded.into_iter().map(|(ticker, first_date)| (overrides.target(&ticker), ticker, first_date)).collect();
+    let yahoo_url = price_chain.yahoo_url.as_str();
+    let mut lookups = stream::iter(lookups)
+        .map(|(symbol, ticker, first_date)| async move {
+            let listing = yahoo::get_listing(yahoo_url, &symbol, first_date, end_date).await;
+            (symbol, ticker, listing)
+        })
+        .buffer_unordered(8);
+
+    print_progress("listings", done, total);
+    while let Some((symbol, ticker, listing)) = lookups.next().await {
+        done += 1;
+        print_progress("listings", done, total);
+        if let Ok(listing) = listing {
+            if let Some(code) = listing.currency.filter(|code| !code.is_empty()) {
+                units.set_listing(&symbol, quotes::QuoteUnit::from_code(&code));
+            }
+            if !listing.splits.is_empty() {
+                detected.insert(ticker, listing.splits);
+            }
+        }
+    }
+    for (ticker, splits) in &detected {
+        for (date, ratio) in splits {
+            println!("    split detected: {} {} ({})", ticker, date, ratio);
+        }
+    }
+    // ########################################################
 
 
 
 
-    // PARSING, FILTERING AND FORMATTING ORDERS ################
-    for order in &mut data {
 
-        let matcher_date = NaiveDate::from_str(&order.dateModified).expect("couldn't parse dateModified: invalid date format");
+    // GETTING FX RATES #######################################
+    // every quote currency we might meet, including those named by ticker overrides, except the one we report in
+    let fx_history = prices::fx_rates(&account_currency, &units, start_date, end_date, &mut overrides, &price_chain, &mut resolver).await
+        .unwrap_or_else(|e| exit_with("FX import", e));
+    //##########################################################
 
-        // zero filledQuantity means it was a "value" order e.g. "buy £100 of AAPL" instead of "buy 0.5 AAPL at £200"
-        // so we need to translate value into quantities. "l_EQ" means a transaction on LSE so it is quoted in pennies
-        // and we multiply by 100
 
-        if order.filledQuantity == 0.0 {
-            if order.ticker.contains("l_EQ"){
-            order.filledQuantity = order.filledValue / (order.fillPrice * 100.0)
-            } else {
-                order.filledQuantity = order.filledValue / order.fillPrice
-            }
-        } else {
-            // pass
-        };
 
-        // changing tickers from T212's format to Yahoo's format
-        order.ticker = yahoo::convert_to_yahoo_ticker(order.ticker.clone());
 
-        // multiplying fill prices by respective fx rate
-        stats::fx_adjust(&order.ticker, matcher_date, &mut order.fillPrice, &fx_history);
 
-        // filtering out cancelled or rejected orders
-        if order.status == String::from("FILLED") {
-            process_order(&order, &mut portfolio_t, &mut ticker_history, &mut real_returns, &mut cash_flows, *time_range.last().unwrap());
-        } else {};
+    // CORPORATE ACTIONS: user file plus splits yahoo knows about for every ticker ever traded
+    let mut actions = match corporate::load_actions("corporate_actions.json") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1)
+        }
+    };
 
-        // set portoflio history's element to a correct pair of {Date: portfolio_t}
-        let index = time_range.iter().position(|&r| r == matcher_date).expect("time range has no such date");
-        portfolio_history[index] = (matcher_date, portfolio_t.clone());
+    corporate::add_detected_splits(&mut actions, detected);
 
-        // adding taxes
-        for fee in &order.taxes {
-            fees_and_taxes.entry(fee.name.clone()).and_modify(|total| *total += fee.quantity).or_insert(fee.quantity);
-        };
-    };
+    // cached closes from before a split are in pre-split terms, those tickers are downloaded again
+    if let Some(mut cache) = price_chain.cache() {
+        for action in actions.iter().filter(|a| a.action == corporate::ActionKind::Split) {
+            if cache.invalidate_split(&overrides.target(&action.ticker), action.date) {
+                println!("    cached prices of {} predate its split on {}, refetching", action.ticker, action.date);
+            }
+        }
+    }
     // #########################################################
 
-    
 
 
 
-    // PARSING DIVIDENDS #######################################
-    let mut blarg: BTreeMap<NaiveDate, f64> = BTreeMap::new();
-    let mut total_dividends: f64 = 0.0;
-    let mut cum_dividends: HashMap<NaiveDate, f32> = HashMap::new();
 
-    for dividend in dividend_history{
-        let date = match NaiveDate::from_str(&dividend.paidOn) {
+    // BUILDING BOOKS PER ACCOUNT (AND CONSOLIDATED) ############
+    let mut account_dividends: Vec<Vec<Dividend>> = Vec::new();
+    for account in &accounts {
+        // get dividends to be passed into return calculation
+        let mut dividend_history = match dividends::get_dividends(&account.api_key, account.base_url()).await {
             Ok(v) => v,
-            Err(_) => continue,
+            Err(e) => exit_with(&format!("dividend import for {}", account.label), e)
         };
-        let amount = dividend.amount;
-        cash_flows.entry(date).and_modify(|cf| *cf += amount).or_insert(amount);
-        dividend_library.entry(dividend.ticker).and_modify(|cf| *cf += amount).or_insert(amount);
-        blarg.entry(date).and_modify(|cf| *cf += amount).or_insert(amount);
-        total_dividends += amount;
-        cum_dividends.entry(date).insert_entry(total_dividends as f32);
+        dividend_history.reverse();
+        account_dividends.push(dividend_history);
     }
-    let dividend_history: BTreeMap<NaiveDate, f64> = blarg;
-    let mut cum_dividends = hashmap_to_sorted_vec(cum_dividends);
-    interpolate(&mut cum_dividends);
-
-    // println!("{:#?}", dividend_library);
-    // #########################################################
 
+    // deposits, withdrawals and other cash movements, for the cash ledger. without them cash, account value and
+    // account MWRR would all be wrong, so like dividends they are not optional
+    let mut account_transactions: Vec<Vec<Transaction>> = Vec::new();
+    for account in &accounts {
+        let mut transactions = match transactions::get_transactions(&account.api_key, account.base_url()).await {
+            Ok(v) => v,
+            Err(e) => exit_with(&format!("transaction import for {}", account.label), e)
+        };
+        transactions.reverse();
+        account_transactions.push(transactions);
+    }
 
+    // the portfolio builder turns fills into orders in account currency (yahoo tickers, corporate actions applied)
+    let builder = |label: &str, environment: Environment| Portfolio::builder(label, &account_currency)
+        .environment(environment)
+        .actions(actions.clone())
+        .quote_units(units.clone())
+        .fx(fx_history.clone())
+        .end_date(end_date);
+    let builder = |label: &str, environment: Environment| match period.start(end_date) {
+        Some(start) => builder(label, environment).start_date(start),
+        None => builder(label, environment)
+    };
 
+    let mut builders: Vec<PortfolioBuilder> = Vec::new();
+    for (i, account) in accounts.iter().enumerate() {
+        builders.push(builder(&account.label, account.environment)
+            .orders(account_orders[i].1.clone())
+            .dividends(account_dividends[i].clone())
+            .transactions(account_transactions[i].clone()));
+    }
 
+    let mut books: Vec<Book> = builders.iter().zip(&accounts).map(|(b, account)| b.book().unwrap_or_else(|e| exit_with(&account.label, e))).collect();
 
-    // GETTING STOCK PRICES FROM YAHOO #########################
-    println!("\n     ticker               lifetime:");
-    
-    for (ticker, (date1, date2)) in ticker_history.into_iter()  {   // conversion is fine since order does not matter for price lookup
-        
-        println!("    {:?},from {:?} to {:?}", ticker, date1, date2);
-        let mut single_ticker_history = match yahoo::get_prices(&ticker, date1, date2, &mut custom_tickers) {
-            Ok(res) => res,
-            Err(e) => panic!("Import from yahoo failed with error code: {}", e)
+    // compare what the order history says we hold (and has in cash) with what the broker says. the broker only
+    // knows today's positions, so a report as of an earlier day has nothing to compare with
+    if end_date < today {
+        println!("\nReport as of {}, skipping reconciliation with today's positions{}", end_date, if trust_broker {" and --trust-broker"} else {""});
+    }
+    for (i, account) in accounts.iter().enumerate().filter(|_| end_date == today) {
+        books[i].broker_cash = match t212::get_cash(&account.api_key, account.base_url()).await {
+            Ok(v) => Some(v),
+            Err(e) => {
+                println!("Could not fetch cash for {}, skipping the cash check: {}", account.label, e);
+                None
+            }
         };
 
-        // multiplying yahoo prices by respective fx rate
-        for (date, price) in single_ticker_history.iter_mut() {  // arbitrary order of iteration, but lookup in fx is still via keys so no problem
-            stats::fx_adjust(&ticker, *date, price, &fx_history);
+        let positions = match t212::get_positions(&account.api_key, account.base_url()).await {
+            Ok(v) => v,
+            Err(e) => {
+                println!("Could not fetch open positions for {}, skipping reconciliation: {}", account.label, e);
+                continue
+            }
+        };
+        // in T212 tickers and instrument currency, so the figures are comparable to the broker's
+        let raw_holdings = final_holdings(&builders[i].instrument_orders());
+        let mismatches = reconcile::reconcile(&raw_holdings, &positions);
+        reconcile::print_reconciliation(&format!("{} [{}]", account.label, account.environment.name()), &mismatches);
+        books[i].reconciliation = Some(mismatches);
+
+        if trust_broker {
+            let holdings = books[i].holdings.clone();
+            if let Err(e) = reconcile::trust_broker(&mut books[i], &positions, &raw_holdings, &holdings, &account_currency, &fx_history, &units) {
+                exit_with(&format!("--trust-broker for {}", account.label), e)
+            }
         }
-        
-        complete_prices.insert(ticker, single_ticker_history); 
-    };
-    // fill in missing weekend prices using Friday prices
-    stats::interpolate_weekends(&mut complete_prices);
-    //##########################################################
+    }
+    let current_holdings: Vec<HashMap<String, (Quantity, Decimal)>> = books.iter().map(|book| book.holdings.clone()).collect();
+    // every account's orders as the books have them, in account currency, for last-trade fallbacks
+    let traded_orders: Vec<Order> = books.iter().flat_map(|book| book.orders.clone()).collect();
+
+    // the consolidated view treats all accounts as one portfolio, so positions in the same ticker are merged.
+    // paper and live money are never merged, so mixed setups get one consolidated view per environment
+    let environments: Vec<Environment> = [Environment::Live, Environment::Demo]
+        .into_iter()
+        .filter(|env| accounts.iter().any(|a| a.environment == *env))
+        .collect();
 
+    for env in &environments {
+        let members: Vec<usize> = (0..accounts.len()).filter(|i| accounts[*i].environment == *env).collect();
+        if members.len() < 2 {
+            continue
+        }
+        let label = if environments.len() == 1 {String::from("all")} else {format!("all-{}", env.name())};
+        let mut consolidated = builder(&label, *env);
+        for i in &members {
+            consolidated = consolidated
+                .orders(account_orders[*i].1.clone())
+                .dividends(account_dividends[*i].clone())
+                .transactions(account_transactions[*i].clone());
+        }
+        let mut book = consolidated.book().unwrap_or_else(|e| exit_with(&label, e));
+        if trust_broker {
+            reconcile::trust_members(&mut book, &members.iter().map(|i| current_holdings[*i].clone()).collect());
+        }
+        books.push(book);
+    }
+    // #########################################################
 
 
 
 
-    // UNREALISED RETURNS ######################################
-    // portfolio_history is "sparse", so days where it wasn't changed are empty
-    // calculate_returns will just infer that empty day portfolio is same as last modified day's one
-    let (return_history, cb_mv_history) = match stats::calc_unreal_returns(&portfolio_history, complete_prices, dividend_history) {
-        Some((v, b)) => (v,b),
-        None => panic!("Calculating returns failed, check dividends arrived")
-    };
 
-    // shadowing
-    let return_history: Vec<(NaiveDate, f32)> = stats::hashmap_to_sorted_vec(return_history)
-    .into_iter()
-    .map(|(date, val)| (date, val as f32))  // convert to f32 for plotters module
-    .collect();
-    // irrelevant atm: benchmark and absolute cb and mv for beta and other stats to add in the future
-    // let cb_mv_history: Vec<(NaiveDate, (f64, f64))> = stats::hashmap_to_sorted_vec(cb_mv_history);
-    // let (_, _cb_mv_history): (Vec<_>, Vec<(f64, f64)>) = cb_mv_history.into_iter().unzip();
-    // let snp_prices: HashMap<NaiveDate, f64> = yahoo::get_prices("^GSPC", start_date, end_date).expect("couldnt get snp");
-    // let mut snp_prices = stats::hashmap_to_sorted_vec(snp_prices);
-    // stats::interpolate(&mut snp_prices);
-    // let _snp_returns = stats::calculate_benchmark_returns(snp_prices);
+    // GETTING STOCK PRICES ####################################
+    let ticker_history = merge_ticker_histories(&books);
+    println!("\n     ticker               lifetime:");
+    
+    for (ticker, (date1, date2)) in ticker_history.iter() {
+        println!("    {:?},from {:?} to {:?}", ticker, date1, date2);
+    }
+    // closes in account currency, every account's orders standing in for tickers without any
+    let market = prices::market_data(&ticker_history, &traded_orders, &account_currency, &units, &fx_history, end_date, &mut overrides, &price_chain, &mut resolver).await
+        .unwrap_or_else(|e| exit_with("prices", e));
+    let (complete_prices, filled, unpriced) = (market.prices, market.filled, market.unpriced);
+    price_chain.save_cache();
+
+    // not fatal any more, they are valued by their fallbacks and flagged in every report
+    if !resolver.unresolved().is_empty() {
+        resolver.print_unresolved();
+    }
     //##########################################################
 
 
 
 
 
-    // REALISED RETURNS #######################################
-    let mut real_returns: Vec<(NaiveDate, (f64, f64))> = stats::hashmap_to_sorted_vec(real_returns)
-    .into_iter()
-    .scan((0.0, 0.0), |state, (date, (a, b))| {  // like a fold, or cumsum over the (market val, cost_basis) tuple
-        state.0 += a;
-        state.1 += b;
-        Some((date, *state))
-    })
-    .collect();
-
-    let temp = match real_returns.last() {
-        Some(v) => v,
-        None => &(end_date, (0.0, 0.0))
-    };
-
-    if temp.0 != end_date {                                            // stretch returns to today
-        real_returns.push((end_date, temp.1))
-    };
-    real_returns.insert(0, (start_date, (0.0001, 0.0001)));            // stretch returns to root day
-
-    stats::interpolate(&mut real_returns);                             // stretch to correspond to # of days
-    let real_returns_abs: Vec<(NaiveDate, f32)> = real_returns.clone().into_iter().map(|(date, (cb, mv))|(date, ((mv - cb) as f32))).collect();
-
-    // switches tuple (market val, cost basis) into single (real_return)
-    let real_returns_rel: Vec<(NaiveDate, f32)> = real_returns.clone().into_iter().map(|(date, (cb, mv))|(date, ((mv/cb - 1.0)*100.0) as f32)).collect();
-    let _just_real_returns: Vec<f32> = stats::strip_dates(real_returns_rel.clone());
+    // RETURNS PER ACCOUNT ####################################
+    let portfolios: Vec<Portfolio> = books.into_iter().map(|book| Portfolio::from_book(book, &complete_prices, &filled, &unpriced)).collect();
+    // the consolidated report is shown by default when there is one
+    let mut selected: usize = portfolios.len() - 1;
+    let mut total_return_prices: Option<HashMap<String, HashMap<NaiveDate, f64>>> = None;
     // ########################################################
 
 
 
 
 
-    // MONEY-WEIGHTED RETURNS #################################
-
-    // let file = File::create("test.json").expect("could not create test file");
-    // serde_json::to_writer(&file, &cash_flows).unwrap();
-
-    // let file = File::create("test2.json").expect("could not create test file");
-    // serde_json::to_writer(&file, &cb_mv_history).unwrap();
-
-    let mut mwrr_returns = Vec::<(NaiveDate, f32)>::new();
-    let cb_mv_history = hashmap_to_btree(cb_mv_history);
-    let cash_flows = hashmap_to_btree(cash_flows);
-    let mut default_mwrr: f64 = 0.0;    // value to fallback to if mwrr algorithm doesn't converge.
-
-    for (date, (_, mv)) in cb_mv_history.iter() {
-
-        // this range + map allows to clone only what is needed for this iteration, i.e. cash_flows[:date]
-        // not including cash flows from date itself. otherwise stock sells get double counted as cf and as mv
-        let mut cash_flows_plus_mv: Vec<(NaiveDate, f64)> = cash_flows.range(..=date) //+ Days::new(1)
-        .map(|(k, v)| (k.clone(), *v))
-        .collect();
-
-        // add today's market value as a cash inflow
-        if let Some((_, value)) = cash_flows_plus_mv.iter_mut().next_back() {
-        *value += mv
-        };
-
-        let irr = mwrr(&cash_flows_plus_mv, 0.5).unwrap_or(default_mwrr) * 100.0;
-        mwrr_returns.push((*date, irr as f32));
-        default_mwrr = irr/100.0;
-        // let portfolio_history: HashMap<NaiveDate, HashMap<String, (f64, f64)>> = portfolio_history.clone().into_iter().collect();
-        // if irr > 20.0 || (date.year_ce() == (true, 2023) && date.month0() == 9){
-        //     println!("date: {:?}, r: {:?}, mv: {:?}", date, irr, mv);
-        //     println!("{:?}", portfolio_history.get(date).unwrap())
-        // }
-    }
-    // ########################################################
-
-
-    
-
-    
     // PRINTING AND PLOTTING TO CONSOLE #######################
-    let naivetime_held = end_date - start_date;
-    let days_held: f32 = naivetime_held.num_days() as f32;
-    let years_held: f32 = (&days_held)/365.0;
-    let months_held: i32 = ((&years_held*12.0) as i32) % 12;                                                                              // vvv this is incorrect
-    println!("\n \n Found portfolio of {:.} years, {:.} months, and {:.} days.\n", years_held.floor(), months_held, days_held as i32 % 365 - 30*months_held);
-    
     // switch to UTF-8 support by default
     if cfg!(target_os = "windows") {
         let _ = Command::new("chcp").arg("65001").status();
     }
-    
-    println!("\n  Unrealized return, %");
-    display_to_console(&return_history, start_date, end_date, 70, 10.0, RGB8::new(254, 255, 110), String::from_str("%").unwrap());
-    
-    let just_returns: Vec<f32> = stats::strip_dates(return_history);
-    let current_return = &just_returns.last().unwrap();
-    let annual_return = ((*current_return/100.0 + 1.0).powf(1.0/(&years_held)) - 1.0) * 100.0;
-    let daily_returns: Vec<f32> = stats::get_daily_returns(just_returns.clone());
-    let (mean, sd, sharpe) = stats::mean_sd_sharpe(&daily_returns);
-    let dividend_yield: f64 = total_dividends / ((cb_mv_history.values().map(|(cb, _)| *cb as f64).sum::<f64>()) / (days_held as f64)) / (years_held as f64) * 100.0;
+
+    print_header(portfolios[selected].report());
+    print_unpriced(portfolios[selected].report());
     printallcommands();
     
     loop {
         let mut input = String::new();
-        io::stdin().read_line(&mut input).unwrap();
-        let command = input.trim().trim();
+        // end of input, e.g. a scripted run, quits like /q
+        match io::stdin().read_line(&mut input) {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(e) => exit_with("command", Error::io("stdin", e))
+        }
+        let command = input.trim();
+        let portfolio = &portfolios[selected];
+        let report = portfolio.report();
         
         match command {
             "/s" => {
                 clear_last_n_lines(5);
+                let summary = portfolio.summary();
                 println!("   _________________________________________");
+                println!("  | {0: <21} | {1: <15} | ", "account", report.title());
                 println!("  |                       |                 |");
-                println!("  | {0: <21} | {1: <15.4} | ", "unrealised PnL(%)", current_return);
+                println!("  | {0: <21} | {1: <15.4} | ", "unrealised PnL(%)", summary.current_return);
                 println!("  |                       |                 |");
-                println!("  | {0: <21} | {1: <15.4} | ", "APR(%)", annual_return);
+                println!("  | {0: <21} | {1: <15} | ", "APR(%)", summary.annual_return.map_or(String::from("n/a"), |v| format!("{:.4}", v)));
                 println!("  |                       |                 |");
-                println!("  | {0: <21} | {1: <15.4} | ", "std. deviation", sd);
+                println!("  | {0: <21} | {1: <15.4} | ", "std. deviation", summary.sd);
                 println!("  |                       |                 |");
-                println!("  | {0: <21} | {1: <15.4} | ", "Sharpe ratio", sharpe);
+                println!("  | {0: <21} | {1: <15.4} | ", "Sharpe ratio", summary.sharpe);
                 println!("  |                       |                 |");
-                println!("  | {0: <21} | {1: <15.4} | ", "daily avg. return(%)", mean);
+                println!("  | {0: <21} | {1: <15.4} | ", "daily avg. return(%)", summary.mean);
                 println!("  |                       |                 |");
                 println!("   ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾ \n \n");          
             },
             "/r" => {clear_last_n_lines(6);
-                println!("\n  Absolute realized return, {}", account_currency);
-                display_to_console(&real_returns_abs, start_date, end_date, 40, 10.0, RGB8::new(255, 51, 255), account_currency.clone());
+                println!("\n  Absolute realized return ({}), {}", report.title(), account_currency);
+                display_to_console(portfolio.realised_pnl(), report.start_date, report.end_date, 40, 10.0, RGB8::new(255, 51, 255), account_currency.clone());
             },
                 
                 "/m" =>     {clear_last_n_lines(6);
-                println!("\n  Money-Weighted Rate of Return (MWRR), %");
-                display_to_console(&mwrr_returns, 
-                *cb_mv_history.first_key_value().unwrap().0,
-                *cb_mv_history.last_key_value().unwrap().0,
-                70, 10.0, RGB8::new(22, 253, 254), String::from_str("%").unwrap());  
+                match (portfolio.mwrr().last(), portfolio.mwrr_annualised().last()) {
+                    (Some((date, cumulative)), Some((_, annual))) => {
+                        println!("\n  Money-Weighted Rate of Return (MWRR) ({}), cumulative %", report.title());
+                        display_to_console(portfolio.mwrr(),
+                        *report.cb_mv_history.first_key_value().unwrap().0,
+                        *report.cb_mv_history.last_key_value().unwrap().0,
+                        70, 10.0, RGB8::new(22, 253, 254), String::from_str("%").unwrap());
+                        println!("  {0: <28} {1: >13.2}%", format!("cumulative to {}", date), cumulative);
+                        println!("  {0: <28} {1: >13.2}%", "annualised", annual);
+                    },
+                    _ => println!("  No MWRR for {} yet, it needs more than a day of history.", report.title())
+                }
+                if let Some(first) = portfolio.mwrr_unsolved().first() {
+                    println!("  no rate solves the flows of {} day(s) from {}, they are left out", portfolio.mwrr_unsolved().len(), first);
+                }
             },
 
             "/d" => {clear_last_n_lines(6);
-                println!("\n  Total dividends, {}", account_currency);
-                display_to_console(&cum_dividends, cum_dividends.first().unwrap().0, end_date, 40, 0.0, RGB8::new(0, 255, 0), account_currency.clone());
+                if report.cum_dividends.is_empty() {
+                    println!("  No dividends received ({}).", report.title());
+                } else {
+                println!("\n  Total dividends ({}), {}", report.title(), account_currency);
+                display_to_console(&report.cum_dividends, report.cum_dividends.first().unwrap().0, report.end_date, 40, 0.0, RGB8::new(0, 255, 0), account_currency.clone());
                 println!("\n  ______________________________________");
                 println!("  ticker:          total dividends ({})", account_currency);
                 println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
-                for (ticker, divi) in dividend_library.iter(){
-                    println!("  {0: <12}             {1:>.2}", ticker, divi)
+                for (ticker, divi) in report.dividend_library.iter(){
+                    println!("  {0: <12}             {1:>.2}", ticker, divi.amount)
                 };
                 println!("  ______________________________________");
-                println!("  Total:                   {:>.2}", total_dividends);
+                println!("  Total:                   {:>.2}", report.total_dividends.amount);
                 println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
-                println!("  dividend yield on cost (annual): {:.2}%", dividend_yield);
+                match report.dividend_yield {
+                    Some(dividend_yield) => println!("  dividend yield on cost (annual): {:.2}%", dividend_yield),
+                    None => println!("  dividend yield on cost (annual): n/a, the report covers a single day")
+                }
                 println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+                }
             },
 
             "/f" => {clear_last_n_lines(6);
-                let msg = format!("Total fees and taxes: {:.2} {}", fees_and_taxes.values().sum::<f32>() * -1.0, account_currency);
+                let msg = format!("Total fees and taxes ({}): {:.2} {}", report.title(), -portfolio.total_fees().amount, account_currency);
+                println!("  {}", msg);
+                println!("  {}", "‾".repeat(msg.chars().count()));
+                draw_pie(portfolio.fees().iter().map(|(k, v)| (k.clone(), -v.to_f64() as f32)).collect());
+                println!("  {}", "_".repeat(msg.chars().count()));
+            },
+
+            "/c" => {clear_last_n_lines(6);
+                match &report.reconciliation {
+                    Some(mismatches) => reconcile::print_reconciliation(&report.title(), mismatches),
+                    None => println!("  No reconciliation for {}, it is only run per account.", report.title())
+                }
+            },
+
+            "/b" => {clear_last_n_lines(6);
+                println!("\n  Account value incl. cash ({}), {}", report.title(), account_currency);
+                display_to_console(&report.account_value, report.start_date, report.end_date, 40, 10.0, RGB8::new(255, 165, 0), account_currency.clone());
+                println!("\n  ______________________________________________");
+                println!("  {0: <28} {1: >14.2}", "cash", report.current_cash.amount);
+                println!("  {0: <28} {1: >14.2}", "positions", report.current_positions);
+                println!("  {0: <28} {1: >14.2}", "total account value", report.current_cash.to_f64() + report.current_positions);
+                println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+                println!("  {0: <28} {1: >13.2}%", "average cash weight", report.avg_cash_weight);
+                match report.cash_drag {
+                    Some(cash_drag) => println!("  {0: <28} {1: >13.2}%", "est. cash drag (annual)", cash_drag),
+                    None => println!("  {0: <28} n/a", "est. cash drag (annual)")
+                }
+                let account_mwrr = portfolio.account_mwrr();
+                match (account_mwrr.cumulative(), account_mwrr.annual) {
+                    (Some(cumulative), Some(annual)) => {
+                        println!("  {0: <28} {1: >13.2}%", "MWRR incl. cash, cumulative", cumulative*100.0);
+                        println!("  {0: <28} {1: >13.2}%", "MWRR incl. cash, annualised", annual*100.0);
+                    },
+                    _ => println!("  {0: <28} n/a, {1}", "MWRR incl. cash", account_mwrr.convergence)
+                }
+                if let Some(broker) = &report.broker_cash {
+                    println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+                    println!("  {0: <28} {1: >14.2}", "broker free cash", broker.free);
+                    println!("  {0: <28} {1: >14.2}", "ledger - broker cash", report.current_cash.amount - broker.free);
+                    println!("  {0: <28} {1: >14.2}", "broker account value", broker.total);
+                }
+                println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+            },
+
+            "/p" => {clear_last_n_lines(6);
+                let total: f64 = report.allocation.values().sum();
+                let msg = format!("Allocation ({}): {:.2} {}", report.title(), total, account_currency);
                 println!("  {}", msg);
                 println!("  {}", "‾".repeat(msg.chars().count()));
-                draw_pie(fees_and_taxes.clone());
+                draw_pie(report.allocation.iter().map(|(k, v)| (k.clone(), *v as f32)).collect());
                 println!("  {}", "_".repeat(msg.chars().count()));
             },
 
+            "/a" => {clear_last_n_lines(6);
+                println!("  accounts:");
+                for (i, r) in portfolios.iter().map(|p| p.report()).enumerate() {
+                    println!("  {} {}", if i == selected {"*"} else {" "}, r.title());
+                }
+                println!("  switch with /a <label>");
+            },
+
             "/q" => {println!("  Quitting...");
                 break},
 
             "" => println!("  Enter valid command or /q to quit."),
             _ => {
                 clear_last_n_lines(1);
-                println!("  Unknown command: {}", command)
+                if command == "/o" || command.starts_with("/o ") {
+                    print_orders(report, command.strip_prefix("/o ").map(|ticker| ticker.trim()), &account_currency);
+                } else if command == "/i" || command.starts_with("/i ") {
+                    // yahoo's adjusted closes, fetched the first time they are needed and converted like the plain ones
+                    if total_return_prices.is_none() {
+                        total_return_prices = Some(prices::total_return_closes(&ticker_history, &account_currency, &units, &fx_history, &overrides, &price_chain).await);
+                    }
+                    let adjusted = total_return_prices.as_ref().unwrap();
+                    match command.strip_prefix("/i ").map(|ticker| ticker.trim()) {
+                        Some(ticker) => plot_total_return(report, ticker, adjusted),
+                        None => print_total_returns(report, &complete_prices, adjusted)
+                    }
+                } else if command == "/t" || command.starts_with("/t ") {
+                    let args: Vec<String> = command.split_whitespace().skip(1).map(|arg| arg.to_string()).collect();
+                    if let Err(e) = tickers_command(&args, &mut overrides, &price_chain).await {
+                        println!("  {}", e);
+                    }
+                } else if command == "/h" || command.starts_with("/h ") {
+                    match command.strip_prefix("/h ").map(|arg| arg.trim()) {
+                        None => print_heat_map(&report.title(), &portfolio.periods(Granularity::Month), &portfolio.periods(Granularity::Quarter), &portfolio.periods(Granularity::Year)),
+                        Some(arg) => match Granularity::parse(arg) {
+                            Some(granularity) => print_period_table(&report.title(), granularity.name(), &portfolio.periods(granularity), &account_currency),
+                            None => println!("  Unknown period {}, use /h month, /h quarter or /h year", arg)
+                        }
+                    }
+                } else if command == "/e" || command.starts_with("/e ") {
+                    // every month, quarter and year of the report in one CSV, named after the account and window by default
+                    let file = command.strip_prefix("/e ").map(|arg| arg.trim().to_string())
+                        .unwrap_or_else(|| format!("periods_{}_{}_{}.csv", report.label, report.start_date, report.end_date));
+                    match fs::write(&file, report.calendar.to_csv()) {
+                        Ok(()) => println!("  exported the period tables of {} to {}", report.title(), file),
+                        Err(e) => println!("  {}", Error::io(&file, e))
+                    }
+                } else if let Some(label) = command.strip_prefix("/a ") {
+                    match portfolios.iter().position(|p| p.report().label.eq_ignore_ascii_case(label.trim())) {
+                        Some(i) => {
+                            selected = i;
+                            print_header(portfolios[selected].report());
+                        },
+                        None => println!("  Unknown account: {}", label.trim())
+                    }
+                } else {
+                    println!("  Unknown command: {}", command)
+                }
                 }
         }
+        print_unpriced(portfolios[selected].report());
         printallcommands()
     }
 
-    // save user-entered cutom tickers back to json file
-    let file = OpenOptions::new()
-        .write(true)
-        .create(true)
-        .truncate(true) // ensures overwrite
-        .open(path)
-        .expect("json writer fail");
-
-    let _ = to_writer(file, &custom_tickers);
 }
 
 // ########################################################
@@ -446,104 +614,253 @@ fn main() {
 
 
 // HELPER FUNCS THAT STAY IN MAIN #########################
-fn remove_duplicates(orders: &mut Vec<Order>) {
-    let mut seen = HashSet::new();
-    orders.retain(|order| seen.insert(order.id));
+
+// shown when the program starts and when the user switches account
+fn print_header(report: &Report) {
+    let days_held: f32 = report.days_held;
+    let years_held: f32 = report.years_held;
+    let months_held: i32 = ((&years_held*12.0) as i32) % 12;                                                                              // vvv this is incorrect
+    println!("\n \n Found portfolio ({}) of {:.} years, {:.} months, and {:.} days, {} to {}.\n", report.title(), years_held.floor(), months_held, days_held as i32 % 365 - 30*months_held, report.start_date, report.end_date);
+    println!("\n  Unrealized return ({}), %", report.title());
+    display_to_console(&report.return_history, report.start_date, report.end_date, 70, 10.0, RGB8::new(254, 255, 110), String::from_str("%").unwrap());
 }
 
 
 
-fn get_time_range(data: &Vec<Order>) -> Result<Vec<NaiveDate>, Box<dyn Error>> {
-    
-    let root_date = data.first().ok_or("couldn't get first order")?.dateModified.as_str();    
+// stops the run with what went wrong, where, and what to do about it; nothing is shown from a history with holes in it
+fn exit_with(context: &str, e: Error) -> ! {
+    eprintln!("Error: {}: {}", context, e);
+    process::exit(1)
+}
 
-    // ^^^ last() returns an option, ok_or converts it to result, "?" propagates the error
 
-    let mut start_date = NaiveDate::parse_from_str(&root_date, "%Y-%m-%d")?;
-    
-    
-    let end_date = Utc::now().date_naive();
-    
-    let mut time_range = Vec::new();
-    
-    while start_date <= end_date {
-        time_range.push(start_date);
-        start_date += Duration::days(1);
+
+// price return against total return (distributions reinvested) over the time each ticker was held. the difference
+// is roughly what a distributing holding paid out; for an accumulating one both are the same
+fn print_total_returns(report: &Report, complete_prices: &HashMap<String, HashMap<NaiveDate, f64>>, adjusted: &HashMap<String, HashMap<NaiveDate, f64>>) {
+
+    let mut tickers: Vec<(&String, &(NaiveDate, NaiveDate))> = report.ticker_history.iter().collect();
+    tickers.sort_by(|a, b| a.0.cmp(b.0));
+
+    println!("\n  Price and total return per holding ({}), %", report.title());
+    println!("  ____________________________________________________________________________");
+    println!("  {0: <14} {1: >11} {2: >11} {3: >12} {4: >12} {5: >10}", "ticker", "from", "to", "price", "total", "difference");
+    println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+    for (ticker, (from, to)) in tickers {
+        // fallback series aren't market prices, so they have no price return to speak of
+        let priced = !report.unpriced.iter().any(|(t, _)| t == ticker);
+        let price = complete_prices.get(ticker).filter(|_| priced).and_then(|closes| stats::period_return(closes, *from, *to));
+        let total = adjusted.get(ticker).and_then(|closes| stats::period_return(closes, *from, *to));
+        let shown = |value: Option<f64>| value.map_or(String::from("n/a"), |v| format!("{:.2}", v));
+        let difference = match (price, total) {
+            (Some(p), Some(t)) => Some(t - p),
+            _ => None
+        };
+        println!("  {0: <14} {1: >11} {2: >11} {3: >12} {4: >12} {5: >10}", ticker, from.to_string(), to.to_string(), shown(price), shown(total), shown(difference));
     }
-    
-    Ok(time_range)    // return
+    println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+    println!("  plot one with /i <ticker>");
 }
 
 
 
-fn process_order(
-    order: &Order,
-    portfolio_t: &mut HashMap<String, (f64, f64)>,
-    ticker_history: &mut HashMap<String, (NaiveDate, NaiveDate)>,
-    real_returns: &mut HashMap<NaiveDate, (f64, f64)>,
-    cash_flows: &mut HashMap<NaiveDate, f64>,
-    last_date: NaiveDate) {
+fn plot_total_return(report: &Report, ticker: &str, adjusted: &HashMap<String, HashMap<NaiveDate, f64>>) {
+    let (from, to) = match report.ticker_history.get(ticker) {
+        Some(range) => *range,
+        None => {
+            println!("  {} was never held in {}", ticker, report.title());
+            return
+        }
+    };
+    let index = adjusted.get(ticker).map(|closes| stats::total_return_index(closes, from, to)).unwrap_or_default();
+    if index.len() < 2 {
+        println!("  No adjusted closes for {}.", ticker);
+        return
+    }
+    println!("\n  Total return index of {} ({}), start = 100", ticker, report.title());
+    display_to_console(&index, index.first().unwrap().0, index.last().unwrap().0, 40, 10.0, RGB8::new(100, 149, 237), String::from("pts"));
+}
 
-    let q_1 = order.filledQuantity;
-    let p_1 = order.fillPrice;
-    let date = NaiveDate::from_str(order.dateModified.as_str()).unwrap();
-    let ticker = order.ticker.clone();
-    
-    // log the order as a cash flow
-    cash_flows.entry(date).and_modify(|days_cash_flow| *days_cash_flow += (-q_1*p_1)).or_insert(-q_1*p_1);
-    
-    // log the order's presence in portolios and ticker histories
-    match portfolio_t.entry(order.ticker.clone()) {
-        Entry::Occupied(mut occupied) => {
-            
-            let (q_0, p_0) = occupied.get_mut();
-            
-            if *q_0 + q_1 == 0.0 {                                              // if sold everything
-                
-                let (keeps_date, _) = ticker_history.get(&ticker).unwrap();
-                ticker_history.insert(ticker, (*keeps_date, date));
-                
-                real_returns.entry(date)
-                .and_modify(|cbmv| *cbmv = (cbmv.0 + *p_0*(-q_1), cbmv.1 + p_1*(-q_1)))
-                .or_insert((*p_0*(-q_1), p_1*(-q_1)));
-            
-            occupied.remove();    // removes ticker from portfolio
-            
-        } else {
-            if q_1 >= 0.0 {                                                // if bought some *more*
-                *p_0 = (*q_0* *p_0 + q_1*p_1)/(*q_0 + q_1);
-                *q_0 += q_1;
-                
-                ticker_history.entry(ticker.clone())
-                    .and_modify(|e| e.1 = last_date.clone())
-                    .or_insert((date.clone(), last_date.clone()));
-                
-                
-            } else {
-                        *q_0 += q_1;                                           // if sold some (not everything)
-                        
-                        ticker_history.entry(ticker.clone())
-                        .and_modify(|e| e.1 = last_date.clone())
-                        .or_insert((date.clone(), last_date.clone()));
-                    
-                    
-                        real_returns.entry(date)
-                        .and_modify(|cbmv| *cbmv = (cbmv.0 + *p_0*(-q_1), cbmv.1 + p_1*(-q_1)))
-                        .or_insert((*p_0*(-q_1), p_1*(-q_1)));
-                    };
-        };
-    },
-    Entry::Vacant(vacant) => {                                            // if bought some
 
-            
-        vacant.insert((q_1, p_1));
-        
-        ticker_history.entry(ticker.clone())
-        .and_modify(|e| e.1 = last_date.clone())
-        .or_insert((date.clone(), last_date.clone()));
-},
-};
-}      // returns nothing, just amends portfolio_t and ticker_history in-place
+
+// the order ledger, or with a ticker its orders and dividends. times are at the instrument's exchange,
+// prices and values in account currency
+fn print_orders(report: &Report, ticker: Option<&str>, account_currency: &str) {
+
+    let orders: Vec<&Order> = report.orders.iter()
+        .filter(|o| o.status == "FILLED" && ticker.map_or(true, |t| o.ticker == t))
+        .collect();
+
+    match ticker {
+        Some(t) => println!("\n  Orders of {} ({}), {}", t, report.title(), account_currency),
+        None => println!("\n  Orders ({}), {}", report.title(), account_currency)
+    }
+    println!("  ________________________________________________________________________________________");
+    println!("  {0: <24} {1: <12} {2: <5} {3: >14} {4: >14} {5: >12}", "executed", "ticker", "side", "quantity", "price", "value");
+    println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+    for order in &orders {
+        let side = if order.filledQuantity.is_sell() {"sell"} else {"buy"};
+        println!("  {0: <24} {1: <12} {2: <5} {3: >14.4} {4: >14.4} {5: >12.2}",
+            exchanges::format_time(&order.executed, order.date), order.ticker, side, order.filledQuantity.abs(), order.fillPrice, (order.filledQuantity*order.fillPrice).abs());
+    }
+    if orders.is_empty() {
+        println!("  no filled orders");
+    }
+    println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+
+    // dividends keep T212 tickers
+    if let Some(t) = ticker {
+        let dividends: Vec<&Dividend> = report.dividends.iter().filter(|d| yahoo::convert_to_yahoo_ticker(d.ticker.clone()).is_ok_and(|y| y == t)).collect();
+        for dividend in &dividends {
+            println!("  {0: <24} {1: <12} {2: <5} {3: >14} {4: >14} {5: >12.2}", dividend.date.to_string(), t, "div", "", "", dividend.amount);
+        }
+        if !dividends.is_empty() {
+            println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+        }
+    } else {
+        println!("  one ticker with its dividends: /o <ticker>");
+    }
+}
 
 
 
+// a reminder under every view that some figures rest on estimated prices
+fn print_unpriced(report: &Report) {
+    if report.unpriced.is_empty() {
+        return
+    }
+    let listed: Vec<String> = report.unpriced.iter().map(|(ticker, fallback)| format!("{} ({})", ticker, fallback.describe())).collect();
+    for line in textwrap::wrap(&format!("! valued without market data: {}", listed.join(", ")), 90) {
+        println!("  {}", line);
+    }
+}
+
+
+
+// inspect lists what is cached, prune drops entries unused for a number of days (90 by default),
+// rebuild downloads everything (or one symbol) again, e.g. after yahoo corrected its history
+async fn cache_command(args: &[String], price_chain: &prices::PriceChain) {
+
+    let today = Utc::now().date_naive();
+
+    match args.first().map(|arg| arg.as_str()) {
+        None | Some("inspect") => {
+            if let Some(cache) = price_chain.cache() {
+                cache.print();
+            }
+            return
+        },
+        Some("prune") => {
+            let max_age: i64 = match args.get(1).map(|arg| arg.parse()) {
+                None => 90,
+                Some(Ok(v)) => v,
+                Some(Err(_)) => {
+                    eprintln!("Error: prune takes a number of days, e.g. cache prune 90");
+                    process::exit(1)
+                }
+            };
+            if let Some(mut cache) = price_chain.cache() {
+                let pruned = cache.prune(max_age, today);
+                println!("removed {} entries not used in the last {} days", pruned, max_age);
+            }
+        },
+        Some("rebuild") => {
+            let failed = price_chain.rebuild_cache(args.get(1).map(|arg| arg.as_str())).await;
+            if !failed.is_empty() {
+                println!("could not download {}, kept what was cached", failed.join(", "));
+            }
+        },
+        Some(other) => {
+            eprintln!("Error: unknown cache command {}, use inspect, prune or rebuild", other);
+            process::exit(1)
+        }
+    }
+    price_chain.save_cache();
+}
+
+
+
+// list (the default), add <ticker> <symbol> [options], edit <ticker> [options], remove <ticker>, test [ticker],
+// import <file> and export <file>, where options are symbol=, currency= (or none), scale= and fallback= (last_trade,
+// manual or zero). changes are saved
+// straight away and used from the next price download on
+async fn tickers_command(args: &[String], overrides: &mut overrides::TickerOverrides, price_chain: &prices::PriceChain) -> Result<(), Error> {
+
+    match args.first().map(|arg| arg.as_str()) {
+        None | Some("list") => overrides.print(),
+        Some("add") => {
+            let (ticker, symbol) = match (args.get(1), args.get(2)) {
+                (Some(ticker), Some(symbol)) => (ticker, symbol),
+                _ => return Err(Error::parse("tickers add", "usage: tickers add <ticker> <symbol> [currency=XXX] [scale=N]"))
+            };
+            let mut entry = overrides::TickerOverride::new(symbol);
+            apply_options(&mut entry, &args[3..])?;
+            overrides.set(ticker, entry)?;
+            println!("  {} is now priced from {}", ticker, symbol);
+        },
+        Some("edit") => {
+            let ticker = args.get(1).ok_or_else(|| Error::parse("tickers edit", "usage: tickers edit <ticker> [symbol=...] [currency=XXX|none] [scale=N]"))?;
+            let mut entry = overrides.get(ticker).cloned().ok_or_else(|| Error::parse("tickers edit", format!("no override for {}", ticker)))?;
+            apply_options(&mut entry, &args[2..])?;
+            overrides.set(ticker, entry)?;
+            println!("  updated {}", ticker);
+        },
+        Some("remove") => {
+            let ticker = args.get(1).ok_or_else(|| Error::parse("tickers remove", "usage: tickers remove <ticker>"))?;
+            if overrides.remove(ticker)? {
+                println!("  removed {}", ticker);
+            } else {
+                println!("  no override for {}", ticker);
+            }
+        },
+        Some("test") => {
+            // fetches the last ten days of every override (or one), so a typo shows up before the next full run
+            let today = Utc::now().date_naive();
+            let tested: Vec<(String, overrides::TickerOverride)> = overrides.tickers.iter()
+                .filter(|(ticker, entry)| args.get(1).map_or(true, |wanted| wanted == *ticker) && !entry.is_ignore())
+                .map(|(ticker, entry)| (ticker.clone(), entry.clone()))
+                .collect();
+            let requests = tested.iter().map(|(_, entry)| (entry.symbol.clone(), today - Duration::days(10), today)).collect();
+            let results = prices::fetch_all(requests, price_chain, "test prices").await;
+
+            for (ticker, entry) in &tested {
+                match results.get(&entry.symbol) {
+                    Some(Ok(closes)) if !closes.is_empty() => {
+                        let (date, close) = closes.iter().max_by_key(|(date, _)| **date).unwrap();
+                        println!("  {0: <16} ok, {1} closed at {2:.4} {3} on {4}", ticker, entry.symbol, close*entry.scale, entry.currency.as_deref().unwrap_or(""), date);
+                    },
+                    Some(Err(e)) => println!("  {0: <16} failed: {1}", ticker, e),
+                    _ => println!("  {0: <16} no prices for {1}", ticker, entry.symbol)
+                }
+            }
+        },
+        Some("import") => {
+            let file = args.get(1).ok_or_else(|| Error::parse("tickers import", "usage: tickers import <file>"))?;
+            let count = overrides.import(file)?;
+            println!("  imported {} overrides from {}", count, file);
+        },
+        Some("export") => {
+            let file = args.get(1).ok_or_else(|| Error::parse("tickers export", "usage: tickers export <file>"))?;
+            overrides.export(file)?;
+            println!("  exported {} overrides to {}", overrides.tickers.len(), file);
+        },
+        Some(other) => return Err(Error::parse("tickers command", format!("unknown command {}, use list, add, edit, remove, test, import or export", other)))
+    }
+    Ok(())
+}
+
+
+
+fn apply_options(entry: &mut overrides::TickerOverride, options: &[String]) -> Result<(), Error> {
+    for option in options {
+        match option.split_once('=') {
+            Some(("symbol", v)) => entry.symbol = v.to_string(),
+            Some(("currency", "none")) => entry.currency = None,
+            Some(("currency", v)) => entry.currency = Some(v.to_string()),
+            Some(("scale", v)) => entry.scale = v.parse().map_err(|_| Error::parse("scale", format!("it must be a number, not {}", v)))?,
+            Some(("fallback", v)) => entry.fallback = Some(v.parse().map_err(|e| Error::parse("fallback", e))?),
+            _ => return Err(Error::parse("ticker override", format!("unknown option {}, use symbol=, currency=, scale= or fallback=", option)))
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,359 @@
+use std::fmt;
+use std::iter::Product;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub, SubAssign};
+use std::str::FromStr;
+use rust_decimal::prelude::{FromPrimitive, ToPrimitive};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+
+
+// an ISO 4217 code, e.g. GBP. small enough to copy along with every amount
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Currency([u8; 3]);
+
+impl Currency {
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.0).unwrap()
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(code: &str) -> Result<Currency, String> {
+        match code.as_bytes() {
+            [a, b, c] if code.chars().all(|ch| ch.is_ascii_uppercase()) => Ok(Currency([*a, *b, *c])),
+            _ => Err(format!("invalid currency \"{}\", use an ISO code like GBP", code))
+        }
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+
+
+// an exact amount in one currency. adding or subtracting amounts in different currencies is a bug and panics,
+// they have to be converted first. cash, fees, dividends, cash flows and realised P&L are kept in Money (and fill
+// and average prices as exact Decimals) so their totals add up to the broker's to the penny; market values come
+// from float closes and fx rates and stay f64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Money {
+    pub amount: Decimal,
+    pub currency: Currency
+}
+
+impl Money {
+
+    pub fn new(amount: Decimal, currency: Currency) -> Money {
+        Money { amount, currency }
+    }
+
+    pub fn zero(currency: Currency) -> Money {
+        Money::new(Decimal::ZERO, currency)
+    }
+
+    // e.g. a settlement worked out from an fx converted price. NaN and infinities count as nothing
+    pub fn from_f64(amount: f64, currency: Currency) -> Money {
+        Money::new(Decimal::from_f64(amount).unwrap_or_default(), currency)
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.amount.to_f64().unwrap_or(0.0)
+    }
+
+    // to the penny (or cent), halves away from zero as brokers settle them
+    pub fn round(&self) -> Money {
+        Money::new(self.amount.round_dp_with_strategy(2, RoundingStrategy::MidpointAwayFromZero), self.currency)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.amount.is_zero()
+    }
+
+    // the sum of amounts in currency, zero if there are none
+    pub fn total<I: IntoIterator<Item = Money>>(currency: Currency, amounts: I) -> Money {
+        amounts.into_iter().fold(Money::zero(currency), |total, amount| total + amount)
+    }
+
+    fn same_currency(&self, other: &Money) {
+        assert!(self.currency == other.currency, "can't combine {} with {}", self, other);
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+    fn add(self, other: Money) -> Money {
+        self.same_currency(&other);
+        Money::new(self.amount + other.amount, self.currency)
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+    fn sub(self, other: Money) -> Money {
+        self.same_currency(&other);
+        Money::new(self.amount - other.amount, self.currency)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        *self = *self + other;
+    }
+}
+
+impl SubAssign for Money {
+    fn sub_assign(&mut self, other: Money) {
+        *self = *self - other;
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+    fn neg(self) -> Money {
+        Money::new(-self.amount, self.currency)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2} {}", self.amount, self.currency)
+    }
+}
+
+
+
+// a number of shares, fractional ones included. exact, so selling everything that was bought leaves zero
+// rather than a rounding leftover, and a position is closed when its quantity is_zero
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Quantity(pub Decimal);
+
+impl Quantity {
+
+    pub const ZERO: Quantity = Quantity(Decimal::ZERO);
+
+    pub fn from_f64(quantity: f64) -> Quantity {
+        Quantity(Decimal::from_f64(quantity).unwrap_or_default())
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or(0.0)
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0.is_zero()
+    }
+
+    pub fn is_sell(&self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+
+    pub fn abs(&self) -> Quantity {
+        Quantity(self.0.abs())
+    }
+
+    // times a split or merger ratio, multiplied before it is divided so 3 shares in a 1:3 reverse split are 1
+    pub fn scale(&self, ratio: Ratio) -> Quantity {
+        Quantity(self.0 * ratio.numerator / ratio.denominator)
+    }
+}
+
+impl Add for Quantity {
+    type Output = Quantity;
+    fn add(self, other: Quantity) -> Quantity {
+        Quantity(self.0 + other.0)
+    }
+}
+
+impl Sub for Quantity {
+    type Output = Quantity;
+    fn sub(self, other: Quantity) -> Quantity {
+        Quantity(self.0 - other.0)
+    }
+}
+
+impl AddAssign for Quantity {
+    fn add_assign(&mut self, other: Quantity) {
+        self.0 += other.0;
+    }
+}
+
+impl SubAssign for Quantity {
+    fn sub_assign(&mut self, other: Quantity) {
+        self.0 -= other.0;
+    }
+}
+
+impl Neg for Quantity {
+    type Output = Quantity;
+    fn neg(self) -> Quantity {
+        Quantity(-self.0)
+    }
+}
+
+// value of this many shares at a price
+impl Mul<Decimal> for Quantity {
+    type Output = Decimal;
+    fn mul(self, price: Decimal) -> Decimal {
+        self.0 * price
+    }
+}
+
+impl fmt::Display for Quantity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0.normalize(), f)
+    }
+}
+
+// new shares per old share in a split, merger or spin-off, kept as a fraction: a 1:3 reverse split is 1/3 and not
+// 0.333.., so a position restated by it still closes exactly
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ratio {
+    pub numerator: Decimal,
+    pub denominator: Decimal
+}
+
+impl Ratio {
+
+    pub const ONE: Ratio = Ratio { numerator: Decimal::ONE, denominator: Decimal::ONE };
+
+    pub fn new(numerator: u64, denominator: u64) -> Ratio {
+        Ratio { numerator: Decimal::from(numerator), denominator: Decimal::from(denominator) }
+    }
+
+    pub fn is_one(&self) -> bool {
+        self.numerator == self.denominator
+    }
+
+    pub fn is_positive(&self) -> bool {
+        self.numerator.is_sign_positive() && self.denominator.is_sign_positive() && !self.numerator.is_zero() && !self.denominator.is_zero()
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        (self.numerator / self.denominator).to_f64().unwrap_or(1.0)
+    }
+
+    // a price per old share as one per new share, e.g. 30 becomes 90 in a 1:3 reverse split
+    pub fn per_new_share(&self, price: Decimal) -> Decimal {
+        price * self.denominator / self.numerator
+    }
+}
+
+impl fmt::Display for Ratio {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.numerator.normalize(), self.denominator.normalize())
+    }
+}
+
+impl Mul for Ratio {
+    type Output = Ratio;
+    fn mul(self, other: Ratio) -> Ratio {
+        Ratio { numerator: self.numerator * other.numerator, denominator: self.denominator * other.denominator }
+    }
+}
+
+impl Product for Ratio {
+    fn product<I: Iterator<Item = Ratio>>(ratios: I) -> Ratio {
+        ratios.fold(Ratio::ONE, |product, ratio| product * ratio)
+    }
+}
+
+// "3:1" or "1:3" as a split is announced, or a number of new shares per old one, e.g. 0.95
+impl FromStr for Ratio {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Ratio, String> {
+        let invalid = || format!("invalid ratio \"{}\", use new:old shares like 1:3 or a number like 0.95", text);
+        let (numerator, denominator) = text.split_once(':').unwrap_or((text, "1"));
+        let numerator = Decimal::from_str(numerator.trim()).map_err(|_| invalid())?;
+        let denominator = Decimal::from_str(denominator.trim()).map_err(|_| invalid())?;
+        let ratio = Ratio { numerator, denominator };
+        if ratio.is_positive() {Ok(ratio)} else {Err(invalid())}
+    }
+}
+
+// from its text, so a number in the file is taken as written rather than as the nearest f64
+impl<'de> Deserialize<'de> for Ratio {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Ratio, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Number(number) => Ratio::from_str(&number.to_string()).map_err(de::Error::custom),
+            Value::String(text) => Ratio::from_str(&text).map_err(de::Error::custom),
+            other => Err(de::Error::custom(format!("invalid ratio {}, use new:old shares like \"1:3\" or a number", other)))
+        }
+    }
+}
+
+
+
+// T212 sends null for orders that never filled
+impl<'de> Deserialize<'de> for Quantity {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Quantity, D::Error> {
+        Ok(Quantity(Option::<Decimal>::deserialize(deserializer)?.unwrap_or_default()))
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gbp(amount: &str) -> Money {
+        Money::new(Decimal::from_str(amount).unwrap(), Currency::from_str("GBP").unwrap())
+    }
+
+    #[test]
+    fn fractional_shares_close_exactly() {
+        // 0.1 + 0.2 bought, 0.3 sold: not zero in f64
+        assert_ne!(0.1_f64 + 0.2 - 0.3, 0.0);
+
+        let bought: Quantity = serde_json::from_str("0.1").unwrap();
+        let more: Quantity = serde_json::from_str("0.2").unwrap();
+        let sold: Quantity = serde_json::from_str("-0.3").unwrap();
+        assert!((bought + more + sold).is_zero());
+
+        let never_filled: Quantity = serde_json::from_str("null").unwrap();
+        assert_eq!(never_filled, Quantity::ZERO);
+    }
+
+    #[test]
+    fn amounts_add_up_to_the_penny() {
+        let fees = vec![gbp("-0.15"), gbp("-0.15"), gbp("-0.15")];
+        assert_eq!(Money::total(Currency::from_str("GBP").unwrap(), fees), gbp("-0.45"));
+        assert_eq!(gbp("12.345").round(), gbp("12.35"));
+        assert_eq!(gbp("-12.345").round(), gbp("-12.35"));
+        assert_eq!(gbp("3.1").to_string(), "3.10 GBP");
+    }
+
+    #[test]
+    #[should_panic(expected = "can't combine")]
+    fn currencies_are_never_mixed() {
+        let usd = Money::new(Decimal::ONE, Currency::from_str("USD").unwrap());
+        let _ = gbp("1") + usd;
+    }
+
+    #[test]
+    fn reverse_split_closes_exactly() {
+        let ratio: Ratio = serde_json::from_str("\"1:3\"").unwrap();
+        let held = Quantity(Decimal::from(3));
+        assert!((held.scale(ratio) - Quantity(Decimal::ONE)).is_zero());
+        assert_eq!(ratio.per_new_share(Decimal::from(30)), Decimal::from(90));
+        assert_eq!(serde_json::from_str::<Ratio>("0.95").unwrap(), Ratio { numerator: Decimal::from_str("0.95").unwrap(), denominator: Decimal::ONE });
+        assert_eq!([Ratio::new(2, 1), Ratio::new(1, 3)].into_iter().product::<Ratio>(), Ratio::new(2, 3));
+        assert!(Ratio::from_str("0:1").is_err());
+        assert!(Ratio::from_str("ten").is_err());
+    }
+
+    #[test]
+    fn currency_codes() {
+        assert_eq!(Currency::from_str("EUR").unwrap().as_str(), "EUR");
+        assert!(Currency::from_str("GBp").is_err());
+        assert!(Currency::from_str("POUND").is_err());
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
+use serde_json::from_reader;
+use crate::error::{Error, Result};
+use crate::unpriced::Fallback;
+
+
+
+// where to get prices for a ticker instead, and in what terms they are quoted. without a currency the
+// listing's own quote currency is used and scale comes on top of it; with one, scale multiplies every close first
+// (0.01 turns pence into pounds). a symbol of IGNORE skips the ticker altogether. fallback says how the position
+// is valued when no prices can be found, the last trade price when not given
+#[derive(Debug, Clone, PartialEq)]
+pub struct TickerOverride {
+    pub symbol: String,
+    pub currency: Option<String>,
+    pub scale: f64,
+    pub fallback: Option<Fallback>
+}
+
+impl TickerOverride {
+
+    pub fn new(symbol: &str) -> TickerOverride {
+        TickerOverride { symbol: symbol.to_string(), currency: None, scale: 1.0, fallback: None }
+    }
+
+    pub fn is_ignore(&self) -> bool {
+        self.symbol.eq_ignore_ascii_case("ignore")
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.symbol.trim().is_empty() || self.symbol.contains(char::is_whitespace) {
+            return Err(format!("invalid symbol \"{}\"", self.symbol))
+        }
+        if let Some(currency) = &self.currency {
+            if currency == "GBX" || currency == "GBp" {
+                return Err(String::from("prices in pence are currency GBP with scale 0.01"))
+            }
+            if currency.len() != 3 || !currency.chars().all(|c| c.is_ascii_uppercase()) {
+                return Err(format!("invalid currency \"{}\", use an ISO code like USD", currency))
+            }
+        }
+        if !(self.scale.is_finite() && self.scale > 0.0) {
+            return Err(format!("scale must be a positive number, not {}", self.scale))
+        }
+        Ok(())
+    }
+}
+
+// plain mappings stay plain strings in the file, as custom_tickers.json always had them
+impl Serialize for TickerOverride {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.currency.is_none() && self.scale == 1.0 && self.fallback.is_none() {
+            return serializer.serialize_str(&self.symbol)
+        }
+        let mut entry = serializer.serialize_struct("TickerOverride", 4)?;
+        entry.serialize_field("symbol", &self.symbol)?;
+        if let Some(currency) = &self.currency {
+            entry.serialize_field("currency", currency)?;
+        }
+        if self.scale != 1.0 {
+            entry.serialize_field("scale", &self.scale)?;
+        }
+        if let Some(fallback) = &self.fallback {
+            entry.serialize_field("fallback", fallback)?;
+        }
+        entry.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for TickerOverride {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Symbol(String),
+            Full {
+                symbol: String,
+                #[serde(default)]
+                currency: Option<String>,
+                #[serde(default = "one")]
+                scale: f64,
+                #[serde(default)]
+                fallback: Option<Fallback>
+            }
+        }
+
+        Ok(match Raw::deserialize(deserializer)? {
+            Raw::Symbol(symbol) => TickerOverride::new(&symbol),
+            Raw::Full { symbol, currency, scale, fallback } => TickerOverride { symbol, currency, scale, fallback }
+        })
+    }
+}
+
+fn one() -> f64 {
+    1.0
+}
+
+
+
+// custom_tickers.json, e.g. {"VUAA.MI": "VUAA.DE", "SMT.L": {"symbol": "SMT.L", "currency": "GBP", "scale": 0.01},
+// "SIVB": {"symbol": "IGNORE", "fallback": "zero"}}.
+// every change is written straight away, so nothing typed in a session is lost to Ctrl-C
+#[derive(Debug, Default)]
+pub struct TickerOverrides {
+    pub path: String,
+    pub tickers: BTreeMap<String, TickerOverride>
+}
+
+impl TickerOverrides {
+
+    // a missing file is no overrides; an unreadable one is an error, as the next save would overwrite it
+    pub fn load(path: &str) -> Result<TickerOverrides> {
+        let tickers = match File::open(path) {
+            Ok(file) if file.metadata().map_err(|e| Error::io(path, e))?.len() > 0 => from_reader(BufReader::new(file)).map_err(|e| Error::parse(path, e))?,
+            Ok(_) => BTreeMap::new(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(Error::io(path, e))
+        };
+        Ok(TickerOverrides { path: path.to_string(), tickers })
+    }
+
+    // written to a temporary file first and renamed over the old one, so the file is always complete
+    pub fn save(&self) -> Result<()> {
+        let temp = format!("{}.tmp", self.path);
+        let text = serde_json::to_string_pretty(&self.tickers).map_err(|e| Error::parse(&self.path, e))?;
+        fs::write(&temp, text).map_err(|e| Error::io(&temp, e))?;
+        fs::rename(&temp, &self.path).map_err(|e| Error::io(&self.path, e))
+    }
+
+    pub fn get(&self, ticker: &str) -> Option<&TickerOverride> {
+        self.tickers.get(ticker)
+    }
+
+    // the symbol prices are fetched under, the ticker itself when there is no override
+    pub fn target(&self, ticker: &str) -> String {
+        self.get(ticker).map_or(ticker.to_string(), |o| o.symbol.clone())
+    }
+
+    pub fn set(&mut self, ticker: &str, entry: TickerOverride) -> Result<()> {
+        entry.validate().map_err(|e| Error::parse(format!("override for {}", ticker), e))?;
+        self.tickers.insert(ticker.to_string(), entry);
+        self.save()
+    }
+
+    pub fn remove(&mut self, ticker: &str) -> Result<bool> {
+        let removed = self.tickers.remove(ticker).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    // merges another overrides file in, its entries win; returns how many were taken
+    pub fn import(&mut self, path: &str) -> Result<usize> {
+        let other = TickerOverrides::load(path)?;
+        for (ticker, entry) in &other.tickers {
+            entry.validate().map_err(|e| Error::parse(path, format!("{}: {}", ticker, e)))?;
+        }
+        let count = other.tickers.len();
+        self.tickers.extend(other.tickers);
+        self.save()?;
+        Ok(count)
+    }
+
+    pub fn export(&self, path: &str) -> Result<()> {
+        let text = serde_json::to_string_pretty(&self.tickers).map_err(|e| Error::parse(path, e))?;
+        fs::write(path, text).map_err(|e| Error::io(path, e))
+    }
+
+    pub fn fallback(&self, ticker: &str) -> Fallback {
+        self.get(ticker).and_then(|o| o.fallback).unwrap_or_default()
+    }
+
+    // currencies named by overrides, which need fx rates on top of the usual ones
+    pub fn currencies(&self) -> Vec<String> {
+        let mut currencies: Vec<String> = self.tickers.values().filter_map(|o| o.currency.clone()).collect();
+        currencies.sort();
+        currencies.dedup();
+        currencies
+    }
+
+    pub fn print(&self) {
+        println!("\n  Ticker overrides ({})", self.path);
+        println!("  ______________________________________________________________");
+        println!("  ticker           prices from      currency     scale  if unpriced");
+        println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+        for (ticker, entry) in &self.tickers {
+            println!("  {0: <16} {1: <16} {2: <9} {3: >8}  {4}", ticker, entry.symbol, entry.currency.as_deref().unwrap_or("-"), entry.scale, entry.fallback.unwrap_or_default().describe());
+        }
+        println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_plain_and_structured_entries() {
+        let text = r#"{"VUAA.MI": "VUAA.DE", "SMT.L": {"symbol": "SMT.L", "currency": "GBP", "scale": 0.01}, "SIVB": {"symbol": "IGNORE", "fallback": "zero"}}"#;
+        let tickers: BTreeMap<String, TickerOverride> = serde_json::from_str(text).unwrap();
+
+        assert_eq!(tickers["VUAA.MI"], TickerOverride::new("VUAA.DE"));
+        assert_eq!(tickers["SMT.L"].currency.as_deref(), Some("GBP"));
+        assert_eq!(tickers["SMT.L"].scale, 0.01);
+        assert_eq!(tickers["SIVB"].fallback, Some(Fallback::Zero));
+        assert!(tickers["SIVB"].is_ignore());
+
+        // and writes them back the same way
+        assert_eq!(serde_json::to_string(&tickers).unwrap(), r#"{"SIVB":{"symbol":"IGNORE","fallback":"zero"},"SMT.L":{"symbol":"SMT.L","currency":"GBP","scale":0.01},"VUAA.MI":"VUAA.DE"}"#);
+    }
+
+    #[test]
+    fn rejects_pence_and_bad_scales() {
+        let mut entry = TickerOverride::new("SMT.L");
+        entry.currency = Some(String::from("GBX"));
+        assert!(entry.validate().is_err());
+
+        entry.currency = Some(String::from("GBP"));
+        entry.scale = 0.0;
+        assert!(entry.validate().is_err());
+
+        entry.scale = 0.01;
+        assert!(entry.validate().is_ok());
+    }
+
+    #[test]
+    fn every_change_is_saved() {
+        let path = std::env::temp_dir().join(format!("t212_overrides_{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let mut overrides = TickerOverrides::load(path).unwrap();
+        overrides.set("VUAA.MI", TickerOverride::new("VUAA.DE")).unwrap();
+        assert_eq!(TickerOverrides::load(path).unwrap().target("VUAA.MI"), "VUAA.DE");
+
+        overrides.remove("VUAA.MI").unwrap();
+        assert_eq!(TickerOverrides::load(path).unwrap().target("VUAA.MI"), "VUAA.MI");
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unreadable_file_is_an_error() {
+        // a path through a file can't be opened, and isn't the same as no file
+        let file = std::env::temp_dir().join(format!("t212_overrides_file_{}", std::process::id()));
+        fs::write(&file, "").unwrap();
+        let path = file.join("custom_tickers.json");
+
+        assert!(matches!(TickerOverrides::load(path.to_str().unwrap()), Err(Error::Io { .. })));
+        fs::remove_file(file).unwrap();
+    }
+}
@@ -0,0 +1,68 @@
+use std::str::FromStr;
+use chrono::{Datelike, Duration, Months, NaiveDate};
+use crate::error::{Error, Result};
+
+
+
+// the stretch of history the reports cover, ending on the day they are as of. everything before it still counts
+// towards the positions, cash and values it opens with, but returns, MWRR, realised P&L, dividends and fees start
+// from zero on its first day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Period {
+    All,
+    YearToDate,
+    Years(u32),             // the last n years, e.g. 1y or 3y
+    Since(NaiveDate)
+}
+
+impl Period {
+
+    // all, ytd, 1y, 3y, ... or a first day as YYYY-MM-DD
+    pub fn parse(text: &str) -> Result<Period> {
+        let text = text.trim().to_lowercase();
+        match text.as_str() {
+            "all" => return Ok(Period::All),
+            "ytd" => return Ok(Period::YearToDate),
+            _ => {}
+        }
+        if let Some(years) = text.strip_suffix('y').and_then(|n| n.parse::<u32>().ok()).filter(|n| *n > 0) {
+            return Ok(Period::Years(years))
+        }
+        NaiveDate::from_str(&text)
+            .map(Period::Since)
+            .map_err(|_| Error::parse("period", format!("{} is not all, ytd, a number of years like 1y or a date like 2024-04-01", text)))
+    }
+
+    // the first day covered by reports as of end_date; None for the whole history
+    pub fn start(&self, end_date: NaiveDate) -> Option<NaiveDate> {
+        match self {
+            Period::All => None,
+            Period::YearToDate => NaiveDate::from_ymd_opt(end_date.year(), 1, 1),
+            // the day after the same date n years earlier, so a year's change is measured from a year-end close
+            Period::Years(n) => end_date.checked_sub_months(Months::new(12 * n)).map(|day| day + Duration::days(1)),
+            Period::Since(day) => Some(*day)
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+
+    #[test]
+    fn periods_start_where_a_statement_would() {
+        let end = day("2024-06-30");
+        assert_eq!(Period::parse("YTD").unwrap().start(end), Some(day("2024-01-01")));
+        assert_eq!(Period::parse("1y").unwrap().start(end), Some(day("2023-07-01")));
+        assert_eq!(Period::parse("3y").unwrap().start(end), Some(day("2021-07-01")));
+        assert_eq!(Period::parse("2024-04-01").unwrap().start(end), Some(day("2024-04-01")));
+        assert_eq!(Period::parse("all").unwrap().start(end), None);
+        // a leap day a year on is the last day of February
+        assert_eq!(Period::Years(1).start(day("2025-02-28")), Some(day("2024-02-29")));
+        assert!(matches!(Period::parse("0y"), Err(Error::Parse { .. })));
+        assert!(matches!(Period::parse("last quarter"), Err(Error::Parse { .. })));
+    }
+}
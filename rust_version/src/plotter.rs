@@ -4,6 +4,7 @@ use chrono::{Duration, NaiveDate};
 use std::{collections::HashMap, io::{self, Write}};
 use piechart::{Chart as PieChart, Color, Data, Style};
 use crate::stats::hashmap_to_sorted_vec;
+use crate::calendar::PeriodRow;
 
 pub fn display_to_console(
     data_to_plot_1: &Vec<(NaiveDate, f32)>,
@@ -14,7 +15,10 @@ pub fn display_to_console(
     colour: RGB8,
     units: String) {
 
-
+    if data_to_plot_1.is_empty() {
+        println!("  nothing to plot between {} and {}", start_date, end_date);
+        return
+    }
 
     let mut points: Vec<(f32, f32)> = data_to_plot_1
         .iter()
@@ -65,7 +69,76 @@ pub fn display_to_console(
 pub fn printallcommands() {
     println!("\n  /s      view portfolio statistics            /m      view MWRR (Trading 212 returns)");
     println!("  /r      view realized returns                /d      view dividend statistics");
-    println!("  /f      view fees and taxes                  /q      quit\n");
+    println!("  /f      view fees and taxes                  /a      list or switch accounts (/a <label>)");
+    println!("  /c      reconcile with broker positions      /b      view cash and account value");
+    println!("  /p      view allocation (incl. cash)         /t      ticker overrides (/t add|edit|remove|test ...)");
+    println!("  /i      price vs total return per holding    /o      order ledger (/o <ticker> for one)");
+    println!("  /h      returns by month, quarter and year   /e      export the period tables (/e <file>)");
+    println!("  /q      quit\n");
+}
+
+
+
+// time-weighted returns as a heat map: a line per year with its months, quarters and the year itself. each group
+// is coloured on its own scale, so a good month is as green as a good year
+pub fn print_heat_map(title: &str, months: &[PeriodRow], quarters: &[PeriodRow], years: &[PeriodRow]) {
+    let month_names = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let twr = |row: &PeriodRow| row.twr;
+    let (month_scale, quarter_scale, year_scale) = (heat_scale(months, twr), heat_scale(quarters, twr), heat_scale(years, twr));
+    let find = |rows: &[PeriodRow], label: String| rows.iter().find(|row| row.label == label).and_then(|row| row.twr);
+
+    println!("\n  Time-weighted return by month, quarter and year ({}), %", title);
+    print!("\n  {0: <6}", "");
+    for name in month_names.iter().chain(["Q1", "Q2", "Q3", "Q4"].iter()) {
+        print!("{0: >7}", name);
+    }
+    println!("{0: >8}", "year");
+    for year in years {
+        print!("  {0: <6}", year.label);
+        for month in 1..=12 {
+            print!("{}", heat_cell(find(months, format!("{}-{:02}", year.label, month)), month_scale));
+        }
+        for quarter in 1..=4 {
+            print!("{}", heat_cell(find(quarters, format!("{}-Q{}", year.label, quarter)), quarter_scale));
+        }
+        println!(" {}", heat_cell(year.twr, year_scale));
+    }
+    println!("\n  /h month, /h quarter or /h year for every figure of each period, /e to export them");
+}
+
+
+
+// every figure of each period, with the two rates coloured like the heat map. MWRR moves with the timing of
+// deposits as well as the market, so it is coloured on its own scale
+pub fn print_period_table(title: &str, granularity: &str, rows: &[PeriodRow], currency: &str) {
+    let (twr_scale, mwrr_scale) = (heat_scale(rows, |row| row.twr), heat_scale(rows, |row| row.mwrr));
+    println!("\n  Performance by {} ({}), {}", granularity, title, currency);
+    println!("  {0: <9}{1: >7}{2: >7}{3: >12}{4: >11}{5: >9}{6: >14}{7: >14}", "period", "TWR %", "MWRR %", "realised", "dividends", "fees", "net contrib.", "ending value");
+    println!("  {}", "‾".repeat(83));
+    for row in rows {
+        println!("  {0: <9}{1}{2}{3: >12.2}{4: >11.2}{5: >9.2}{6: >14.2}{7: >14.2}", row.label, heat_cell(row.twr, twr_scale), heat_cell(row.mwrr, mwrr_scale),
+            row.realised, row.dividends, row.fees, row.net_contributions, row.ending_value);
+    }
+    println!("  {}", "‾".repeat(83));
+}
+
+
+
+// the largest move of one rate among the rows, which gets the strongest colour
+fn heat_scale(rows: &[PeriodRow], rate: impl Fn(&PeriodRow) -> Option<f64>) -> f64 {
+    rows.iter().filter_map(rate).fold(0.5, |max, value| max.max(value.abs()))
+}
+
+// seven characters wide, grey at zero turning greener or redder with the size of the move
+fn heat_cell(value: Option<f64>, scale: f64) -> String {
+    let value = match value {
+        Some(v) => v,
+        None => return format!("{0: >7}", "·")
+    };
+    let strength = (value.abs() / scale).min(1.0);
+    let (r, g, b) = if value >= 0.0 {(0.0, 150.0, 60.0)} else {(190.0, 30.0, 30.0)};
+    let mix = |to: f64| (60.0 + (to - 60.0) * strength) as u8;
+    format!("\x1B[48;2;{};{};{}m\x1B[97m{:>6.1} \x1B[0m", mix(r), mix(g), mix(b), value)
 }
 
 
@@ -82,14 +155,26 @@ pub fn clear_last_n_lines(n: u8) {
 }
 
 
+// rewrites one status line in place, e.g. "  fetching prices  12/83", and ends it once everything is in
+pub fn print_progress(what: &str, done: usize, total: usize) {
+    let mut stdout = io::stdout();
+    write!(stdout, "\r  fetching {} {:>4}/{}", what, done, total).unwrap();
+    if done == total {
+        writeln!(stdout).unwrap();
+    }
+    stdout.flush().unwrap();
+}
+
+
+// slices are drawn largest first; values must be positive, so callers flip the sign of fees
 pub fn draw_pie(hm: HashMap<String, f32>){
     let mut data = vec![];
     let mut palette = [Color::RGB(190, 190, 0), Color::RGB(220, 5, 5), Color::Green, Color::Black].iter().cycle();
     let mut ordered_hm: Vec<(String, f32)> = hm.into_iter().collect();
-    ordered_hm.sort_by(|a, b| a.1.total_cmp(&b.1));
+    ordered_hm.sort_by(|a, b| b.1.total_cmp(&a.1));
 
     for (key, val) in ordered_hm.iter(){
-        let twodpval = (val * 100.0).round() / 100.0 * -1.0;
+        let twodpval = (val * 100.0).round() / 100.0;
         data.push(Data { label: key.into(), value: twodpval, color: Some(Style::from(*palette.next().unwrap())), fill: '•' });
         
     } 
@@ -0,0 +1,859 @@
+use chrono::NaiveDate;
+use std::collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap};
+use crate::stats::{self, hashmap_to_btree, hashmap_to_sorted_vec, interpolate, xirr, Convergence, Xirr};
+use crate::accounts::Environment;
+use crate::reconcile::Mismatch;
+use crate::cash;
+use crate::calendar::{Calendar, Day};
+use crate::corporate::{self, ActionKind, CorporateAction};
+use crate::t212::{Cash, Dividend, Order, Transaction};
+use crate::quotes::QuoteUnit;
+use crate::unpriced::Fallback;
+use crate::money::{Currency, Money, Quantity};
+use crate::error::{Error, Result};
+use rust_decimal::prelude::FromPrimitive;
+use rust_decimal::Decimal;
+
+
+
+// everything that can be derived from an order and dividend history before market prices are known.
+// one Book is built per account, plus one for the consolidated view of all accounts
+pub struct Book {
+    pub label: String,
+    pub environment: Environment,
+    pub currency: Currency,                            // of the account, which cash, fees and dividends are in
+    pub time_range: Vec<NaiveDate>,
+    pub window_start: NaiveDate,                       // first day the report covers, see period::Period
+    pub portfolio_history: Vec<(NaiveDate, HashMap<String, (Quantity, Decimal)>)>,
+    pub cash_flows: HashMap<NaiveDate, Money>,                 // buys negative, sales and dividends positive
+    pub ticker_history: HashMap<String, (NaiveDate, NaiveDate)>,
+    pub real_returns: HashMap<NaiveDate, (Money, Money)>,      // cost basis and proceeds of what was sold each day
+    pub dividend_history: BTreeMap<NaiveDate, Money>,
+    pub dividend_library: HashMap<String, Money>,
+    pub cum_dividends: Vec<(NaiveDate, f32)>,
+    pub total_dividends: Money,
+    pub fees_and_taxes: HashMap<String, Money>,
+    pub holdings: HashMap<String, (Quantity, Decimal)>,    // today's positions and their cost basis, i.e. the final portfolio_t
+    pub cash_ledger: BTreeMap<NaiveDate, Money>,
+    pub external_flows: BTreeMap<NaiveDate, Money>,
+    pub reconciliation: Option<Vec<Mismatch>>,         // only for single accounts, set after comparing with the broker
+    pub broker_cash: Option<Cash>,                     // likewise
+    pub orders: Vec<Order>,                            // as passed in, for the order ledger
+    pub dividends: Vec<Dividend>
+}
+
+
+
+// everything the console commands display for one account (or the consolidated view)
+pub struct Report {
+    pub label: String,
+    pub environment: Environment,
+    pub currency: Currency,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    pub years_held: f32,
+    pub days_held: f32,
+    pub return_history: Vec<(NaiveDate, f32)>,
+    pub real_returns_abs: Vec<(NaiveDate, f32)>,
+    pub mwrr_returns: Vec<(NaiveDate, f32)>,            // %, cumulative from the first order to each day
+    pub mwrr_annualised: Vec<(NaiveDate, f32)>,         // %, the same rates a year
+    pub mwrr_unsolved: Vec<NaiveDate>,                  // days whose flows have no rate, left out of both
+    pub cb_mv_history: BTreeMap<NaiveDate, (f64, f64)>,
+    pub cum_dividends: Vec<(NaiveDate, f32)>,
+    pub dividend_library: HashMap<String, Money>,
+    pub total_dividends: Money,
+    pub fees_and_taxes: HashMap<String, Money>,
+    pub current_return: f32,
+    pub annual_return: Option<f32>,                     // None over a single day
+    pub mean: f32,
+    pub sd: f32,
+    pub sharpe: f32,
+    pub dividend_yield: Option<f64>,
+    pub account_value: Vec<(NaiveDate, f32)>,           // cash plus market value of positions
+    pub current_cash: Money,
+    pub current_positions: f64,
+    pub avg_cash_weight: f32,                           // %, average share of account value held as cash
+    pub cash_drag: Option<f32>,                         // %, estimated annual return given up by holding cash
+    pub account_mwrr: Xirr,                             // over deposits and withdrawals with cash included
+    pub calendar: Calendar,                             // the window day by day, for the period tables
+    pub allocation: HashMap<String, f64>,               // market value per ticker today, plus a "cash" slice
+    pub holdings: HashMap<String, (Quantity, Decimal)>, // quantity and cost basis of today's positions
+    pub reconciliation: Option<Vec<Mismatch>>,
+    pub broker_cash: Option<Cash>,
+    pub unpriced: Vec<(String, Fallback)>,              // tickers of this book valued without market data
+    pub ticker_history: HashMap<String, (NaiveDate, NaiveDate)>,    // first and last day each ticker was held
+    pub orders: Vec<Order>,                             // in account currency, with yahoo tickers
+    pub dividends: Vec<Dividend>                        // with T212 tickers
+}
+
+impl Report {
+    // account label tagged with its environment, so paper and live results are never confused
+    pub fn title(&self) -> String {
+        format!("{} [{}]", self.label, self.environment.name())
+    }
+}
+
+
+
+// the histories a book is built from, all ascending. orders must already be deduplicated, converted to yahoo
+// tickers and fx adjusted
+pub struct History<'a> {
+    pub orders: &'a Vec<Order>,
+    pub dividends: &'a Vec<Dividend>,
+    pub transactions: &'a Vec<Transaction>,
+    pub actions: &'a Vec<CorporateAction>
+}
+
+
+
+pub fn build_book(
+    label: &str,
+    environment: Environment,
+    currency: Currency,
+    history: History,
+    time_range: Vec<NaiveDate>) -> Result<Book> {
+
+    let History { orders, dividends: dividend_history, transactions, actions } = history;
+    let (first_date, last_date) = match (time_range.first(), time_range.last()) {
+        (Some(first), Some(last)) => (*first, *last),
+        _ => return Err(Error::parse("time range", "it has no days"))
+    };
+
+    let mut portfolio_history: Vec<(NaiveDate, HashMap<String, (Quantity, Decimal)>)> = time_range.clone()
+    .into_iter()
+    .map(|d| (d, HashMap::new()))    // create empty portfolio hashmap for every date
+    .collect();
+
+    // initialize where we store cash flows (only for use in mwrr calculations)
+    let mut cash_flows: HashMap<NaiveDate, Money> = HashMap::new();
+
+    // initialize where we store dates for which certain tickers wiere present in portfolio
+    let mut ticker_history: HashMap<String, (NaiveDate, NaiveDate)> = HashMap::new();
+
+    // initialize portfolio "holder/folder" at time t
+    let mut portfolio_t: HashMap<String, (Quantity, Decimal)> = HashMap::new();
+
+    // initialize where we store realized returns
+    let mut real_returns: HashMap<NaiveDate, (Money, Money)> = HashMap::new();
+
+    // initialize storage of total fees
+    let mut fees_and_taxes: HashMap<String, Money> = HashMap::new();
+
+
+    // splits and renames are already in the orders (corporate::restate_orders), mergers and spin-offs happen here
+    let mut pending = actions.iter()
+        .filter(|a| matches!(a.action, ActionKind::Merger | ActionKind::Spinoff))
+        .peekable();
+
+    for order in orders {
+
+        let matcher_date = order.date;
+
+        // corporate actions take effect before the orders of their day
+        while let Some(action) = pending.next_if(|a| a.date <= matcher_date) {
+            corporate::apply_action(action, &mut portfolio_t, &mut ticker_history, last_date);
+            if let Some(index) = time_range.iter().position(|&r| r == action.date) {
+                portfolio_history[index] = (action.date, portfolio_t.clone());
+            }
+        }
+
+        // filtering out cancelled or rejected orders
+        if order.status == "FILLED" {
+            // log the order as a cash flow, negative for buys
+            let settled = Money::new(order.filledQuantity * order.fillPrice, currency);
+            *cash_flows.entry(matcher_date).or_insert(Money::zero(currency)) -= settled;
+
+            if let Some((cost, proceeds)) = process_order(&order, &mut portfolio_t, &mut ticker_history, last_date) {
+                let (cb, mv) = real_returns.entry(matcher_date).or_insert((Money::zero(currency), Money::zero(currency)));
+                *cb += Money::new(cost, currency);
+                *mv += Money::new(proceeds, currency);
+            }
+        } else {};
+
+        // set portoflio history's element to a correct pair of {Date: portfolio_t}
+        let index = time_range.iter().position(|&r| r == matcher_date)
+            .ok_or_else(|| Error::parse("time range", format!("order {} is dated {}, outside {} to {}", order.id, matcher_date, first_date, last_date)))?;
+        portfolio_history[index] = (matcher_date, portfolio_t.clone());
+
+        // adding taxes
+        for fee in &order.taxes {
+            *fees_and_taxes.entry(fee.name.clone()).or_insert(Money::zero(currency)) += Money::new(fee.quantity, currency);
+        };
+    };
+
+    // actions after the last order
+    while let Some(action) = pending.next_if(|a| a.date <= last_date) {
+        corporate::apply_action(action, &mut portfolio_t, &mut ticker_history, last_date);
+        if let Some(index) = time_range.iter().position(|&r| r == action.date) {
+            portfolio_history[index] = (action.date, portfolio_t.clone());
+        }
+    }
+
+
+    // PARSING DIVIDENDS
+    let mut blarg: BTreeMap<NaiveDate, Money> = BTreeMap::new();
+    let mut total_dividends = Money::zero(currency);
+    let mut cum_dividends: HashMap<NaiveDate, f32> = HashMap::new();
+    let mut dividend_library: HashMap<String, Money> = HashMap::new();
+
+    for dividend in dividend_history {
+        let date = dividend.date;
+        let amount = Money::new(dividend.amount, currency);
+        *cash_flows.entry(date).or_insert(Money::zero(currency)) += amount;
+        *dividend_library.entry(dividend.ticker.clone()).or_insert(Money::zero(currency)) += amount;
+        *blarg.entry(date).or_insert(Money::zero(currency)) += amount;
+        total_dividends += amount;
+        cum_dividends.entry(date).insert_entry(total_dividends.to_f64() as f32);
+    }
+    let mut cum_dividends = hashmap_to_sorted_vec(cum_dividends);
+    interpolate(&mut cum_dividends);
+
+    let cash_ledger = cash::build_ledger(currency, &time_range, orders, dividend_history, transactions);
+    let external_flows = cash::external_flows(currency, transactions);
+
+    Ok(Book {
+        label: label.to_string(),
+        environment,
+        currency,
+        window_start: first_date,
+        time_range,
+        portfolio_history,
+        cash_flows,
+        ticker_history,
+        real_returns,
+        dividend_history: blarg,
+        dividend_library,
+        cum_dividends,
+        total_dividends,
+        fees_and_taxes,
+        holdings: portfolio_t,
+        cash_ledger,
+        external_flows,
+        reconciliation: None,
+        broker_cash: None,
+        orders: orders.clone(),
+        dividends: dividend_history.clone()
+    })
+}
+
+
+
+// the time-series half of the analysis, run once prices for every ticker in book.ticker_history are known
+// filled holds the days of each price series that were carried forward rather than observed, and
+// unpriced the tickers valued by a fallback series instead of market data, across all books
+pub fn build_report(
+    book: Book,
+    complete_prices: &HashMap<String, HashMap<NaiveDate, f64>>,
+    filled: &HashMap<String, BTreeSet<NaiveDate>>,
+    unpriced: &HashMap<String, Fallback>) -> Report {
+
+    let first_date = *book.time_range.first().unwrap();
+    let end_date = *book.time_range.last().unwrap();
+    // the report covers start_date to end_date. the history before only sets where the window opens from, i.e. the
+    // values on the base day before it, which every series is re-based to. no base day when it covers everything
+    let start_date = book.window_start.clamp(first_date, end_date);
+    let base_date = if start_date > first_date {Some(start_date - chrono::Duration::days(1))} else {None};
+    let in_window = |date: &NaiveDate| *date >= start_date;
+
+
+    // UNREALISED RETURNS ######################################
+    // portfolio_history is "sparse", so days where it wasn't changed are empty
+    // calculate_returns will just infer that empty day portfolio is same as last modified day's one
+    let (return_history, cb_mv_history) = stats::calc_unreal_returns(&book.portfolio_history, complete_prices, &book.dividend_history);
+
+    // shadowing
+    let return_history: Vec<(NaiveDate, f32)> = stats::hashmap_to_sorted_vec(return_history)
+    .into_iter()
+    .map(|(date, val)| (date, val as f32))  // convert to f32 for plotters module
+    .collect();
+
+    // as a level from 100, divided by the base day's (nothing held then, nothing to divide by)
+    let base_level = base_date.and_then(|base| value_on(&return_history, base))
+        .map(|r| 100.0 + r)
+        .filter(|level| level.is_finite() && *level > 0.0)
+        .unwrap_or(100.0);
+    let return_history: Vec<(NaiveDate, f32)> = return_history.into_iter()
+        .filter(|(date, _)| in_window(date))
+        .map(|(date, r)| (date, (100.0 + r) / base_level * 100.0 - 100.0))
+        .collect();
+    //##########################################################
+
+
+
+    // REALISED RETURNS #######################################
+    // summed exactly, and only made f32 for the plot
+    let realised_by_day: Vec<(NaiveDate, (Money, Money))> = stats::hashmap_to_sorted_vec(book.real_returns);
+    let zero = Money::zero(book.currency);
+    let mut real_returns: Vec<(NaiveDate, (Money, Money))> = realised_by_day.iter()
+    .scan((zero, zero), |state, (date, (a, b))| {  // like a fold, or cumsum over the (cost_basis, market val) tuple
+        state.0 += *a;
+        state.1 += *b;
+        Some((*date, *state))
+    })
+    .collect();
+
+    let temp = match real_returns.last() {
+        Some(v) => *v,
+        None => (first_date, (zero, zero))       // nothing sold yet, e.g. as of an early day
+    };
+
+    if temp.0 != end_date {                                            // stretch returns to today
+        real_returns.push((end_date, temp.1))
+    };
+    real_returns.insert(0, (first_date, (zero, zero)));                // stretch returns to root day
+
+    stats::interpolate(&mut real_returns);                             // stretch to correspond to # of days
+    let real_returns_abs: Vec<(NaiveDate, f32)> = real_returns.into_iter().map(|(date, (cb, mv))|(date, (mv - cb).to_f64() as f32)).collect();
+    let realised_before = base_date.and_then(|base| value_on(&real_returns_abs, base)).unwrap_or(0.0);
+    let real_returns_abs: Vec<(NaiveDate, f32)> = real_returns_abs.into_iter()
+        .filter(|(date, _)| in_window(date))
+        .map(|(date, pnl)| (date, pnl - realised_before))
+        .collect();
+    // ########################################################
+
+
+
+    // MONEY-WEIGHTED RETURNS #################################
+    let mut mwrr_returns = Vec::<(NaiveDate, f32)>::new();
+    let mut mwrr_annualised = Vec::<(NaiveDate, f32)>::new();
+    let mut mwrr_unsolved = Vec::<NaiveDate>::new();
+    let cb_mv_history = hashmap_to_btree(cb_mv_history);
+    let cash_flows = hashmap_to_btree(book.cash_flows);
+    let mut guess: f64 = 0.1;    // each day starts from the day before's rate, which is usually close
+
+    // the positions' value on the base day is what the window starts with, like a purchase of all of them
+    let base_mv: Option<(NaiveDate, f64)> = base_date.and_then(|base| cb_mv_history.get(&base).map(|(_, mv)| (base, *mv)));
+    let opening: Vec<(NaiveDate, f64)> = base_mv.map(|(base, mv)| (base, -mv)).into_iter().collect();
+
+    for (date, (_, mv)) in cb_mv_history.range(start_date..) {
+
+        // buys, sells and dividends up to and including date, and the positions' value at its close as a final sale.
+        // a sale on date is already out of mv, so it isn't counted twice
+        let mut cash_flows_plus_mv: Vec<(NaiveDate, f64)> = opening.clone();
+        cash_flows_plus_mv.extend(cash_flows.range(..=date)
+            .filter(|(k, _)| !base_date.is_some_and(|base| **k <= base))
+            .map(|(k, v)| (*k, v.to_f64())));
+        cash_flows_plus_mv.push((*date, *mv));
+
+        // a day without a rate is left out rather than given the one before, so a gap in the chart shows it
+        let irr = xirr(&cash_flows_plus_mv, guess);
+        match (irr.annual, irr.cumulative()) {
+            (Some(annual), Some(cumulative)) => {
+                mwrr_returns.push((*date, (cumulative * 100.0) as f32));
+                mwrr_annualised.push((*date, (annual * 100.0) as f32));
+                guess = annual;
+            },
+            _ if irr.convergence == Convergence::TooShort => {},    // the first day, nothing to measure yet
+            _ => mwrr_unsolved.push(*date)
+        }
+    }
+    // ########################################################
+
+
+
+    // SUMMARY STATISTICS #####################################
+    let naivetime_held = end_date - base_date.unwrap_or(start_date);
+    let days_held: f32 = naivetime_held.num_days() as f32;
+    let years_held: f32 = (&days_held)/365.0;
+
+    let just_returns: Vec<f32> = stats::strip_dates(return_history.clone());
+    let current_return = just_returns.last().copied().unwrap_or(0.0);    // nothing held in the whole window
+    // a window of a single day has no rate a year, nor a yield below
+    let annual_return = (days_held > 0.0).then(|| ((current_return/100.0 + 1.0).powf(1.0/years_held) - 1.0) * 100.0);
+    // volatility and Sharpe only over days something held actually traded, as carried-forward weekends and holidays
+    // would count as flat days. fallback series have no trading days of their own, so they are left out of this
+    let priced_ranges: HashMap<String, (NaiveDate, NaiveDate)> = book.ticker_history.iter()
+        .filter(|(ticker, _)| !unpriced.contains_key(*ticker))
+        .map(|(ticker, range)| (ticker.clone(), *range))
+        .collect();
+    let trading_days = stats::trading_days(&priced_ranges, complete_prices, filled);
+    let trading_returns: Vec<f32> = return_history.iter()
+        .filter(|(date, _)| trading_days.is_empty() || trading_days.contains(date))
+        .map(|(_, value)| *value)
+        .collect();
+    let daily_returns: Vec<f32> = stats::get_daily_returns(trading_returns);
+    let (mean, sd, sharpe) = stats::mean_sd_sharpe(&daily_returns);
+
+    // dividends and fees of the window only, which is every one of them without a base day
+    let dividends: Vec<Dividend> = book.dividends.iter().filter(|d| in_window(&d.date)).cloned().collect();
+    let orders: Vec<Order> = book.orders.iter().filter(|o| in_window(&o.date)).cloned().collect();
+    let (dividend_library, total_dividends, fees_and_taxes) = match base_date {
+        None => (book.dividend_library, book.total_dividends, book.fees_and_taxes),
+        Some(_) => {
+            let mut library: HashMap<String, Money> = HashMap::new();
+            for dividend in &dividends {
+                *library.entry(dividend.ticker.clone()).or_insert(Money::zero(book.currency)) += Money::new(dividend.amount, book.currency);
+            }
+            let mut fees: HashMap<String, Money> = HashMap::new();
+            for fee in orders.iter().flat_map(|o| &o.taxes) {
+                *fees.entry(fee.name.clone()).or_insert(Money::zero(book.currency)) += Money::new(fee.quantity, book.currency);
+            }
+            (library, Money::total(book.currency, dividends.iter().map(|d| Money::new(d.amount, book.currency))), fees)
+        }
+    };
+    let dividends_before = base_date.and_then(|base| value_on(&book.cum_dividends, base)).unwrap_or(0.0);
+    let cum_dividends: Vec<(NaiveDate, f32)> = book.cum_dividends.iter()
+        .filter(|(date, _)| in_window(date))
+        .map(|(date, total)| (*date, total - dividends_before))
+        .collect();
+
+    let cb_mv_history: BTreeMap<NaiveDate, (f64, f64)> = cb_mv_history.into_iter().filter(|(date, _)| in_window(date)).collect();
+    let dividend_yield: Option<f64> = (days_held > 0.0).then(|| {
+        let average_cost: f64 = cb_mv_history.values().map(|(cb, _)| *cb).sum::<f64>() / (days_held as f64);
+        if average_cost > 0.0 {total_dividends.to_f64() / average_cost / (years_held as f64) * 100.0} else {0.0}
+    });
+    // ########################################################
+
+
+
+    // CASH AND ACCOUNT VALUE #################################
+    let account_value: Vec<(NaiveDate, f32)> = cb_mv_history.iter()
+        .map(|(date, (_, mv))| (*date, (mv + book.cash_ledger.get(date).map_or(0.0, |cash| cash.to_f64())) as f32))
+        .collect();
+
+    let current_cash = book.cash_ledger.values().last().copied().unwrap_or(Money::zero(book.currency));
+
+    let mut allocation: HashMap<String, f64> = HashMap::new();
+    for (ticker, (q, _)) in &book.holdings {
+        if let Some(price) = complete_prices.get(ticker).and_then(|history| latest_price(history, end_date)) {
+            allocation.insert(ticker.clone(), q.to_f64()*price);
+        }
+    }
+    let current_positions: f64 = allocation.values().sum();
+    allocation.insert(String::from("cash"), current_cash.to_f64().max(0.0));
+
+    let cash_weights: Vec<f32> = account_value.iter()
+        .filter(|(_, value)| *value > 0.0)
+        .map(|(date, value)| (book.cash_ledger.get(date).map_or(0.0, |cash| cash.to_f64()) as f32 / value).max(0.0))
+        .collect();
+    let avg_cash_weight = cash_weights.iter().sum::<f32>() / (cash_weights.len().max(1) as f32) * 100.0;
+    // cash earns nothing here, so it gives up its weight's share of what the positions returned
+    let cash_drag = annual_return.map(|annual| avg_cash_weight / 100.0 * annual);
+
+    // account-level MWRR: deposits in, withdrawals out, and today's cash plus positions as the final inflow.
+    // a window opens with the account's value on its base day paid in
+    let opening_value = base_mv.map(|(base, mv)| (base, -(mv + book.cash_ledger.get(&base).map_or(0.0, |cash| cash.to_f64()))));
+    let mut account_flows: Vec<(NaiveDate, f64)> = opening_value.into_iter().collect();
+    account_flows.extend(book.external_flows.iter()
+        .filter(|(k, _)| !base_date.is_some_and(|base| **k <= base))
+        .map(|(k, v)| (*k, v.to_f64())));
+    account_flows.push((end_date, current_cash.to_f64() + current_positions));
+    let account_mwrr = xirr(&account_flows, 0.1);
+    // ########################################################
+
+
+
+    // CALENDAR PERIODS #######################################
+    // the window day by day, for the monthly, quarterly and yearly tables. money paid in before the first day,
+    // e.g. the first deposit ahead of the first order, counts on the first day, and anything dated on a day the
+    // window has no value for (a dividend paid on a Sunday) on the next day it has one
+    let mut days: BTreeMap<NaiveDate, Day> = cb_mv_history.iter()
+        .map(|(date, (_, mv))| (*date, Day { value: mv + book.cash_ledger.get(date).map_or(0.0, |cash| cash.to_f64()), ..Day::default() }))
+        .collect();
+    for (date, flow) in book.external_flows.iter().filter(|(date, _)| !base_date.is_some_and(|base| **date <= base)) {
+        if let Some(day) = days.range_mut(*date.max(&start_date)..).next().map(|(_, day)| day) {
+            day.contributions -= flow.to_f64();
+        }
+    }
+    for (date, (cb, mv)) in realised_by_day.iter().filter(|(date, _)| in_window(date)) {
+        if let Some(day) = days.range_mut(*date..).next().map(|(_, day)| day) {
+            day.realised += (*mv - *cb).to_f64();
+        }
+    }
+    for dividend in &dividends {
+        if let Some(day) = days.range_mut(dividend.date..).next().map(|(_, day)| day) {
+            day.dividends += Money::new(dividend.amount, book.currency).to_f64();
+        }
+    }
+    for order in &orders {
+        if let Some(day) = days.range_mut(order.date..).next().map(|(_, day)| day) {
+            day.fees += Money::total(book.currency, order.taxes.iter().map(|fee| Money::new(fee.quantity, book.currency))).to_f64();
+        }
+    }
+    let calendar = Calendar {
+        opening: opening_value.map(|(base, value)| (base, -value)),
+        days
+    };
+    // ########################################################
+
+    let mut book_unpriced: Vec<(String, Fallback)> = book.ticker_history.keys()
+        .filter_map(|ticker| unpriced.get(ticker).map(|fallback| (ticker.clone(), *fallback)))
+        .collect();
+    book_unpriced.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Report {
+        label: book.label,
+        environment: book.environment,
+        currency: book.currency,
+        start_date,
+        end_date,
+        years_held,
+        days_held,
+        return_history,
+        real_returns_abs,
+        mwrr_returns,
+        mwrr_annualised,
+        mwrr_unsolved,
+        cb_mv_history,
+        cum_dividends,
+        dividend_library,
+        total_dividends,
+        fees_and_taxes,
+        current_return,
+        annual_return,
+        mean,
+        sd,
+        sharpe,
+        dividend_yield,
+        account_value,
+        current_cash,
+        current_positions,
+        avg_cash_weight,
+        cash_drag,
+        account_mwrr,
+        calendar,
+        allocation,
+        holdings: book.holdings,
+        reconciliation: book.reconciliation,
+        broker_cash: book.broker_cash,
+        unpriced: book_unpriced,
+        ticker_history: book.ticker_history,
+        orders,
+        dividends
+    }
+}
+
+
+
+// the last value of a date-ascending series on or before date
+fn value_on<T: Copy>(series: &[(NaiveDate, T)], date: NaiveDate) -> Option<T> {
+    series.iter().take_while(|(d, _)| *d <= date).last().map(|(_, v)| *v)
+}
+
+
+
+// zero filledQuantity means it was a "value" order e.g. "buy £100 of AAPL" instead of "buy 0.5 AAPL at £200"
+// so we need to translate value into quantities. the fill price is in the instrument's quote unit, so a line
+// quoted in pence has it scaled to pounds first, while one quoted in pounds is taken as is
+pub fn fill_value_quantity(order: &mut Order, unit: &QuoteUnit) {
+
+    let price = order.fillPrice * Decimal::from_f64(unit.scale).unwrap_or(Decimal::ONE);
+    if order.filledQuantity.is_zero() && !price.is_zero() {
+        order.filledQuantity = Quantity(order.filledValue / price)
+    };
+}
+
+
+
+// T212 returns one record per fill, all sharing the order's id. fills are combined into one order with the summed
+// quantity and value at the quantity-weighted fill price, identical ones included, as records repeated by the
+// pagination are already gone (t212::drop_page_overlap). the combined order takes the place (and date) of
+// its last fill, since that is when the order completed. expects ascending orders with quantities filled in
+pub fn aggregate_fills(orders: Vec<Order>) -> Vec<Order> {
+
+    let mut last_index: HashMap<u64, usize> = HashMap::new();
+    for (i, order) in orders.iter().enumerate() {
+        last_index.insert(order.id, i);
+    }
+
+    let mut combined: HashMap<u64, Order> = HashMap::new();
+    let mut aggregated: Vec<Order> = Vec::new();
+
+    for (i, order) in orders.into_iter().enumerate() {
+        let id = order.id;
+
+        match combined.entry(id) {
+            Entry::Vacant(vacant) => {vacant.insert(order);},
+            Entry::Occupied(mut occupied) => {
+                let total = occupied.get_mut();
+
+                // records without a fill (e.g. the cancelled remainder) add nothing but may carry the final status
+                if !order.filledQuantity.is_zero() {
+                    let quantity = total.filledQuantity + order.filledQuantity;
+                    if !quantity.is_zero() {
+                        total.fillPrice = (total.filledQuantity*total.fillPrice + order.filledQuantity*order.fillPrice) / quantity.0;
+                    }
+                    total.filledQuantity = quantity;
+                    total.filledValue += order.filledValue;
+                    total.taxes.extend(order.taxes);
+                }
+                total.dateModified = order.dateModified;
+                total.date = order.date;
+                total.executed = order.executed;
+                if total.status != "FILLED" {
+                    total.status = order.status;
+                }
+            }
+        };
+
+        if last_index[&id] == i {
+            aggregated.push(combined.remove(&id).unwrap());
+        }
+    }
+    aggregated
+}
+
+
+
+// quantity and cost basis of every open position after all orders, i.e. the last portfolio_t.
+// prices are taken as they are on the orders, so raw T212 orders give figures comparable to the broker's
+pub fn final_holdings(orders: &Vec<Order>) -> HashMap<String, (Quantity, Decimal)> {
+
+    let mut portfolio_t: HashMap<String, (Quantity, Decimal)> = HashMap::new();
+    let mut ticker_history: HashMap<String, (NaiveDate, NaiveDate)> = HashMap::new();
+
+    for order in orders {
+        if order.status == "FILLED" {
+            process_order(&order, &mut portfolio_t, &mut ticker_history, order.date);
+        }
+    }
+    portfolio_t
+}
+
+
+
+// the average price of a position from its quantity and cost basis, zero for none
+pub fn average_price(quantity: Quantity, cost: Decimal) -> Decimal {
+    if quantity.is_zero() {Decimal::ZERO} else {cost / quantity.0}
+}
+
+
+
+// last known price on or before date; prices are weekend filled but today's close may not exist yet
+fn latest_price(history: &HashMap<NaiveDate, f64>, date: NaiveDate) -> Option<f64> {
+    history.iter()
+        .filter(|(d, _)| **d <= date)
+        .max_by_key(|(d, _)| **d)
+        .map(|(_, p)| *p)
+}
+
+
+
+// union of several ticker lifetimes, so each ticker's prices are fetched once for all accounts
+pub fn merge_ticker_histories(books: &Vec<Book>) -> HashMap<String, (NaiveDate, NaiveDate)> {
+    let mut merged: HashMap<String, (NaiveDate, NaiveDate)> = HashMap::new();
+
+    for book in books {
+        for (ticker, (date1, date2)) in &book.ticker_history {
+            merged.entry(ticker.clone())
+            .and_modify(|e| *e = (e.0.min(*date1), e.1.max(*date2)))
+            .or_insert((*date1, *date2));
+        }
+    }
+    merged
+}
+
+
+
+// amends portfolio_t and ticker_history in-place, and returns what a sale realised: the cost basis of the shares
+// sold and what they were sold for, both exact and in the order's currency. None for a buy.
+// positions keep their total cost basis rather than an average price, so selling everything takes out exactly what
+// was paid, and a partial sale its share of it
+fn process_order(
+    order: &Order,
+    portfolio_t: &mut HashMap<String, (Quantity, Decimal)>,
+    ticker_history: &mut HashMap<String, (NaiveDate, NaiveDate)>,
+    last_date: NaiveDate) -> Option<(Decimal, Decimal)> {
+
+    let q_1 = order.filledQuantity;
+    let value = q_1 * order.fillPrice;                                     // negative for sales
+    let date = order.date;
+    let ticker = order.ticker.clone();
+
+    // log the order's presence in portolios and ticker histories
+    match portfolio_t.entry(order.ticker.clone()) {
+        Entry::Occupied(mut occupied) => {
+
+            let (q_0, cost_0) = occupied.get_mut();
+
+            if (*q_0 + q_1).is_zero() {                                         // if sold everything, exactly
+
+                let keeps_date = ticker_history.get(&ticker).map_or(date, |(first, _)| *first);
+                ticker_history.insert(ticker, (keeps_date, date));
+
+                let realised = (*cost_0, -value);
+                occupied.remove();    // removes ticker from portfolio
+                Some(realised)
+
+            } else if !q_1.is_sell() {                                         // if bought some *more*
+                *cost_0 += value;
+                *q_0 += q_1;
+
+                ticker_history.entry(ticker.clone())
+                    .and_modify(|e| e.1 = last_date)
+                    .or_insert((date, last_date));
+                None
+
+            } else {                                                            // if sold some (not everything)
+                let cost = *cost_0 * -q_1.0 / q_0.0;
+                *cost_0 -= cost;
+                *q_0 += q_1;
+
+                ticker_history.entry(ticker.clone())
+                    .and_modify(|e| e.1 = last_date)
+                    .or_insert((date, last_date));
+
+                Some((cost, -value))
+            }
+        },
+        Entry::Vacant(vacant) => {                                              // if bought some
+            vacant.insert((q_1, value));
+
+            ticker_history.entry(ticker.clone())
+                .and_modify(|e| e.1 = last_date)
+                .or_insert((date, last_date));
+            None
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::test_util::{day, exact, order};
+    use crate::exchanges;
+    use crate::t212::{self, Items};
+    use rust_decimal::prelude::ToPrimitive;
+
+    // same preparation main does: ascending order, trading dates, value orders given quantities
+    fn load_fixture(json: &str) -> Vec<Order> {
+        let page: Items = serde_json::from_str(json).unwrap();
+        prepare(page.items)
+    }
+
+    fn prepare(mut orders: Vec<Order>) -> Vec<Order> {
+        orders.reverse();
+        for order in orders.iter_mut() {
+            order.date = exchanges::trading_date(&order.dateModified, exchanges::time_zone(&order.ticker)).unwrap();
+            fill_value_quantity(order, &QuoteUnit::from_code("USD"));
+        }
+        aggregate_fills(orders)
+    }
+
+    fn by_id(orders: &Vec<Order>, id: u64) -> &Order {
+        orders.iter().find(|o| o.id == id).unwrap()
+    }
+
+    #[test]
+    fn partial_fills_combine_at_weighted_price() {
+        let orders = load_fixture(include_str!("../tests/fixtures/partial_fills.json"));
+        assert_eq!(orders.len(), 3);
+
+        let aapl = by_id(&orders, 31000000003);
+        assert_eq!(aapl.filledQuantity, Quantity(exact("10")));
+        assert_eq!(aapl.filledValue, exact("1702"));
+        assert_eq!(aapl.fillPrice, exact("170.2"));
+        assert_eq!(aapl.taxes.len(), 2);
+        assert_eq!(aapl.date, day("2024-03-05"));
+        assert_eq!(aapl.dateModified, "2024-03-05T15:40:51.000+02:00");
+    }
+
+    #[test]
+    fn value_order_fills_combine_at_weighted_price() {
+        let orders = load_fixture(include_str!("../tests/fixtures/partial_fills.json"));
+
+        let sap = by_id(&orders, 31000000002);
+        let quantity = 400.0/180.0 + 200.0/179.0;
+        assert!((sap.filledQuantity.to_f64() - quantity).abs() < 1e-9);
+        assert!((sap.fillPrice.to_f64().unwrap() - 600.0/quantity).abs() < 1e-9);
+        assert_eq!(sap.filledValue, exact("600"));
+    }
+
+    #[test]
+    fn cancelled_remainder_keeps_filled_part() {
+        let orders = load_fixture(include_str!("../tests/fixtures/partial_fills.json"));
+
+        let msft = by_id(&orders, 31000000001);
+        assert_eq!(msft.status, "FILLED");
+        assert_eq!(msft.filledQuantity, Quantity(exact("2")));
+        assert_eq!(msft.fillPrice, exact("389.5"));
+
+        let holdings = final_holdings(&orders);
+        assert_eq!(holdings["MSFT_US_EQ"], (Quantity(exact("2")), exact("779")));
+        assert_eq!(holdings["AAPL_US_EQ"].0, Quantity(exact("10")));
+    }
+
+    #[test]
+    fn repeated_page_record_is_not_double_counted() {
+        // the fixture's first two records as one page, the repeat of the second starting the next
+        let page: Items = serde_json::from_str(include_str!("../tests/fixtures/partial_fills_page_repeat.json")).unwrap();
+        let mut fetched = page.items;
+        let next_page = fetched.split_off(2);
+        let mut next_page = t212::drop_page_overlap(&fetched, next_page);
+        fetched.append(&mut next_page);
+        let orders = prepare(fetched);
+        assert_eq!(orders.len(), 2);
+
+        // bought 1.5 and sold 1.5, so nothing is left open
+        let holdings = final_holdings(&orders);
+        assert!(holdings.is_empty());
+    }
+
+    #[test]
+    fn identical_fills_both_count() {
+        // two fills of one order, same size and price in the same second, on one page
+        let fill = |id: u64| Order { id, ..order("TSLA_US_EQ", "2024-05-09", "1.5", "168") };
+        let orders = prepare(t212::drop_page_overlap(&[], vec![fill(32000000001), fill(32000000001)]));
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].filledQuantity, Quantity(exact("3")));
+        assert_eq!(orders[0].filledValue, exact("504"));
+        assert_eq!(orders[0].fillPrice, exact("168"));
+
+        // and a page after them that starts with another order isn't cut
+        let previous = vec![fill(32000000001), fill(32000000001)];
+        let mut sell = fill(32000000002);
+        sell.filledQuantity = Quantity(exact("-3"));
+        assert_eq!(t212::drop_page_overlap(&previous, vec![sell]).len(), 1);
+    }
+
+    #[test]
+    fn unreadable_cursor_is_an_error() {
+        let cursor = t212::cursor_from(&String::from("2024-05-09T14:30:02.000+02:00"), "/api/v0/equity/history/orders");
+        assert_eq!(cursor.unwrap(), "1715257802000");
+        // rather than the end of the history, which would drop the pages before it
+        let cursor = t212::cursor_from(&String::from("09/05/2024 14:30"), "/api/v0/equity/history/orders");
+        assert!(matches!(cursor, Err(Error::Parse { .. })));
+    }
+
+    #[test]
+    fn value_orders_on_pence_and_pound_lines() {
+        let value_order = |ticker: &str, price: &str| Order { filledValue: exact("100"), ..order(ticker, "2024-03-18", "0", price) };
+
+        // £100 of Scottish Mortgage at 800p
+        let mut smt = value_order("SMTl_EQ", "800");
+        fill_value_quantity(&mut smt, &QuoteUnit::from_code("GBX"));
+        assert_eq!(smt.filledQuantity, Quantity(exact("12.5")));
+
+        // £100 of VUSA at £80
+        let mut vusa = value_order("VUSAl_EQ", "80");
+        fill_value_quantity(&mut vusa, &QuoteUnit::from_code("GBP"));
+        assert_eq!(vusa.filledQuantity, Quantity(exact("1.25")));
+    }
+
+    #[test]
+    fn fractional_sells_close_the_position() {
+        let fill = |date: &str, quantity: &str, price: &str| order("VUSA.L", date, quantity, price);
+        // 0.1 + 0.2 - 0.3 is 5.5e-17 in f64, which used to leave the position open forever
+        let orders = vec![
+            fill("2024-03-18", "0.1", "80"),
+            fill("2024-03-19", "0.2", "81"),
+            fill("2024-03-20", "-0.3", "82")
+        ];
+        assert!(final_holdings(&orders).is_empty());
+
+        let range: Vec<NaiveDate> = (18..=22).map(|d| NaiveDate::from_ymd_opt(2024, 3, d).unwrap()).collect();
+        let book = build_book("ISA", Environment::Live, Currency::from_str("GBP").unwrap(),
+            History { orders: &orders, dividends: &Vec::new(), transactions: &Vec::new(), actions: &Vec::new() }, range).unwrap();
+        assert!(book.holdings.is_empty());
+        assert_eq!(book.ticker_history["VUSA.L"].1, day("2024-03-20"));
+        // sold for 24.60 what was bought for 8.00 and 16.20, an average price of 80.666.. that no longer matters
+        assert_eq!(book.cash_ledger.values().last().unwrap().to_string(), "0.40 GBP");
+        let (cost, proceeds) = book.real_returns[&day("2024-03-20")];
+        assert_eq!(proceeds - cost, Money::new(exact("0.40"), book.currency));
+        let flows = Money::total(book.currency, book.cash_flows.values().copied());
+        assert_eq!(flows, Money::new(exact("0.40"), book.currency));
+    }
+}
@@ -0,0 +1,640 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{read_to_string, File};
+use std::io::{BufReader, ErrorKind};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Mutex, MutexGuard};
+use chrono::{Duration, NaiveDate, Utc};
+use futures::future::{FutureExt, LocalBoxFuture};
+use futures::stream::{self, StreamExt};
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::from_reader;
+use crate::cache::PriceCache;
+use crate::error::{Error, Result};
+use crate::overrides::{TickerOverride, TickerOverrides};
+use crate::plotter::print_progress;
+use crate::quotes::QuoteUnits;
+use crate::resolver::{Resolution, Resolver};
+use crate::stats;
+use crate::stooq::{self, Stooq};
+use crate::t212::Order;
+use crate::unpriced::{self, Fallback};
+use crate::yahoo::{self, Yahoo};
+
+
+const MAX_CONCURRENT: usize = 8;      // downloads in flight across all sources, each source also limits its own host
+
+
+
+// anything that can give daily closes for a (yahoo-style) symbol. an empty map means the source
+// doesn't know the symbol, an error means the source itself failed
+pub trait PriceProvider {
+    fn name(&self) -> &str;
+    fn daily_closes<'a>(&'a self, symbol: &'a str, start_date: NaiveDate, end_date: NaiveDate) -> LocalBoxFuture<'a, Result<HashMap<NaiveDate, f64>>>;
+
+    // remote sources go through the price cache, local ones are cheaper to read than to cache
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+
+
+// one connection pool for every price download instead of a new client per request
+pub fn client() -> &'static Client {
+    static CLIENT: std::sync::OnceLock<Client> = std::sync::OnceLock::new();
+    CLIENT.get_or_init(Client::new)
+}
+
+
+
+// price_sources.json, e.g. {"default": ["yahoo", "stooq"], "csv_dir": "prices", "cache": "price_cache.json", "tickers": {"VUAA.MI": ["csv", "yahoo"]}}.
+// yahoo_url and stooq_url point the downloads somewhere else, e.g. at a local stand-in for testing
+#[derive(Debug, Deserialize)]
+pub struct SourceConfig {
+    #[serde(default = "default_chain")]
+    pub default: Vec<String>,
+
+    #[serde(default = "default_csv_dir")]
+    pub csv_dir: String,
+
+    #[serde(default = "default_cache")]
+    pub cache: String,
+
+    #[serde(default)]
+    pub tickers: HashMap<String, Vec<String>>,
+
+    #[serde(default = "default_yahoo_url")]
+    pub yahoo_url: String,
+
+    #[serde(default = "default_stooq_url")]
+    pub stooq_url: String
+}
+
+fn default_chain() -> Vec<String> {
+    vec![String::from("yahoo"), String::from("stooq"), String::from("csv")]
+}
+
+fn default_csv_dir() -> String {
+    String::from("prices")
+}
+
+fn default_cache() -> String {
+    String::from("price_cache.json")
+}
+
+fn default_yahoo_url() -> String {
+    yahoo::BASE_URL.to_string()
+}
+
+fn default_stooq_url() -> String {
+    stooq::BASE_URL.to_string()
+}
+
+
+
+// tries providers in order until one has data, so a run still finishes when one of them is down
+pub struct PriceChain {
+    pub yahoo_url: String,                    // for listings and symbol search, which aren't price sources
+    providers: Vec<Box<dyn PriceProvider>>,
+    default: Vec<String>,
+    tickers: HashMap<String, Vec<String>>,
+    cache: Option<Mutex<PriceCache>>
+}
+
+impl PriceChain {
+
+    pub fn new(providers: Vec<Box<dyn PriceProvider>>, default: Vec<String>, tickers: HashMap<String, Vec<String>>) -> PriceChain {
+        PriceChain { yahoo_url: yahoo::BASE_URL.to_string(), providers, default, tickers, cache: None }
+    }
+
+    pub fn with_cache(mut self, cache: PriceCache) -> PriceChain {
+        self.cache = Some(Mutex::new(cache));
+        self
+    }
+
+    pub fn cache(&self) -> Option<MutexGuard<'_, PriceCache>> {
+        self.cache.as_ref().map(|cache| cache.lock().unwrap())
+    }
+
+    pub fn save_cache(&self) {
+        if let Some(cache) = self.cache() {
+            if let Err(e) = cache.save() {
+                eprintln!("could not write {}: {}", cache.path, e);
+            }
+        }
+    }
+
+    // reads price_sources.json if present, otherwise yahoo, then stooq, then the local csv directory. a file that is
+    // there but can't be read is an error, rather than a reason to quietly use another chain
+    pub fn from_config(path: &str) -> Result<PriceChain> {
+
+        let config: SourceConfig = match File::open(path) {
+            Ok(file) => from_reader(BufReader::new(file)).map_err(|e| Error::parse(path, e))?,
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(Error::io(path, e)),
+            Err(_) => SourceConfig {
+                default: default_chain(),
+                csv_dir: default_csv_dir(),
+                cache: default_cache(),
+                tickers: HashMap::new(),
+                yahoo_url: default_yahoo_url(),
+                stooq_url: default_stooq_url()
+            }
+        };
+
+        let providers: Vec<Box<dyn PriceProvider>> = vec![
+            Box::new(Yahoo::new(false, &config.yahoo_url)),
+            Box::new(Yahoo::new(true, &config.yahoo_url)),
+            Box::new(Stooq::new(&config.stooq_url)),
+            Box::new(CsvDir { dir: PathBuf::from(&config.csv_dir) })
+        ];
+
+        for name in config.default.iter().chain(config.tickers.values().flatten()) {
+            if !providers.iter().any(|p| p.name() == name) {
+                return Err(Error::parse(path, format!("unknown price source {}, use yahoo, yahoo-adjusted, stooq or csv", name)))
+            }
+        }
+
+        let mut chain = PriceChain::new(providers, config.default, config.tickers).with_cache(PriceCache::load(&config.cache));
+        chain.yahoo_url = config.yahoo_url.trim_end_matches('/').to_string();
+        Ok(chain)
+    }
+
+    pub async fn daily_closes(&self, symbol: &str, start_date: NaiveDate, end_date: NaiveDate) -> Result<HashMap<NaiveDate, f64>> {
+
+        let chain = self.tickers.get(symbol).unwrap_or(&self.default);
+        let mut failures: Vec<String> = Vec::new();
+
+        for name in chain {
+            let provider = match self.providers.iter().find(|p| p.name() == name) {
+                Some(v) => v,
+                None => continue
+            };
+            match self.provider_closes(provider.as_ref(), symbol, start_date, end_date).await {
+                Ok(prices) if !prices.is_empty() => return Ok(prices),
+                Ok(_) => {},
+                Err(e) => failures.push(format!("{}: {}", name, e))
+            }
+        }
+
+        // every source answered but none knows the symbol: that's for the user to fix, not an error
+        if failures.len() == chain.len() && !chain.is_empty() {
+            return Err(Error::MissingPrice { symbol: symbol.to_string(), message: failures.join("; ") })
+        }
+        Ok(HashMap::new())
+    }
+
+    // closes from one named source, whether or not it is part of the symbol's chain, e.g. a manual csv series
+    // or yahoo-adjusted for total returns. an unknown source has no data
+    pub async fn source_closes(&self, source: &str, symbol: &str, start_date: NaiveDate, end_date: NaiveDate) -> Result<HashMap<NaiveDate, f64>> {
+        match self.providers.iter().find(|p| p.name() == source) {
+            Some(provider) => self.provider_closes(provider.as_ref(), symbol, start_date, end_date).await,
+            None => Ok(HashMap::new())
+        }
+    }
+
+    async fn provider_closes(&self, provider: &dyn PriceProvider, symbol: &str, start_date: NaiveDate, end_date: NaiveDate) -> Result<HashMap<NaiveDate, f64>> {
+        match (&self.cache, provider.cacheable()) {
+            (Some(_), true) => self.cached_closes(provider, symbol, start_date, end_date).await,
+            _ => provider.daily_closes(symbol, start_date, end_date).await
+        }
+    }
+
+    // downloads only what the cache can't answer for; if that fails, whatever is cached is still better than nothing
+    async fn cached_closes(&self, provider: &dyn PriceProvider, symbol: &str, start_date: NaiveDate, end_date: NaiveDate) -> Result<HashMap<NaiveDate, f64>> {
+
+        let today = Utc::now().date_naive();
+        let missing = match self.cache().unwrap().get(provider.name(), symbol) {
+            Some(entry) => entry.missing(start_date, end_date),
+            None => vec![(start_date, end_date)]
+        };
+
+        let mut failure: Option<Error> = None;
+        for (from, to) in missing {
+            match provider.daily_closes(symbol, from, to).await {
+                Ok(prices) => self.cache().unwrap().store(provider.name(), symbol, from, to, prices, today),
+                Err(e) => failure = Some(e)
+            }
+        }
+
+        let prices = self.cache().unwrap().closes(provider.name(), symbol, start_date, end_date, today);
+        match failure {
+            Some(e) if prices.is_empty() => Err(e),
+            _ => Ok(prices)
+        }
+    }
+
+    // downloads every cached entry (or only symbol's) again over the range it covers. an entry is only
+    // replaced once its download worked, returns the symbols that failed
+    pub async fn rebuild_cache(&self, symbol: Option<&str>) -> Vec<String> {
+
+        let ranges = match self.cache() {
+            Some(cache) => cache.ranges(symbol),
+            None => return Vec::new()
+        };
+        let total = ranges.len();
+        let mut done = 0;
+        let mut failed: Vec<String> = Vec::new();
+        let today = Utc::now().date_naive();
+
+        let mut downloads = stream::iter(ranges)
+            .map(|(name, symbol, from, to)| async move {
+                let prices = match self.providers.iter().find(|p| p.name() == name) {
+                    Some(provider) => provider.daily_closes(&symbol, from, to).await,
+                    None => Err(Error::parse("price_sources.json", format!("unknown price source {}", name)))
+                };
+                (name, symbol, from, to, prices)
+            })
+            .buffer_unordered(MAX_CONCURRENT);
+
+        print_progress("cache", done, total);
+        while let Some((name, symbol, from, to, prices)) = downloads.next().await {
+            done += 1;
+            print_progress("cache", done, total);
+            match prices {
+                Ok(prices) if !prices.is_empty() => {
+                    let mut cache = self.cache().unwrap();
+                    cache.remove(&name, &symbol);
+                    cache.store(&name, &symbol, from, to, prices, today);
+                },
+                _ => failed.push(format!("{} ({})", symbol, name))
+            }
+        }
+        failed
+    }
+}
+
+
+
+// one <dir>/<SYMBOL>.csv per symbol with at least Date (YYYY-MM-DD) and Close columns, e.g. a Yahoo or Stooq download
+pub struct CsvDir {
+    pub dir: PathBuf
+}
+
+impl PriceProvider for CsvDir {
+
+    fn name(&self) -> &str {
+        "csv"
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+
+    fn daily_closes<'a>(&'a self, symbol: &'a str, start_date: NaiveDate, end_date: NaiveDate) -> LocalBoxFuture<'a, Result<HashMap<NaiveDate, f64>>> {
+        async move {
+            let path = self.dir.join(format!("{}.csv", symbol));
+            if !path.exists() {
+                return Ok(HashMap::new())
+            }
+            let text = read_to_string(&path).map_err(|e| Error::io(path.display().to_string(), e))?;
+            let prices = parse_csv(&text).map_err(|e| Error::parse(path.display().to_string(), e))?;
+
+            Ok(prices.into_iter().filter(|(date, _)| *date >= start_date && *date <= end_date).collect())
+        }.boxed_local()
+    }
+}
+
+
+
+// Date and Close columns are found by name, so column order and extra columns don't matter
+pub fn parse_csv(text: &str) -> Result<HashMap<NaiveDate, f64>, String> {
+
+    let mut lines = text.lines();
+    let header: Vec<String> = lines.next().ok_or("empty price file")?
+        .split(',')
+        .map(|h| h.trim().to_ascii_lowercase())
+        .collect();
+
+    let date_col = header.iter().position(|h| h == "date").ok_or("price file has no Date column")?;
+    let close_col = header.iter().position(|h| h == "close").ok_or("price file has no Close column")?;
+
+    let mut prices: HashMap<NaiveDate, f64> = HashMap::new();
+    for line in lines {
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        if let (Some(date), Some(close)) = (fields.get(date_col), fields.get(close_col)) {
+            // blank or "null" closes are days without trading
+            if let (Ok(date), Ok(close)) = (NaiveDate::from_str(date), close.parse::<f64>()) {
+                prices.insert(date, close);
+            }
+        }
+    }
+    Ok(prices)
+}
+
+
+
+// downloads every (symbol, start, end) through the chain with at most MAX_CONCURRENT requests in flight.
+// results are keyed by symbol and come back in whatever order they finish; what is printed as "what" in the progress line.
+// requests for the same symbol are downloaded once, over all of their days, so each caller takes its own days (see within)
+pub async fn fetch_all(requests: Vec<(String, NaiveDate, NaiveDate)>, chain: &PriceChain, what: &str) -> HashMap<String, Result<HashMap<NaiveDate, f64>>> {
+    fetch_from(requests, chain, None, what).await
+}
+
+// the same from one named source only, or through each symbol's chain when source is None
+pub async fn fetch_from(requests: Vec<(String, NaiveDate, NaiveDate)>, chain: &PriceChain, source: Option<&str>, what: &str) -> HashMap<String, Result<HashMap<NaiveDate, f64>>> {
+
+    let mut ranges: HashMap<String, (NaiveDate, NaiveDate)> = HashMap::new();
+    for (symbol, start_date, end_date) in requests {
+        ranges.entry(symbol)
+            .and_modify(|(start, end)| {*start = (*start).min(start_date); *end = (*end).max(end_date);})
+            .or_insert((start_date, end_date));
+    }
+    let total = ranges.len();
+    let mut results = HashMap::new();
+
+    let mut downloads = stream::iter(ranges)
+        .map(|(symbol, (start_date, end_date))| async move {
+            let prices = match source {
+                Some(name) => chain.source_closes(name, &symbol, start_date, end_date).await,
+                None => chain.daily_closes(&symbol, start_date, end_date).await
+            };
+            (symbol, prices)
+        })
+        .buffer_unordered(MAX_CONCURRENT);
+
+    print_progress(what, 0, total);
+    while let Some((symbol, prices)) = downloads.next().await {
+        results.insert(symbol, prices);
+        print_progress(what, results.len(), total);
+    }
+    results
+}
+
+
+
+// the closes of a fetch_all result from start_date to end_date
+pub fn within(closes: &HashMap<NaiveDate, f64>, start_date: NaiveDate, end_date: NaiveDate) -> HashMap<NaiveDate, f64> {
+    closes.iter().filter(|(date, _)| **date >= start_date && **date <= end_date).map(|(date, price)| (*date, *price)).collect()
+}
+
+
+
+// takes what fetch_all found for ticker (under its override's symbol, if it has one); if that was nothing, asks the
+// resolver for another symbol until a source has prices for it, or it is skipped. picks are saved as overrides of the
+// ticker right away. runs after the downloads so questions don't interleave with them
+pub async fn get_prices(ticker: &str, start_date: NaiveDate, end_date: NaiveDate, fetched: HashMap<NaiveDate, f64>, overrides: &mut TickerOverrides, chain: &PriceChain, resolver: &mut Resolver) -> Result<HashMap<NaiveDate, f64>> {
+
+    let mut symbol = overrides.target(ticker);
+    let mut tried: Vec<String> = Vec::new();
+    let mut price_range = fetched;
+
+    loop {
+        if !price_range.is_empty() {
+            return Ok(price_range)
+        }
+        if symbol.eq_ignore_ascii_case("ignore") {
+            return Ok(HashMap::new())
+        }
+        tried.push(symbol.clone());
+
+        symbol = match resolver.resolve(&symbol).await? {
+            Resolution::Ticker(v) => v,
+            Resolution::Ignore => return Ok(HashMap::new()),
+            Resolution::Unresolved(candidates) => {
+                resolver.give_up(ticker, candidates);
+                return Ok(HashMap::new())
+            }
+        };
+        // automatic picks going round in circles won't find anything new
+        if tried.contains(&symbol) && !resolver.interactive {
+            resolver.give_up(ticker, Vec::new());
+            return Ok(HashMap::new())
+        }
+        overrides.set(ticker, TickerOverride::new(&symbol))?;
+        price_range = chain.daily_closes(&symbol, start_date, end_date).await?;
+    }
+}
+
+
+
+// the rates from base to every quote currency a run may meet, T212's usual ones and those of instrument listings and
+// ticker overrides, keyed like "GBPUSD" and carried over weekends and holidays. a rate no source has is an error,
+// nothing quoted in that currency could be valued
+pub async fn fx_rates(base: &str, units: &QuoteUnits, start_date: NaiveDate, end_date: NaiveDate, overrides: &mut TickerOverrides, chain: &PriceChain, resolver: &mut Resolver) -> Result<HashMap<String, HashMap<NaiveDate, f64>>> {
+
+    let mut quotes: Vec<String> = ["USD", "EUR", "CAD", "GBP"].iter().map(|quote| quote.to_string()).collect();
+    for quote in overrides.currencies().into_iter().chain(units.currencies()) {
+        if !quotes.contains(&quote) {
+            quotes.push(quote);
+        }
+    }
+    let pairs: Vec<String> = quotes.iter().filter(|quote| *quote != base).map(|quote| format!("{}{}", base, quote)).collect();
+
+    // from a couple of days early, so a first day on a weekend has Friday's rate
+    let start_date = start_date - Duration::days(2);
+    let requests = pairs.iter().map(|pair| (overrides.target(&format!("{}=X", pair)), start_date, end_date)).collect();
+    let mut fetched = fetch_all(requests, chain, "fx rates").await;
+
+    let mut fx_history: HashMap<String, HashMap<NaiveDate, f64>> = HashMap::new();
+    for pair in pairs {
+        let symbol = format!("{}=X", pair);
+        let found = fetched.remove(&overrides.target(&symbol)).unwrap_or(Ok(HashMap::new()))?;
+        let rates = get_prices(&symbol, start_date, end_date, found, overrides, chain, resolver).await?;
+        fx_history.insert(pair, rates);
+    }
+    stats::forward_fill_all(&mut fx_history, end_date);
+    Ok(fx_history)
+}
+
+
+
+// closes of every ticker held, in account currency with weekends and holidays filled in, the days that were filled
+// (see stats::forward_fill_all) and the tickers valued without market data, with how
+pub struct MarketData {
+    pub prices: HashMap<String, HashMap<NaiveDate, f64>>,
+    pub filled: HashMap<String, BTreeSet<NaiveDate>>,
+    pub unpriced: HashMap<String, Fallback>
+}
+
+// downloads closes for each ticker over the days it was held (see portfolio::merge_ticker_histories), asking the
+// resolver about tickers no source knows. a ticker still without prices is valued by its fallback: a manual csv,
+// zero, or the last trade among orders, which must already be in account currency
+pub async fn market_data(held: &HashMap<String, (NaiveDate, NaiveDate)>, orders: &Vec<Order>, base: &str, units: &QuoteUnits, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>, end_date: NaiveDate, overrides: &mut TickerOverrides, chain: &PriceChain, resolver: &mut Resolver) -> Result<MarketData> {
+
+    // fetched under each ticker's override, IGNOREd tickers aren't fetched at all
+    let requests = held.iter()
+        .filter(|(ticker, _)| !overrides.get(ticker).is_some_and(|o| o.is_ignore()))
+        .map(|(ticker, (date1, date2))| (overrides.target(ticker), *date1, *date2))
+        .collect();
+    let fetched = fetch_all(requests, chain, "prices").await;
+
+    let mut prices: HashMap<String, HashMap<NaiveDate, f64>> = HashMap::new();
+    let mut unpriced: HashMap<String, Fallback> = HashMap::new();
+    for (ticker, (date1, date2)) in held {
+        let (date1, date2) = (*date1, *date2);
+
+        // several tickers can share an override's symbol, so results are cloned rather than taken.
+        // sources that failed outright aren't worth asking about a replacement, the ticker goes straight to its fallback
+        let mut closes = match fetched.get(&overrides.target(ticker)) {
+            Some(Err(e)) => {
+                println!("    could not fetch prices for {}: {}", ticker, e);
+                HashMap::new()
+            },
+            found => {
+                let found = found.and_then(|res| res.as_ref().ok()).map(|closes| within(closes, date1, date2)).unwrap_or_default();
+                match get_prices(ticker, date1, date2, found, overrides, chain, resolver).await {
+                    Ok(res) => res,
+                    Err(e) => {
+                        println!("    could not fetch prices for {}: {}", ticker, e);
+                        HashMap::new()
+                    }
+                }
+            }
+        };
+
+        // a manual series is in the ticker's own currency like market data, so it goes through the same fx adjustment
+        let fallback = overrides.fallback(ticker);
+        let mut manual = false;
+        if closes.is_empty() && fallback == Fallback::Manual {
+            closes = chain.source_closes("csv", ticker, date1, date2).await.unwrap_or_default();
+            manual = !closes.is_empty();
+        }
+        units.price_unit(ticker, overrides).closes_to_account(base, &mut closes, fx_history)?;
+
+        // no market data: valued from its own fills (already in account currency), or written off
+        if closes.is_empty() {
+            let (series, used) = match fallback {
+                Fallback::Zero => (unpriced::zero_series(date1, date2), Fallback::Zero),
+                _ => (unpriced::last_trade_series(orders, ticker, date1, date2), Fallback::LastTrade)
+            };
+            println!("    no prices for {}, valued by {}", ticker, used.describe());
+            closes = series;
+            unpriced.insert(ticker.clone(), used);
+        } else if manual {
+            println!("    no prices for {}, valued by {}", ticker, Fallback::Manual.describe());
+            unpriced.insert(ticker.clone(), Fallback::Manual);
+        }
+        prices.insert(ticker.clone(), closes);
+    }
+    let filled = stats::forward_fill_all(&mut prices, end_date);
+    Ok(MarketData { prices, filled, unpriced })
+}
+
+
+
+// yahoo's adjusted closes (dividends reinvested) of every ticker held, in account currency. a ticker without them,
+// or without the rate to convert them, is left out
+pub async fn total_return_closes(held: &HashMap<String, (NaiveDate, NaiveDate)>, base: &str, units: &QuoteUnits, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>, overrides: &TickerOverrides, chain: &PriceChain) -> HashMap<String, HashMap<NaiveDate, f64>> {
+    let requests = held.iter()
+        .filter(|(ticker, _)| !overrides.get(ticker).is_some_and(|o| o.is_ignore()))
+        .map(|(ticker, (date1, date2))| (overrides.target(ticker), *date1, *date2))
+        .collect();
+    let fetched = fetch_from(requests, chain, Some("yahoo-adjusted"), "total return").await;
+
+    let mut series: HashMap<String, HashMap<NaiveDate, f64>> = HashMap::new();
+    for (ticker, (date1, date2)) in held {
+        if let Some(Ok(closes)) = fetched.get(&overrides.target(ticker)) {
+            let mut closes = within(closes, *date1, *date2);
+            match units.price_unit(ticker, overrides).closes_to_account(base, &mut closes, fx_history) {
+                Ok(()) => {series.insert(ticker.clone(), closes);},
+                Err(e) => println!("  total return of {} left out: {}", ticker, e)
+            }
+        }
+    }
+    series
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+
+    struct Down;
+
+    impl PriceProvider for Down {
+        fn name(&self) -> &str {
+            "down"
+        }
+        fn daily_closes<'a>(&'a self, _: &'a str, _: NaiveDate, _: NaiveDate) -> LocalBoxFuture<'a, Result<HashMap<NaiveDate, f64>>> {
+            async { Err(Error::Api { endpoint: String::from("down"), message: String::from("503 Service Unavailable") }) }.boxed_local()
+        }
+    }
+
+    fn fixtures() -> CsvDir {
+        CsvDir { dir: PathBuf::from(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/prices")) }
+    }
+
+    #[tokio::test]
+    async fn csv_provider_reads_closes_in_range() {
+        let prices = fixtures().daily_closes("AAPL", day("2024-01-03"), day("2024-01-05")).await.unwrap();
+        assert_eq!(prices.len(), 3);
+        assert_eq!(prices[&day("2024-01-03")], 184.25);
+        assert!(!prices.contains_key(&day("2024-01-02")));
+    }
+
+    #[tokio::test]
+    async fn chain_falls_back_when_a_provider_is_down() {
+        let chain = PriceChain::new(
+            vec![Box::new(Down), Box::new(fixtures())],
+            vec![String::from("down"), String::from("csv")],
+            HashMap::new());
+
+        let prices = chain.daily_closes("AAPL", day("2024-01-02"), day("2024-01-08")).await.unwrap();
+        assert_eq!(prices.len(), 5);
+    }
+
+    #[tokio::test]
+    async fn chain_fails_only_when_every_provider_fails() {
+        let chain = PriceChain::new(vec![Box::new(Down)], vec![String::from("down")], HashMap::new());
+        assert!(chain.daily_closes("AAPL", day("2024-01-02"), day("2024-01-08")).await.is_err());
+
+        // unknown to the csv source is not a failure, just no data
+        let chain = PriceChain::new(vec![Box::new(fixtures())], vec![String::from("csv")], HashMap::new());
+        assert!(chain.daily_closes("MSFT", day("2024-01-02"), day("2024-01-08")).await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn unreadable_config_is_an_error() {
+        let file = std::env::temp_dir().join(format!("t212_price_sources_{}", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let path = file.join("price_sources.json");
+
+        assert!(matches!(PriceChain::from_config(path.to_str().unwrap()), Err(Error::Io { .. })));
+        std::fs::remove_file(file).unwrap();
+    }
+
+    #[tokio::test]
+    async fn fetch_all_keys_results_by_symbol() {
+        let chain = PriceChain::new(vec![Box::new(fixtures())], vec![String::from("csv")], HashMap::new());
+        let requests = vec![
+            (String::from("AAPL"), day("2024-01-02"), day("2024-01-08")),
+            (String::from("MSFT"), day("2024-01-02"), day("2024-01-08"))
+        ];
+
+        let results = fetch_all(requests, &chain, "prices").await;
+        assert_eq!(results.len(), 2);
+        assert_eq!(results["AAPL"].as_ref().unwrap().len(), 5);
+        assert!(results["MSFT"].as_ref().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn fetch_all_downloads_a_shared_symbol_over_every_range() {
+        let chain = PriceChain::new(vec![Box::new(fixtures())], vec![String::from("csv")], HashMap::new());
+        let requests = vec![
+            (String::from("AAPL"), day("2024-01-05"), day("2024-01-08")),
+            (String::from("AAPL"), day("2024-01-02"), day("2024-01-03"))
+        ];
+
+        let results = fetch_all(requests, &chain, "prices").await;
+        let closes = results["AAPL"].as_ref().unwrap();
+        assert_eq!(closes.len(), 5);
+        assert_eq!(within(closes, day("2024-01-02"), day("2024-01-03")).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn market_data_fills_the_weekend() {
+        let chain = PriceChain::new(vec![Box::new(fixtures())], vec![String::from("csv")], HashMap::new());
+        let held = HashMap::from([(String::from("AAPL"), (day("2024-01-03"), day("2024-01-08")))]);
+        let mut overrides = TickerOverrides::default();
+        let mut resolver = Resolver::new(false);
+
+        let market = market_data(&held, &Vec::new(), "USD", &QuoteUnits::default(), &HashMap::new(), day("2024-01-08"), &mut overrides, &chain, &mut resolver).await.unwrap();
+        assert_eq!(market.prices["AAPL"][&day("2024-01-06")], 181.18);
+        assert_eq!(market.filled["AAPL"], BTreeSet::from([day("2024-01-06"), day("2024-01-07")]));
+        assert!(market.unpriced.is_empty());
+    }
+}
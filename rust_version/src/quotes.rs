@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use rust_decimal::prelude::FromPrimitive;
+use crate::error::Result;
+use crate::overrides::TickerOverrides;
+use crate::stats;
+use crate::t212::Instrument;
+use crate::yahoo;
+
+
+
+// what a quoted price is in: a currency, and how much of it one quoted unit is. LSE lines are quoted either in
+// pence (GBX, yahoo's "GBp") or in pounds, dollars or euros, so the exchange alone can't say whether to divide by 100
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuoteUnit {
+    pub currency: String,
+    pub scale: f64
+}
+
+impl QuoteUnit {
+
+    pub fn new(currency: &str, scale: f64) -> QuoteUnit {
+        QuoteUnit { currency: currency.to_string(), scale }
+    }
+
+    // a currency code as T212 or yahoo report it; minor units are turned into their major currency
+    pub fn from_code(code: &str) -> QuoteUnit {
+        match code {
+            "GBX" | "GBp" => QuoteUnit::new("GBP", 0.01),
+            "ZAC" | "ZAc" => QuoteUnit::new("ZAR", 0.01),
+            "ILA" => QuoteUnit::new("ILS", 0.01),
+            _ => QuoteUnit::new(&code.to_uppercase(), 1.0)
+        }
+    }
+
+    // the old guess from the yahoo suffix, only for instruments neither T212 nor yahoo describe. LSE is taken to be pence
+    pub fn from_suffix(ticker: &str) -> QuoteUnit {
+        let euro_borsen = vec![".AS", ".DE", ".MC", ".PA", ".SW", ".MI", ".LS", ".AT", ".BE"];
+
+        if ticker.contains(".TO") {
+            QuoteUnit::new("CAD", 1.0)
+        } else if euro_borsen.iter().any(|&b| ticker.contains(b)) {
+            QuoteUnit::new("EUR", 1.0)
+        } else if ticker.contains(".L") {
+            QuoteUnit::new("GBP", 0.01)
+        } else {
+            QuoteUnit::new("USD", 1.0)
+        }
+    }
+
+    // price in this unit to base currency, with the fx rate of matcher_date
+    pub fn to_account(&self, base: &str, matcher_date: NaiveDate, price: &mut f64, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>) -> Result<()> {
+        *price *= self.scale;
+        stats::fx_convert(&self.currency, base, matcher_date, price, fx_history)
+    }
+
+    // a whole series of closes, each day with its own rate
+    pub fn closes_to_account(&self, base: &str, closes: &mut HashMap<NaiveDate, f64>, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>) -> Result<()> {
+        for (date, price) in closes.iter_mut() {
+            self.to_account(base, *date, price, fx_history)?;
+        }
+        Ok(())
+    }
+
+    // the same for an exact fill or average price. pence to pounds stays exact, only an fx rate rounds it
+    pub fn exact_to_account(&self, base: &str, matcher_date: NaiveDate, price: &mut Decimal, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>) -> Result<()> {
+        *price *= Decimal::from_f64(self.scale).unwrap_or(Decimal::ONE);
+        if self.currency != base {
+            *price /= Decimal::from_f64(stats::fx_rate(&self.currency, base, matcher_date, fx_history)?).unwrap_or(Decimal::ONE);
+        }
+        Ok(())
+    }
+}
+
+
+
+// quote units of everything traded: fills and broker positions are in the unit of T212's instrument,
+// closes in that of the listing they are downloaded from
+#[derive(Debug, Clone, Default)]
+pub struct QuoteUnits {
+    instruments: HashMap<String, QuoteUnit>,       // by T212 ticker, from the instrument list
+    listings: HashMap<String, QuoteUnit>           // by yahoo symbol, from yahoo's chart meta or else the instrument
+}
+
+impl QuoteUnits {
+
+    pub fn add_instruments(&mut self, instruments: &Vec<Instrument>) {
+        for instrument in instruments {
+            if let Some(code) = instrument.currencyCode.as_ref().filter(|code| !code.is_empty()) {
+                self.instruments.insert(instrument.ticker.clone(), QuoteUnit::from_code(code));
+            }
+        }
+    }
+
+    pub fn set_listing(&mut self, symbol: &str, unit: QuoteUnit) {
+        self.listings.insert(symbol.to_string(), unit);
+    }
+
+    // t212_ticker as it comes from the API, e.g. VUSAl_EQ. one without a yahoo symbol is guessed to be in dollars
+    pub fn fill_unit(&self, t212_ticker: &str) -> QuoteUnit {
+        match self.instruments.get(t212_ticker) {
+            Some(unit) => unit.clone(),
+            None => QuoteUnit::from_suffix(&yahoo::convert_to_yahoo_ticker(t212_ticker.to_string()).unwrap_or_default())
+        }
+    }
+
+    // the unit closes of ticker come in, after its override: an override naming a currency says it all,
+    // otherwise its scale goes on top of whatever the listing is quoted in
+    pub fn price_unit(&self, ticker: &str, overrides: &TickerOverrides) -> QuoteUnit {
+        if let Some(currency) = overrides.get(ticker).and_then(|o| o.currency.as_ref().map(|c| QuoteUnit::new(c, o.scale))) {
+            return currency
+        }
+        let mut unit = match self.listings.get(&overrides.target(ticker)) {
+            Some(unit) => unit.clone(),
+            None => QuoteUnit::from_suffix(ticker)
+        };
+        unit.scale *= overrides.get(ticker).map_or(1.0, |o| o.scale);
+        unit
+    }
+
+    // every currency prices or fills may be in, for the fx download
+    pub fn currencies(&self) -> Vec<String> {
+        let mut currencies: Vec<String> = self.listings.values().map(|unit| unit.currency.clone()).collect();
+        currencies.sort();
+        currencies.dedup();
+        currencies
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overrides::TickerOverride;
+    use std::str::FromStr;
+
+    fn instruments() -> Vec<Instrument> {
+        serde_json::from_str(include_str!("../tests/fixtures/t212_instruments.json")).unwrap()
+    }
+
+    #[test]
+    fn pence_and_pound_lines_on_the_lse() {
+        let mut units = QuoteUnits::default();
+        units.add_instruments(&instruments());
+
+        assert_eq!(units.fill_unit("SMTl_EQ"), QuoteUnit::new("GBP", 0.01));     // Scottish Mortgage, in pence
+        assert_eq!(units.fill_unit("VUSAl_EQ"), QuoteUnit::new("GBP", 1.0));     // Vanguard S&P 500, in pounds
+        assert_eq!(units.fill_unit("IGLNl_EQ"), QuoteUnit::new("USD", 1.0));     // physical gold, in dollars
+        // not in the list: guessed from the exchange as before
+        assert_eq!(units.fill_unit("BARCl_EQ"), QuoteUnit::new("GBP", 0.01));
+    }
+
+    #[test]
+    fn listings_win_over_the_suffix_and_overrides_win_over_listings() {
+        let mut units = QuoteUnits::default();
+        let mut overrides = TickerOverrides::default();
+        units.set_listing("VUSA.L", QuoteUnit::from_code("GBP"));
+        units.set_listing("SMT.L", QuoteUnit::from_code("GBp"));
+
+        assert_eq!(units.price_unit("VUSA.L", &overrides), QuoteUnit::new("GBP", 1.0));
+        assert_eq!(units.price_unit("SMT.L", &overrides), QuoteUnit::new("GBP", 0.01));
+
+        overrides.tickers.insert(String::from("SMT.L"), TickerOverride { symbol: String::from("SMT.L"), currency: Some(String::from("GBP")), scale: 1.0, fallback: None });
+        assert_eq!(units.price_unit("SMT.L", &overrides), QuoteUnit::new("GBP", 1.0));
+
+        overrides.tickers.insert(String::from("VUSA.L"), TickerOverride { symbol: String::from("VUSA.L"), currency: None, scale: 0.5, fallback: None });
+        assert_eq!(units.price_unit("VUSA.L", &overrides), QuoteUnit::new("GBP", 0.5));
+    }
+
+    #[test]
+    fn pence_are_converted_once() {
+        let day = NaiveDate::from_str("2024-03-18").unwrap();
+        let fx_history = HashMap::from([(String::from("EURGBP"), HashMap::from([(day, 0.85)]))]);
+
+        let mut pence = 6280.0;
+        QuoteUnit::from_code("GBp").to_account("EUR", day, &mut pence, &fx_history).unwrap();
+        assert!((pence - 62.80/0.85).abs() < 1e-9);
+
+        let mut pounds = 62.80;
+        QuoteUnit::from_code("GBP").to_account("EUR", day, &mut pounds, &fx_history).unwrap();
+        assert!((pounds - pence).abs() < 1e-9);
+
+        let mut same = 62.80;
+        QuoteUnit::from_code("GBX").to_account("GBP", day, &mut same, &fx_history).unwrap();
+        assert!((same - 0.628).abs() < 1e-9);
+
+        // a fill in pence is exactly its price in pounds
+        let mut fill = Decimal::from_str("6280.5").unwrap();
+        QuoteUnit::from_code("GBp").exact_to_account("GBP", day, &mut fill, &fx_history).unwrap();
+        assert_eq!(fill, Decimal::from_str("62.805").unwrap());
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use reqwest::header::HeaderMap;
+
+
+
+// T212's documented limits per account, as (requests, period) for each endpoint. anything not listed gets a
+// conservative one a second; the rate-limit headers of every response correct these as we go
+pub fn endpoint_limit(path: &str) -> (u32, Duration) {
+    match path {
+        "/api/v0/equity/history/orders" => (6, Duration::from_secs(60)),
+        "/api/v0/history/dividends" => (6, Duration::from_secs(60)),
+        "/api/v0/history/transactions" => (6, Duration::from_secs(60)),
+        "/api/v0/equity/portfolio" => (1, Duration::from_secs(5)),
+        "/api/v0/equity/account/cash" => (1, Duration::from_secs(2)),
+        "/api/v0/equity/metadata/instruments" => (1, Duration::from_secs(50)),
+        _ => (1, Duration::from_secs(1))
+    }
+}
+
+
+
+// a full bucket allows a burst of capacity requests, after that one more every period/capacity
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    per_second: f64,
+    updated: Instant,
+    blocked_until: Option<Instant>        // the server said nothing is left until then
+}
+
+impl TokenBucket {
+
+    pub fn new(limit: u32, period: Duration, now: Instant) -> TokenBucket {
+        let capacity = limit.max(1) as f64;
+        TokenBucket { capacity, tokens: capacity, per_second: capacity / period.as_secs_f64(), updated: now, blocked_until: None }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed*self.per_second).min(self.capacity);
+        self.updated = now;
+    }
+
+    // takes a token if there is one; otherwise how long until there is, without taking anything
+    pub fn take(&mut self, now: Instant) -> Option<Duration> {
+        if let Some(until) = self.blocked_until {
+            if until > now {
+                return Some(until - now)
+            }
+            self.blocked_until = None;
+            self.tokens = self.capacity;            // a new period has started
+            self.updated = now;
+        }
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None
+        }
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.per_second))
+    }
+
+    // what the server counted: never more tokens than requests it still allows, none at all until reset_in has passed
+    pub fn sync(&mut self, remaining: u32, reset_in: Option<Duration>, now: Instant) {
+        self.refill(now);
+        self.tokens = self.tokens.min(remaining as f64);
+        if remaining == 0 {
+            self.blocked_until = reset_in.map(|wait| now + wait);
+        }
+    }
+}
+
+
+
+// x-ratelimit-remaining and x-ratelimit-reset (a unix timestamp), as T212 sends them with every response
+pub fn remaining(headers: &HeaderMap) -> Option<u32> {
+    headers.get("x-ratelimit-remaining")?.to_str().ok()?.trim().parse().ok()
+}
+
+pub fn reset_in(headers: &HeaderMap, now_unix: u64) -> Option<Duration> {
+    let reset: u64 = headers.get("x-ratelimit-reset")?.to_str().ok()?.trim().parse().ok()?;
+    Some(Duration::from_secs(reset.saturating_sub(now_unix)))
+}
+
+// how long a 429 asks us to wait: until the reset if T212 says when that is, else Retry-After seconds
+pub fn retry_after(headers: &HeaderMap, now_unix: u64) -> Option<Duration> {
+    reset_in(headers, now_unix)
+        .filter(|wait| !wait.is_zero())
+        .or_else(|| headers.get("retry-after")?.to_str().ok()?.trim().parse().ok().map(Duration::from_secs))
+}
+
+pub fn now_unix() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |t| t.as_secs())
+}
+
+
+
+// one bucket per account and endpoint, shared by everything in the process
+static BUCKETS: Mutex<Option<HashMap<(String, String), TokenBucket>>> = Mutex::new(None);
+
+// waits until key (the account's API key) may call path again, and takes its turn
+pub async fn acquire(key: &str, path: &str) {
+    loop {
+        let wait = {
+            let mut buckets = BUCKETS.lock().unwrap();
+            let now = Instant::now();
+            buckets.get_or_insert_with(HashMap::new)
+                .entry((key.to_string(), path.to_string()))
+                .or_insert_with(|| {
+                    let (limit, period) = endpoint_limit(path);
+                    TokenBucket::new(limit, period, now)
+                })
+                .take(now)
+        };
+        match wait {
+            None => return,
+            Some(wait) => pause(wait).await
+        }
+    }
+}
+
+// the rate-limit headers of a response from path, so the next acquire knows what the server knows
+pub fn observe(key: &str, path: &str, headers: &HeaderMap) {
+    if let Some(remaining) = remaining(headers) {
+        let mut buckets = BUCKETS.lock().unwrap();
+        let now = Instant::now();
+        if let Some(bucket) = buckets.get_or_insert_with(HashMap::new).get_mut(&(key.to_string(), path.to_string())) {
+            bucket.sync(remaining, reset_in(headers, now_unix()), now);
+        }
+    }
+}
+
+
+
+// how often and how patiently a failed request is tried again. 429s wait as long as T212 asks,
+// server errors and dropped connections back off exponentially from base_delay
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration
+}
+
+impl RetryPolicy {
+
+    pub const DEFAULT: RetryPolicy = RetryPolicy { max_retries: 5, base_delay: Duration::from_secs(1), max_delay: Duration::from_secs(60) };
+
+    // base_delay for the first retry (attempt 0), doubled with every one after up to max_delay, the upper half of it random
+    // so several accounts fetched at once don't retry in step. jitter is in [0, 1)
+    pub fn backoff(&self, attempt: u32, jitter: f64) -> Duration {
+        let full = self.base_delay.saturating_mul(2u32.saturating_pow(attempt)).min(self.max_delay);
+        full.mul_f64(0.5 + 0.5*jitter.clamp(0.0, 1.0))
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::DEFAULT
+    }
+}
+
+static POLICY: Mutex<RetryPolicy> = Mutex::new(RetryPolicy::DEFAULT);
+
+pub fn retry_policy() -> RetryPolicy {
+    *POLICY.lock().unwrap()
+}
+
+// e.g. from --max-retries; applies to every T212 request from then on
+pub fn set_retry_policy(policy: RetryPolicy) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+
+
+// sleeps without blocking the runtime. long waits count down on the console so a paused run doesn't look hung
+pub async fn pause(wait: Duration) {
+    if wait < Duration::from_secs(5) {
+        tokio::time::sleep(wait).await;
+        return
+    }
+    let mut seconds = wait.as_secs_f64().ceil() as u64;
+    while seconds > 0 {
+        print!("\rwaiting for T212, next request in {:2}s", seconds);
+        std::io::Write::flush(&mut std::io::stdout()).unwrap_or(());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        seconds -= 1;
+    }
+    print!("\r{}\r", " ".repeat(40));
+    std::io::Write::flush(&mut std::io::stdout()).unwrap_or(());
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn buckets_allow_a_burst_then_space_requests_out() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(6, Duration::from_secs(60), start);
+        for _ in 0..6 {
+            assert_eq!(bucket.take(start), None);
+        }
+        let wait = bucket.take(start).unwrap();
+        assert!((wait.as_secs_f64() - 10.0).abs() < 1e-6);
+
+        assert_eq!(bucket.take(start + Duration::from_secs(10)), None);
+        assert!(bucket.take(start + Duration::from_secs(10)).is_some());
+    }
+
+    #[test]
+    fn the_server_has_the_last_word() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(6, Duration::from_secs(60), start);
+
+        // another process used the allowance up: nothing until the reset, then a full bucket
+        bucket.sync(0, Some(Duration::from_secs(30)), start);
+        assert_eq!(bucket.take(start + Duration::from_secs(12)), Some(Duration::from_secs(18)));
+        for _ in 0..6 {
+            assert_eq!(bucket.take(start + Duration::from_secs(30)), None);
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-remaining", HeaderValue::from_static("0"));
+        headers.insert("x-ratelimit-reset", HeaderValue::from_static("1700000042"));
+        assert_eq!(remaining(&headers), Some(0));
+        assert_eq!(retry_after(&headers, 1_700_000_000), Some(Duration::from_secs(42)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("retry-after", HeaderValue::from_static("7"));
+        assert_eq!(retry_after(&headers, 1_700_000_000), Some(Duration::from_secs(7)));
+        assert_eq!(retry_after(&HeaderMap::new(), 1_700_000_000), None);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_with_jitter() {
+        let policy = RetryPolicy::DEFAULT;
+        assert_eq!(policy.backoff(0, 1.0), Duration::from_secs(1));
+        assert_eq!(policy.backoff(3, 1.0), Duration::from_secs(8));
+        assert_eq!(policy.backoff(3, 0.0), Duration::from_secs(4));
+        assert_eq!(policy.backoff(20, 1.0), Duration::from_secs(60));
+        assert!(policy.backoff(2, 0.5) > Duration::from_secs(2) && policy.backoff(2, 0.5) < Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use crate::error::Result;
+use crate::money::Quantity;
+use crate::portfolio::{average_price, Book};
+use crate::quotes::QuoteUnits;
+use crate::t212::Position;
+use crate::yahoo;
+
+
+const QUANTITY_TOLERANCE: Quantity = Quantity(Decimal::from_parts(1, 0, 0, false, 6));      // 0.000001, the broker may round fractional shares, anything above this is real drift
+const PRICE_TOLERANCE: Decimal = Decimal::from_parts(5, 0, 0, false, 3);        // relative, i.e. 0.5% of the broker's average price
+
+
+
+#[derive(Debug, Clone)]
+pub enum Mismatch {
+    MissingLocally { ticker: String, broker_quantity: Quantity },              // broker holds it, order history doesn't
+    MissingAtBroker { ticker: String, quantity: Quantity },                    // order history holds it, broker doesn't
+    QuantityDrift { ticker: String, quantity: Quantity, broker_quantity: Quantity },
+    PriceDrift { ticker: String, average_price: Decimal, broker_average_price: Decimal }
+}
+
+impl Mismatch {
+    pub fn ticker(&self) -> &str {
+        match self {
+            Mismatch::MissingLocally { ticker, .. } => ticker,
+            Mismatch::MissingAtBroker { ticker, .. } => ticker,
+            Mismatch::QuantityDrift { ticker, .. } => ticker,
+            Mismatch::PriceDrift { ticker, .. } => ticker
+        }
+    }
+}
+
+
+
+// holdings must come from portfolio::final_holdings on raw T212 orders, so tickers and
+// average prices are in the same (instrument) terms as the broker's positions
+pub fn reconcile(holdings: &HashMap<String, (Quantity, Decimal)>, positions: &Vec<Position>) -> Vec<Mismatch> {
+
+    let mut mismatches: Vec<Mismatch> = Vec::new();
+
+    for position in positions {
+        match holdings.get(&position.ticker) {
+            None => mismatches.push(Mismatch::MissingLocally {
+                ticker: position.ticker.clone(),
+                broker_quantity: position.quantity
+            }),
+            Some((q, cost)) => {
+                if (*q - position.quantity).abs() > QUANTITY_TOLERANCE {
+                    mismatches.push(Mismatch::QuantityDrift {
+                        ticker: position.ticker.clone(),
+                        quantity: *q,
+                        broker_quantity: position.quantity
+                    });
+                }
+                let p = average_price(*q, *cost);
+                if position.averagePrice > Decimal::ZERO && (p / position.averagePrice - Decimal::ONE).abs() > PRICE_TOLERANCE {
+                    mismatches.push(Mismatch::PriceDrift {
+                        ticker: position.ticker.clone(),
+                        average_price: p,
+                        broker_average_price: position.averagePrice
+                    });
+                }
+            }
+        }
+    }
+
+    for (ticker, (q, _)) in holdings {
+        // dust the broker rounded away is not a real holding
+        if q.abs() > QUANTITY_TOLERANCE && !positions.iter().any(|position| &position.ticker == ticker) {
+            mismatches.push(Mismatch::MissingAtBroker { ticker: ticker.clone(), quantity: *q });
+        }
+    }
+
+    mismatches.sort_by(|a, b| a.ticker().cmp(b.ticker()));
+    mismatches
+}
+
+
+
+pub fn print_reconciliation(title: &str, mismatches: &Vec<Mismatch>) {
+    println!("\n  Reconciliation against Trading 212 open positions ({})", title);
+    println!("  ______________________________________________________________");
+
+    if mismatches.is_empty() {
+        println!("  all computed positions match the broker");
+        println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+        return
+    }
+
+    println!("  {0: <16} {1: <16} {2: >14} {3: >14}", "ticker", "issue", "computed", "broker");
+    println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+    for mismatch in mismatches {
+        match mismatch {
+            Mismatch::MissingLocally { ticker, broker_quantity } =>
+                println!("  {0: <16} {1: <16} {2: >14} {3: >14.6}", ticker, "missing locally", "-", broker_quantity),
+            Mismatch::MissingAtBroker { ticker, quantity } =>
+                println!("  {0: <16} {1: <16} {2: >14.6} {3: >14}", ticker, "not at broker", quantity, "-"),
+            Mismatch::QuantityDrift { ticker, quantity, broker_quantity } => {
+                println!("  {0: <16} {1: <16} {2: >14.6} {3: >14.6}", ticker, "quantity drift", quantity, broker_quantity);
+                if let Some(hint) = split_hint(quantity.to_f64(), broker_quantity.to_f64()) {
+                    println!("  {0: <16} {1}", "", hint);
+                }
+            },
+            Mismatch::PriceDrift { ticker, average_price, broker_average_price } =>
+                println!("  {0: <16} {1: <16} {2: >14.4} {3: >14.4}", ticker, "avg. price drift", average_price, broker_average_price)
+        }
+    }
+    println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+}
+
+
+
+// a quantity off by a clean ratio is most likely a split yahoo doesn't know about
+fn split_hint(quantity: f64, broker_quantity: f64) -> Option<String> {
+    if quantity <= 0.0 || broker_quantity <= 0.0 {
+        return None
+    }
+    let ratio = broker_quantity / quantity;
+    let whole = if ratio >= 1.0 {ratio.round()} else {(1.0/ratio).round()};
+    let clean = if ratio >= 1.0 {(ratio - whole).abs() < 1e-3} else {(1.0/ratio - whole).abs() < 1e-3};
+
+    match (clean && whole >= 2.0, ratio >= 1.0) {
+        (true, true) => Some(format!("looks like a {}:1 split, add it to corporate_actions.json", whole)),
+        (true, false) => Some(format!("looks like a 1:{} reverse split, add it to corporate_actions.json", whole)),
+        _ => None
+    }
+}
+
+
+
+// replaces today's snapshot in the book with the broker's positions.
+// raw_holdings are in instrument terms (T212 tickers), holdings in account terms (yahoo tickers, fx adjusted);
+// where both exist the broker's average price is rescaled by our own instrument-to-account ratio,
+// so historic fx rates are kept, otherwise today's fx rate is used on the broker's price in the instrument's quote unit.
+// a position that can't be mapped to a yahoo symbol or converted is an error, today's holdings stay as computed then
+pub fn trust_broker(
+    book: &mut Book,
+    positions: &Vec<Position>,
+    raw_holdings: &HashMap<String, (Quantity, Decimal)>,
+    holdings: &HashMap<String, (Quantity, Decimal)>,
+    base: &str,
+    fx_history: &HashMap<String, HashMap<NaiveDate, f64>>,
+    units: &QuoteUnits) -> Result<()> {
+
+    let today = *book.time_range.last().unwrap();
+    let mut snapshot: HashMap<String, (Quantity, Decimal)> = HashMap::new();
+
+    for position in positions {
+        let ticker = yahoo::convert_to_yahoo_ticker(position.ticker.clone())?;
+
+        let price = match (holdings.get(&ticker), raw_holdings.get(&position.ticker)) {
+            (Some((q, cost)), Some((q_raw, cost_raw))) if average_price(*q_raw, *cost_raw) > Decimal::ZERO =>
+                average_price(*q, *cost) * position.averagePrice / average_price(*q_raw, *cost_raw),
+            _ => {
+                let mut p = position.averagePrice;
+                units.fill_unit(&position.ticker).exact_to_account(base, today, &mut p, fx_history)?;
+                p
+            }
+        };
+
+        snapshot.insert(ticker.clone(), (position.quantity, position.quantity * price));
+        book.ticker_history.entry(ticker)
+            .and_modify(|e| e.1 = today)
+            .or_insert((today, today));
+    }
+
+    // an empty snapshot means "unchanged" to calc_unreal_returns, so a broker with no positions can't be expressed
+    if snapshot.is_empty() {
+        println!("  broker reports no open positions for {}, keeping computed holdings", book.label);
+        return Ok(())
+    }
+
+    book.holdings = snapshot.clone();
+    *book.portfolio_history.last_mut().unwrap() = (today, snapshot);
+    Ok(())
+}
+
+
+
+// today's snapshot of a consolidated book, rebuilt from each member account's current holdings
+pub fn trust_members(book: &mut Book, member_holdings: &Vec<HashMap<String, (Quantity, Decimal)>>) {
+
+    let today = *book.time_range.last().unwrap();
+    let mut snapshot: HashMap<String, (Quantity, Decimal)> = HashMap::new();
+
+    for holdings in member_holdings {
+        for (ticker, (q_1, cost_1)) in holdings {
+            snapshot.entry(ticker.clone())
+                .and_modify(|(q_0, cost_0)| {
+                    *cost_0 += *cost_1;
+                    *q_0 += *q_1;
+                })
+                .or_insert((*q_1, *cost_1));
+            book.ticker_history.entry(ticker.clone())
+                .and_modify(|e| e.1 = today)
+                .or_insert((today, today));
+        }
+    }
+
+    if !snapshot.is_empty() {
+        book.holdings = snapshot.clone();
+        *book.portfolio_history.last_mut().unwrap() = (today, snapshot);
+    }
+}
@@ -0,0 +1,221 @@
+use std::io;
+use textwrap::wrap;
+use crate::plotter::clear_last_n_lines;
+use crate::yahoo;
+use crate::error::{Error, Result};
+
+
+
+const MAX_CANDIDATES: usize = 8;       // length of the pick list
+const AUTO_MIN_SCORE: i32 = 4;         // an automatic pick must at least have the same base ticker
+
+
+
+// one hit of yahoo's symbol search
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub symbol: String,
+    pub name: String,
+    pub exchange: String,
+    pub quote_type: String,
+    pub score: i32
+}
+
+pub enum Resolution {
+    Ticker(String),
+    Ignore,           // the user chose to skip it
+    Unresolved(Vec<Candidate>)        // no confident match and no user to ask, with what the search found
+}
+
+
+
+// finds a replacement for symbols no price source knows. with a terminal it offers a numbered pick list, without one
+// (or with --non-interactive) it takes the best candidate only when the choice is clear, and remembers what it couldn't
+pub struct Resolver {
+    pub interactive: bool,
+    pub yahoo_url: String,               // where the symbol search is, yahoo::BASE_URL unless price_sources.json says otherwise
+    unresolved: Vec<(String, Vec<Candidate>)>
+}
+
+impl Resolver {
+
+    pub fn new(interactive: bool) -> Resolver {
+        Resolver { interactive, yahoo_url: yahoo::BASE_URL.to_string(), unresolved: Vec::new() }
+    }
+
+    pub async fn resolve(&mut self, symbol: &str) -> Result<Resolution> {
+
+        // a failed search only means no suggestions, the user can still type a ticker
+        let candidates = match yahoo::search(&self.yahoo_url, base_ticker(symbol)).await {
+            Ok(v) => rank(symbol, v),
+            Err(_) => Vec::new()
+        };
+
+        if self.interactive {
+            return ask(symbol, &candidates)
+        }
+
+        Ok(match auto_pick(&candidates) {
+            Some(candidate) => {
+                println!("    resolved {} to {} ({}, {})", symbol, candidate.symbol, candidate.name, candidate.exchange);
+                Resolution::Ticker(candidate.symbol.clone())
+            },
+            None => Resolution::Unresolved(candidates)
+        })
+    }
+
+    // keyed by the ticker as it came from the orders, not by whatever replacement was tried last
+    pub fn give_up(&mut self, symbol: &str, candidates: Vec<Candidate>) {
+        if !self.unresolved.iter().any(|(s, _)| s == symbol) {
+            self.unresolved.push((symbol.to_string(), candidates));
+        }
+    }
+
+    pub fn unresolved(&self) -> &Vec<(String, Vec<Candidate>)> {
+        &self.unresolved
+    }
+
+    pub fn print_unresolved(&self) {
+        println!("\n  No prices found for these tickers:");
+        println!("  ______________________________________________________________");
+        for (symbol, candidates) in &self.unresolved {
+            let suggestions: Vec<&str> = candidates.iter().take(3).map(|c| c.symbol.as_str()).collect();
+            if suggestions.is_empty() {
+                println!("  {0: <16} no suggestions", symbol);
+            } else {
+                println!("  {0: <16} maybe {1}", symbol, suggestions.join(", "));
+            }
+        }
+        println!("  ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾");
+        println!("  map them in custom_tickers.json, e.g. {{\"VUAA.MI\": \"VUAA.DE\"}}, or to \"IGNORE\" to skip them");
+    }
+}
+
+
+
+// exchange and currency are read from the yahoo suffix, since search results don't reliably carry a currency
+fn suffix(symbol: &str) -> &str {
+    symbol.rsplit_once('.').map_or("", |(_, s)| s)
+}
+
+fn base_ticker(symbol: &str) -> &str {
+    symbol.rsplit_once('.').map_or(symbol, |(b, _)| b)
+}
+
+fn suffix_currency(suffix: &str) -> &str {
+    match suffix {
+        "" => "USD",
+        "L" => "GBP",
+        "SW" => "CHF",
+        "TO" => "CAD",
+        "AS" | "DE" | "F" | "MC" | "PA" | "MI" | "LS" | "VI" | "BR" => "EUR",
+        _ => ""
+    }
+}
+
+
+
+// same base ticker first, then same exchange, then same currency. derivatives and the like are dropped,
+// ties are broken by symbol so the order never depends on how yahoo sorted them
+pub fn rank(symbol: &str, candidates: Vec<Candidate>) -> Vec<Candidate> {
+
+    let mut ranked: Vec<Candidate> = candidates.into_iter()
+        .filter(|c| matches!(c.quote_type.as_str(), "EQUITY" | "ETF" | "MUTUALFUND" | "CURRENCY"))
+        .filter(|c| c.symbol != symbol)
+        .map(|mut c| {
+            c.score = 0;
+            if base_ticker(&c.symbol).eq_ignore_ascii_case(base_ticker(symbol)) {
+                c.score += 4;
+            }
+            if suffix(&c.symbol) == suffix(symbol) {
+                c.score += 2;
+            }
+            if suffix_currency(suffix(&c.symbol)) == suffix_currency(suffix(symbol)) {
+                c.score += 1;
+            }
+            c
+        })
+        .collect();
+
+    ranked.sort_by(|a, b| b.score.cmp(&a.score).then(a.symbol.cmp(&b.symbol)));
+    ranked.truncate(MAX_CANDIDATES);
+    ranked
+}
+
+
+
+// the top candidate, if it shares the base ticker and nothing else scores as well
+pub fn auto_pick(ranked: &Vec<Candidate>) -> Option<&Candidate> {
+    let best = ranked.first()?;
+    let clear = ranked.get(1).map_or(true, |second| second.score < best.score);
+
+    if best.score >= AUTO_MIN_SCORE && clear {Some(best)} else {None}
+}
+
+
+
+fn ask(symbol: &str, candidates: &Vec<Candidate>) -> Result<Resolution> {
+
+    println!("   __________________________________________");
+    let message = format!("Ticker {} from Trading 212 could not be found. Pick a number, type the correct ticker on Yahoo Finance, or type IGNORE to skip it once", symbol);
+    for line in wrap(&message, 40){
+        println!("   {}", line)
+    }
+    for (i, candidate) in candidates.iter().enumerate() {
+        println!("   {0: >2}) {1: <12} {2: <6} {3}", i + 1, candidate.symbol, candidate.exchange, candidate.name.chars().take(20).collect::<String>());
+    }
+    println!("   ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾ \n \n");
+
+    let mut input = String::new();
+    io::stdin().read_line(&mut input).map_err(|e| Error::io("stdin", e))?;
+    clear_last_n_lines(1);
+    let command = input.trim();
+
+    if command.is_empty() || command.eq_ignore_ascii_case("ignore") {
+        return Ok(Resolution::Ignore)
+    }
+    Ok(match command.parse::<usize>().ok().and_then(|n| candidates.get(n.wrapping_sub(1))) {
+        Some(candidate) => Resolution::Ticker(candidate.symbol.clone()),
+        None => Resolution::Ticker(command.to_string())
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn candidates() -> Vec<Candidate> {
+        let json: Value = serde_json::from_str(include_str!("../tests/fixtures/yahoo_search_vuaa.json")).unwrap();
+        yahoo::parse_search(&json)
+    }
+
+    #[test]
+    fn prefers_same_base_and_currency() {
+        let ranked = rank("VUAA.MI", candidates());
+        let symbols: Vec<&str> = ranked.iter().map(|c| c.symbol.as_str()).collect();
+
+        assert_eq!(symbols, vec!["VUAA.DE", "VUAA.L", "VUSA.AS"]);
+        assert_eq!(auto_pick(&ranked).unwrap().symbol, "VUAA.DE");
+    }
+
+    #[test]
+    fn ties_are_left_to_the_user() {
+        // two listings both in EUR score the same, so no automatic pick
+        let mut both = candidates();
+        both.push(Candidate { symbol: String::from("VUAA.F"), name: String::new(), exchange: String::from("FRA"), quote_type: String::from("ETF"), score: 0 });
+
+        let ranked = rank("VUAA.MI", both);
+        assert_eq!(ranked[0].symbol, "VUAA.DE");
+        assert_eq!(ranked[1].symbol, "VUAA.F");
+        assert!(auto_pick(&ranked).is_none());
+    }
+
+    #[test]
+    fn unrelated_tickers_are_never_picked() {
+        let ranked = rank("XYZ.MI", candidates());
+        assert!(auto_pick(&ranked).is_none());
+    }
+}
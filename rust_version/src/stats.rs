@@ -1,7 +1,11 @@
-use chrono::{Days, NaiveDate};
-use std::{collections::{HashMap, BTreeMap}, f32::INFINITY};
+use chrono::{Duration, NaiveDate};
+use std::{collections::{HashMap, BTreeMap, BTreeSet}, f32::INFINITY, fmt};
 
+use crate::error::{Error, Result};
 use crate::t212::Dividend;
+use crate::money::{Money, Quantity};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
 
 
 const RISK_FREE_RATE: f32 = 0.03;
@@ -9,13 +13,15 @@ const N_MARKET_DAYS: f32 = 252.0;
 
 
 
-// unrealized, non-TWR, non-MWR
+// unrealized, non-TWR, non-MWR. a day without cost basis, i.e. nothing held or nothing of it priced yet, has no
+// return to speak of and is left out of the returns rather than given a NaN or infinity; it keeps its (0, 0) cost
+// basis and market value
 pub fn calc_unreal_returns(
-    portfolio_history: &Vec<(NaiveDate, HashMap<String, (f64, f64)>)>,
-    complete_prices: HashMap<String, HashMap<NaiveDate, f64>>,
-    dividend_history: BTreeMap<NaiveDate, f64>
+    portfolio_history: &Vec<(NaiveDate, HashMap<String, (Quantity, Decimal)>)>,
+    complete_prices: &HashMap<String, HashMap<NaiveDate, f64>>,
+    dividend_history: &BTreeMap<NaiveDate, Money>
 
-    ) -> Option<(HashMap<NaiveDate, f64>, HashMap<NaiveDate, (f64, f64)>)> {
+    ) -> (HashMap<NaiveDate, f64>, HashMap<NaiveDate, (f64, f64)>) {
 
     let mut return_history: HashMap<NaiveDate, f64> = HashMap::new();
     let mut portfolio = portfolio_history[0].1.clone();
@@ -47,20 +53,25 @@ pub fn calc_unreal_returns(
 
 
         let mut market_val: f64 = 0.0;
-        let mut cost_basis: f64 = 0.0;
+        let mut cost_basis = Decimal::ZERO;
 
-        for (ticker, (q_0, p_0)) in &portfolio {
+        for (ticker, (q_0, cost)) in &portfolio {
 
-            let single_history = complete_prices.get(ticker)?; // get price history for ticker
+            // get price history for ticker; unpriced tickers get a fallback series in main, so this is only a safety net
+            let single_history = match complete_prices.get(ticker) {
+                Some(v) => v,
+                None => continue
+            };
 
 
             if let Some(v) = single_history.get(&date) {      // get specific day from that price history
                 let p_1: f64 = *v;
+                let q_0: f64 = q_0.to_f64();
 
                 market_val += p_1*q_0;
-                cost_basis += q_0*p_0;
+                cost_basis += *cost;                           // exact, only the market side is a float
                 
-                let abs_return: f64 = p_0*q_0*(p_1/p_0 - 1.0);
+                let abs_return: f64 = p_1*q_0 - cost.to_f64().unwrap_or(0.0);
                 sum_of_abs_returns += abs_return;
 
             } else {
@@ -68,15 +79,20 @@ pub fn calc_unreal_returns(
                 };
         };
 
-        let total_dividends: f64 = dividend_history.range(..=date).map(|(_, v)| v).sum();
+        let cost_basis = cost_basis.to_f64().unwrap_or(0.0);
+        cb_mv_history.insert(date, (cost_basis, market_val));
+        if cost_basis <= 0.0 {
+            continue
+        }
+
+        let total_dividends: f64 = dividend_history.range(..=date).map(|(_, v)| v.to_f64()).sum();
 
         let daily_return = (100.0/cost_basis)*(sum_of_abs_returns + total_dividends);
 
         return_history.insert(date, daily_return);
-        cb_mv_history.insert(date, (cost_basis, market_val));    
     };
     
-    Some((return_history, cb_mv_history))
+    (return_history, cb_mv_history)
 } 
 
 
@@ -117,20 +133,21 @@ pub fn strip_dates(return_history: Vec<(NaiveDate, f32)>) -> Vec<f32> {
 
 
 
+// fills every missing day of a date-ascending history with the value before it, in one pass
 pub fn interpolate<T: Clone>(history: &mut Vec<(NaiveDate, T)>) {
-    let mut i = 0;
-
-    while i + 1 < history.len() {
-        let current_date = history[i].0; 
-        let next_date = current_date.checked_add_days(Days::new(1)).unwrap();
-
-        if next_date != history[i + 1].0 {
-            let cloned_value = history[i].1.clone();
-            history.insert(i + 1, (next_date, cloned_value));
-        } else {
-            i += 1;
+    let mut filled: Vec<(NaiveDate, T)> = Vec::with_capacity(history.len());
+
+    for (date, value) in history.drain(..) {
+        if let Some((last_date, last_value)) = filled.last().map(|(d, v)| (*d, v.clone())) {
+            let mut next_date = last_date + Duration::days(1);
+            while next_date < date {
+                filled.push((next_date, last_value.clone()));
+                next_date += Duration::days(1);
+            }
         }
+        filled.push((date, value));
     }
+    *history = filled;
 }
 
 
@@ -168,62 +185,114 @@ pub fn mean_sd_sharpe(just_returns: &Vec<f32>) -> (f32, f32, f32){
 
 
 
-pub fn interpolate_weekends(full_history: & mut HashMap<String, HashMap<NaiveDate, f64>>){
-        
-    for _ in 0..5{
-        for (_, single_element) in full_history.iter_mut() {
-            for (key, value) in single_element.clone() {
-                if let Some(next_day) = key.checked_add_days(Days::new(1)) {
-                    if !single_element.contains_key(&next_day) {
-                        single_element.insert(next_day, value);
-                    }
-                }
+// every calendar day from the first close up to end_date gets the last close on or before it, whatever the gap
+// (weekends, holidays, suspensions). returns the days that were filled rather than observed
+pub fn forward_fill(series: &mut HashMap<NaiveDate, f64>, end_date: NaiveDate) -> BTreeSet<NaiveDate> {
+
+    let mut filled: BTreeSet<NaiveDate> = BTreeSet::new();
+    let mut date = match series.keys().min() {
+        Some(v) => *v,
+        None => return filled
+    };
+    let mut last = series[&date];
+
+    while date <= end_date {
+        match series.get(&date) {
+            Some(price) => last = *price,
+            None => {
+                series.insert(date, last);
+                filled.insert(date);
             }
         }
+        date += Duration::days(1);
     }
+    filled
 }
 
 
 
+// forward_fill for every series, with the filled days of each
+pub fn forward_fill_all(full_history: &mut HashMap<String, HashMap<NaiveDate, f64>>, end_date: NaiveDate) -> HashMap<String, BTreeSet<NaiveDate>> {
+    full_history.iter_mut()
+        .map(|(key, series)| (key.clone(), forward_fill(series, end_date)))
+        .collect()
+}
 
 
-// finds the correct currency using ticker name and borse list, then fetches it from fx_history and multiplies by it
-pub fn fx_adjust(ticker: &String, matcher_date: NaiveDate, price: &mut f64, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>) {
-    
-    let euro_borsen = vec![".AS", ".DE", ".MC", ".PA", ".SW", ".MI", ".LS", ".AT", ".BE"]; 
-
-    let contains_any: bool = euro_borsen.iter().any(|&b| ticker.contains(b));
-
-    if ticker.contains(".TO") {
-        let temp_fx = fx_history
-            .get("GBPCAD")
-            .unwrap()
-            .get(&matcher_date)
-            .expect(&format!("couldn't get FX GBPCAD for {}", &matcher_date));
-        *price = *price / temp_fx;
-    } else {
-        if contains_any {
-            let temp_fx = fx_history
-                .get("GBPEUR")
-                .unwrap()
-                .get(&matcher_date)
-                .expect(&format!("couldn't get FX GBPEUR for {}", &matcher_date));
-            *price = *price / temp_fx;
-        } else if ticker.contains(".L") {
-            *price = *price / 100.0
-            // do nothing as it is already GBP and not other currency or GBX;
-        } else {
-            let temp_fx = fx_history
-                .get("GBPUSD")
-                .unwrap()
-                .get(&matcher_date)
-                .expect(&format!("couldn't get FX GBPUSD for {}", &matcher_date));
-            *price = *price / temp_fx;
+
+// days on which at least one of the given tickers had an observed close while it was held
+pub fn trading_days(
+    ranges: &HashMap<String, (NaiveDate, NaiveDate)>,
+    complete_prices: &HashMap<String, HashMap<NaiveDate, f64>>,
+    filled: &HashMap<String, BTreeSet<NaiveDate>>) -> BTreeSet<NaiveDate> {
+
+    let mut days: BTreeSet<NaiveDate> = BTreeSet::new();
+    for (ticker, (from, to)) in ranges {
+        if let (Some(series), Some(marks)) = (complete_prices.get(ticker), filled.get(ticker)) {
+            days.extend(series.keys().filter(|d| *d >= from && *d <= to && !marks.contains(d)));
         }
+    }
+    days
+}
+
+
+
+
+
+// price in quote currency to base currency, with the fx rate of matcher_date. fx_history is forward filled, so a
+// missing rate means the pair was never fetched or its history starts too late; price is left alone then
+pub fn fx_convert(quote: &str, base: &str, matcher_date: NaiveDate, price: &mut f64, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>) -> Result<()> {
+
+    if quote == base {
+        return Ok(())                     // already in account currency
+    }
+    *price = *price / fx_rate(quote, base, matcher_date, fx_history)?;
+    Ok(())
+}
+
+
+
+// units of quote per unit of base on matcher_date, e.g. 1.27 for base GBP and quote USD. a rate of zero is as
+// good as none, since prices are divided by it
+pub fn fx_rate(quote: &str, base: &str, matcher_date: NaiveDate, fx_history: &HashMap<String, HashMap<NaiveDate, f64>>) -> Result<f64> {
+
+    let pair = format!("{}{}", base, quote);
+    fx_history
+        .get(&pair)
+        .and_then(|history| history.get(&matcher_date))
+        .copied()
+        .filter(|rate| *rate > 0.0)
+        .ok_or(Error::MissingFx { pair, date: matcher_date })
+}
+
+
+// closes from start_date to end_date as an index starting at 100. with yahoo's adjclose this is a total-return
+// index, distributions reinvested, so distributing and accumulating share classes compare like for like
+pub fn total_return_index(closes: &HashMap<NaiveDate, f64>, start_date: NaiveDate, end_date: NaiveDate) -> Vec<(NaiveDate, f32)> {
+
+    let mut in_range: Vec<(NaiveDate, f64)> = closes.iter()
+        .filter(|(date, price)| **date >= start_date && **date <= end_date && **price > 0.0)
+        .map(|(date, price)| (*date, *price))
+        .collect();
+    in_range.sort_by_key(|(date, _)| *date);
+
+    let first = match in_range.first() {
+        Some((_, price)) => *price,
+        None => return Vec::new()
     };
+    in_range.into_iter().map(|(date, price)| (date, (price/first*100.0) as f32)).collect()
 }
 
 
+
+// %, from the first close on or after start_date to the last one on or before end_date
+pub fn period_return(closes: &HashMap<NaiveDate, f64>, start_date: NaiveDate, end_date: NaiveDate) -> Option<f64> {
+    let index = total_return_index(closes, start_date, end_date);
+    index.last().map(|(_, value)| *value as f64 - 100.0)
+}
+
+
+
 pub fn calculate_benchmark_returns(bench_returns: Vec<(NaiveDate, f64)>) -> Vec<f32>{
     let mut returns = Vec::new();
 
@@ -257,62 +326,167 @@ pub fn covariance(just_returns: &Vec<f32>, bench_returns: &Vec<f32>, mean: f32,
 
 
 
-// Newton-Raphson method for money-weighted rate of return
+// how xirr got its rate, or why there isn't one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Convergence {
+    Newton(u32),          // Newton-Raphson from the guess, in this many iterations
+    Bisection(u32),       // Newton ran off or stalled; the root was bracketed and bisected in this many
+    NoRoot,               // no rate makes the flows sum to zero, e.g. deposits with nothing to show for them
+    TooShort              // fewer than two flows, or all on one day, so there is no time for a rate to act over
+}
+
+impl fmt::Display for Convergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Convergence::Newton(n) => write!(f, "Newton-Raphson, {} iterations", n),
+            Convergence::Bisection(n) => write!(f, "bisection, {} iterations", n),
+            Convergence::NoRoot => write!(f, "no rate solves these flows"),
+            Convergence::TooShort => write!(f, "not enough history")
+        }
+    }
+}
+
+// money-weighted return of a set of cash flows on a 365-day year, deposits negative, withdrawals and the final
+// value positive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Xirr {
+    pub annual: Option<f64>,        // fraction a year, None when it didn't converge
+    pub years: f64,                 // from the first flow to the last
+    pub convergence: Convergence
+}
+
+impl Xirr {
+    // the same rate over the whole span of the flows rather than a year
+    pub fn cumulative(&self) -> Option<f64> {
+        self.annual.map(|rate| (1.0 + rate).powf(self.years) - 1.0)
+    }
+}
 
-pub fn mwrr(cashflows: &Vec<(NaiveDate, f64)>, guess: f64) -> Option<f64> {
+pub fn xirr(cashflows: &[(NaiveDate, f64)], guess: f64) -> Xirr {
 
-    const ITERS: usize = 100;
-    const TOLERANCE: f64 = 0.005;
+    const NEWTON_ITERS: u32 = 50;
+    const BISECTION_ITERS: u32 = 200;
+    const TOLERANCE: f64 = 1e-10;
 
-    //  || cashflows.len() == 1 
-    if cashflows.is_empty(){
-        return None;
+    let first = cashflows.iter().map(|cf| cf.0).min();
+    let last = cashflows.iter().map(|cf| cf.0).max();
+    let (t0, years) = match (first, last) {
+        (Some(first), Some(last)) if last > first => (first, (last - first).num_days() as f64 / 365.0),
+        _ => return Xirr { annual: None, years: 0.0, convergence: Convergence::TooShort }
     };
+    let flows: Vec<(f64, f64)> = cashflows.iter().map(|cf| ((cf.0 - t0).num_days() as f64 / 365.0, cf.1)).collect();
 
-    let t0 = cashflows[0].0;
-    let total_days = (cashflows.last().unwrap().0 - cashflows.first().unwrap().0).num_days() as f64;
     let npv = |rate: f64| -> f64 {
-        cashflows.iter().map(|cf| {
-            let days = (cf.0 - t0).num_days() as f64;
-            cf.1 / (1.0 + rate).powf(days / total_days)
-        }).sum()
+        flows.iter().map(|(t, v)| v / (1.0 + rate).powf(*t)).sum()
     };
-
     // the derivative of the cash flow sum function to be used in x1 = x0 - f(x0)/f'(x0)
     let npv_derivative = |rate: f64| -> f64 {
-        cashflows.iter().map(|cf| {
-            let days = (cf.0 - t0).num_days() as f64;
-            let exp = days / total_days;
-            -cf.1 * exp / (1.0 + rate).powf(exp + 1.0)
-        }).sum()
-    };    
-
-
-    let try_converge = |guess: f64| -> Option<f64> {
-        let mut rate = guess;
-        for _ in 0..ITERS {
-            let f = npv(rate);
-            let mut f_dash = npv_derivative(rate);
-
-            
-            // check for non-zero derivative or just a really small number that leads to large step size 
-            if f_dash.abs() < 1e-10 {
-                // println!("f_dash: {:?}", f_dash);
-                f_dash = 100.0 * f;
-                // break
-            };
-            let next_rate = (rate - f / f_dash).clamp(-0.99, std::f64::INFINITY);
-            if (next_rate - rate).abs() < TOLERANCE {
-                return Some(next_rate);
-            }
-            rate = next_rate;
+        flows.iter().map(|(t, v)| -v * t / (1.0 + rate).powf(t + 1.0)).sum()
+    };
+
+    // Newton-Raphson, given up as soon as a step leaves the rates that mean anything (below -100%) or stalls
+    let mut rate = guess;
+    for i in 0..NEWTON_ITERS {
+        let f_dash = npv_derivative(rate);
+        if f_dash == 0.0 || !f_dash.is_finite() {
+            break
         }
-                
-        None
+        let next_rate = rate - npv(rate) / f_dash;
+        if !next_rate.is_finite() || next_rate <= -1.0 {
+            break
+        }
+        if (next_rate - rate).abs() < TOLERANCE * rate.abs().max(1.0) {
+            return Xirr { annual: Some(next_rate), years, convergence: Convergence::Newton(i + 1) }
+        }
+        rate = next_rate;
+    }
+
+    // otherwise look for a sign change, stepping through growth factors ln(1 + rate) from a near-total loss to
+    // ~10^8 % a year (a few days' flows can annualise to that), and bisect in between
+    let npv_of_growth = |growth: f64| npv(growth.exp() - 1.0);
+    let steps = [-12.0, -6.0, -3.0, -1.5, -0.7, -0.3, -0.1, 0.0, 0.1, 0.3, 0.7, 1.5, 3.0, 6.0, 12.0, 24.0];
+    let bracket = steps.windows(2)
+        .map(|w| (w[0], w[1]))
+        .find(|(lo, hi)| {
+            let (f_lo, f_hi) = (npv_of_growth(*lo), npv_of_growth(*hi));
+            f_lo.is_finite() && f_hi.is_finite() && (f_lo.signum() != f_hi.signum() || f_hi == 0.0)
+        });
+    let (mut lo, mut hi) = match bracket {
+        Some(bracket) => bracket,
+        None => return Xirr { annual: None, years, convergence: Convergence::NoRoot }
     };
-    
-    match try_converge(guess) {
-        Some(rate) => Some(rate),        // cash flow function might not converge if guess is +ve and irr is -ve
-        None => try_converge(-guess)    // or vice versa so we try -guess if it didnt work first time around
+    let f_lo = npv_of_growth(lo);
+    let mut iterations = 0;
+    while hi - lo > TOLERANCE && iterations < BISECTION_ITERS {
+        let mid = (lo + hi) / 2.0;
+        if npv_of_growth(mid).signum() == f_lo.signum() {
+            lo = mid;
+        } else {
+            hi = mid;
+        }
+        iterations += 1;
+    }
+    Xirr { annual: Some(((lo + hi) / 2.0).exp() - 1.0), years, convergence: Convergence::Bisection(iterations) }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+
+    #[test]
+    fn missing_fx_is_reported_with_the_pair_and_day() {
+        let fx_history = HashMap::from([(String::from("GBPUSD"), HashMap::from([(day("2024-03-18"), 1.25)]))]);
+
+        let mut price = 125.0;
+        fx_convert("USD", "GBP", day("2024-03-18"), &mut price, &fx_history).unwrap();
+        assert_eq!(price, 100.0);
+
+        let mut price = 125.0;
+        match fx_convert("USD", "GBP", day("2024-03-15"), &mut price, &fx_history) {
+            Err(Error::MissingFx { pair, date }) => assert_eq!((pair.as_str(), date), ("GBPUSD", day("2024-03-15"))),
+            other => panic!("expected missing fx, got {:?}", other)
+        }
+        assert!(matches!(fx_convert("EUR", "GBP", day("2024-03-18"), &mut price, &fx_history), Err(Error::MissingFx { .. })));
+        assert_eq!(price, 125.0);
+    }
+
+    #[test]
+    fn forward_fill_covers_long_gaps_and_marks_them() {
+        // Thursday before Easter, then the Tuesday after: a four-day gap, plus a weekend at the end
+        let mut series = HashMap::from([(day("2024-03-28"), 10.0), (day("2024-04-02"), 11.0), (day("2024-04-05"), 12.0)]);
+        let filled = forward_fill(&mut series, day("2024-04-07"));
+
+        assert_eq!(series.len(), 11);
+        assert_eq!(series[&day("2024-04-01")], 10.0);
+        assert_eq!(series[&day("2024-04-07")], 12.0);
+        assert_eq!(filled.iter().map(|d| d.to_string()).collect::<Vec<String>>(),
+            vec!["2024-03-29", "2024-03-30", "2024-03-31", "2024-04-01", "2024-04-03", "2024-04-04", "2024-04-06", "2024-04-07"]);
+        assert!(!filled.contains(&day("2024-04-02")));
+    }
+
+    #[test]
+    fn interpolate_fills_between_entries() {
+        let mut history = vec![(day("2024-01-01"), 1), (day("2024-01-04"), 4), (day("2024-01-05"), 5)];
+        interpolate(&mut history);
+        assert_eq!(history, vec![(day("2024-01-01"), 1), (day("2024-01-02"), 1), (day("2024-01-03"), 1), (day("2024-01-04"), 4), (day("2024-01-05"), 5)]);
+    }
+
+    #[test]
+    fn trading_days_leave_out_filled_and_unheld_days() {
+        let mut prices = HashMap::from([
+            (String::from("AAPL"), HashMap::from([(day("2024-03-28"), 170.0), (day("2024-04-02"), 169.0)])),
+            (String::from("VUSA.L"), HashMap::from([(day("2024-03-27"), 80.0), (day("2024-03-28"), 81.0)]))
+        ]);
+        let filled = forward_fill_all(&mut prices, day("2024-04-02"));
+        let ranges = HashMap::from([
+            (String::from("AAPL"), (day("2024-03-28"), day("2024-04-02"))),
+            (String::from("VUSA.L"), (day("2024-03-28"), day("2024-04-02")))
+        ]);
+
+        let days = trading_days(&ranges, &prices, &filled);
+        assert_eq!(days, BTreeSet::from([day("2024-03-28"), day("2024-04-02")]));
     }
 }
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use futures::future::{FutureExt, LocalBoxFuture};
+use reqwest::header::USER_AGENT;
+use chrono::NaiveDate;
+use tokio::sync::Semaphore;
+use crate::error::{Error, Result};
+use crate::prices::{client, parse_csv, PriceProvider};
+
+
+// stooq is a small free service, so it gets fewer parallel requests than yahoo
+static HOST: Semaphore = Semaphore::const_new(2);
+const PAUSE: Duration = Duration::from_millis(250);
+
+
+
+pub const BASE_URL: &str = "https://stooq.com";
+
+pub struct Stooq {
+    pub base_url: String                  // BASE_URL, or a stand-in serving the same csv
+}
+
+impl Stooq {
+    pub fn new(base_url: &str) -> Stooq {
+        Stooq { base_url: base_url.trim_end_matches('/').to_string() }
+    }
+}
+
+impl PriceProvider for Stooq {
+
+    fn name(&self) -> &str {
+        "stooq"
+    }
+
+    fn daily_closes<'a>(&'a self, symbol: &'a str, start_date: NaiveDate, end_date: NaiveDate) -> LocalBoxFuture<'a, Result<HashMap<NaiveDate, f64>>> {
+        async move {
+            match to_stooq_symbol(symbol) {
+                Some(stooq_symbol) => fetch_closes(&self.base_url, &stooq_symbol, start_date, end_date).await,
+                None => Ok(HashMap::new())          // exchange not covered by stooq
+            }
+        }.boxed_local()
+    }
+}
+
+
+
+async fn fetch_closes(base_url: &str, symbol: &str, start_date: NaiveDate, end_date: NaiveDate) -> Result<HashMap<NaiveDate, f64>> {
+
+    let url = format!(
+        "{}/q/d/l/?s={}&d1={}&d2={}&i=d",
+        base_url, symbol, start_date.format("%Y%m%d"), end_date.format("%Y%m%d")
+    );
+
+    let slot = HOST.acquire().await.map_err(|e| Error::Api { endpoint: url.clone(), message: e.to_string() })?;
+    let response = client().get(&url)
+        .header(USER_AGENT, "Mozilla/5.0")
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    tokio::time::sleep(PAUSE).await;
+    drop(slot);
+
+    // unknown symbols come back as a plain "No data" instead of a csv
+    if !response.starts_with("Date") {
+        return Ok(HashMap::new())
+    }
+    parse_csv(&response).map_err(|e| Error::parse(format!("stooq prices of {}", symbol), e))
+}
+
+
+
+// yahoo symbols to stooq's, e.g. AAPL -> aapl.us, VUSA.L -> vusa.uk, GBPUSD=X -> gbpusd
+fn to_stooq_symbol(symbol: &str) -> Option<String> {
+
+    let symbol = symbol.to_ascii_lowercase();
+
+    if let Some(pair) = symbol.strip_suffix("=x") {
+        return Some(pair.to_string())
+    }
+
+    match symbol.rsplit_once('.') {
+        None => Some(format!("{}.us", symbol)),
+        Some((base, "l")) => Some(format!("{}.uk", base)),
+        Some((base, "de")) => Some(format!("{}.de", base)),
+        Some(_) => None
+    }
+}
@@ -1,44 +1,50 @@
 #![allow(non_snake_case)]
 #![allow(dead_code)]
 use std::collections::HashMap;
+use std::hash::Hash;
 use reqwest::{header::{HeaderMap, HeaderValue, AUTHORIZATION}, Response};
-use std::error::Error;
-use chrono::DateTime;
+use chrono::{DateTime, NaiveDate};
+use chrono_tz::Tz;
 use serde::{Deserialize, Deserializer};
-use std::{thread, time::Duration};
+use rust_decimal::Decimal;
+use std::time::Duration;
 use serde_json::Value;
+use crate::error::{Error, Result};
+use crate::exchanges;
+use crate::money::Quantity;
+use crate::ratelimit;
 
 
 
-#[tokio::main]
-pub async fn get_orders(api_key: &str) -> Result<Vec<Order>, Box<dyn Error>> {
+pub async fn get_orders(api_key: &str, base_url: &str) -> Result<Vec<Order>> {
 
     let mut data = Vec::<Order>::new();
     let mut cursor = String::from("");    // start with empty cursor
     let mut orders = Vec::new();          // and empty vector <T> (holds any type but mine is Vec<Order>)
 
-    while cursor != String::from("complete") {    // repeat until process_items() returns cursor as "complete"
+    while cursor != "complete" {    // repeat until process_items() returns cursor as "complete"
 
-        let api_response = recursive_call_api(&api_key, "https://live.trading212.com/api/v0/equity/history/orders", &cursor, ResponseType::Orders).await;
+        let api_response = recursive_call_api(&api_key, base_url, "/api/v0/equity/history/orders", &cursor, ResponseType::Orders).await?;
         // println!("{:?}", api_response);
 
+        // a failed page fails the whole import: the history would otherwise stop there without anyone noticing
         (cursor, orders) = match api_response {                    // process_items returns a tuple so we catch both cursor
-            Ok(CallResponse::Orders(items)) => process_items(items),            // and orders in this match
-            _ => {
-
-                // eprintln!("{}", e);               // doesn't assign tuple but breaks loop so compiler doesn't care
-                break
-            }
+            CallResponse::Orders(items) => process_items(items)?,              // and orders in this match
+            _ => return Err(unexpected("/api/v0/equity/history/orders"))
         };
 
+        let mut orders = drop_page_overlap(&data, orders);
         data.append(&mut orders);
         
     };
 
     
     
+    // timestamps are kept whole; the day an order counts on is the one at its exchange
     for item in &mut data {
-        item.dateModified = item.dateModified.chars().take(10).collect();    // convert date to daily
+        let tz = exchanges::time_zone(&item.ticker);
+        item.executed = exchanges::exchange_time(&item.dateModified, tz);
+        item.date = exchanges::trading_date(&item.dateModified, tz)?;
     }
 
     Ok(data)
@@ -46,10 +52,44 @@ pub async fn get_orders(api_key: &str) -> Result<Vec<Order>, Box<dyn Error>> {
 
 
 
+// open positions as the broker sees them right now; not paginated, so a single call does it
+pub async fn get_positions(api_key: &str, base_url: &str) -> Result<Vec<Position>> {
+
+    match recursive_call_api(&api_key, base_url, "/api/v0/equity/portfolio", &String::from(""), ResponseType::Positions).await? {
+        CallResponse::Positions(positions) => Ok(positions),
+        _ => Err(unexpected("/api/v0/equity/portfolio"))
+    }
+}
+
+
+
+// the account's cash figures right now, used to cross-check the cash ledger
+pub async fn get_cash(api_key: &str, base_url: &str) -> Result<Cash> {
+
+    match recursive_call_api(&api_key, base_url, "/api/v0/equity/account/cash", &String::from(""), ResponseType::Cash).await? {
+        CallResponse::Cash(cash) => Ok(cash),
+        _ => Err(unexpected("/api/v0/equity/account/cash"))
+    }
+}
+
+
+
+// every instrument the broker offers, with the currency its prices are quoted in (GBX for pence). not paginated,
+// but limited to one call every 50 seconds, so it is fetched once per environment
+pub async fn get_instruments(api_key: &str, base_url: &str) -> Result<Vec<Instrument>> {
+
+    match recursive_call_api(&api_key, base_url, "/api/v0/equity/metadata/instruments", &String::from(""), ResponseType::Instruments).await? {
+        CallResponse::Instruments(instruments) => Ok(instruments),
+        _ => Err(unexpected("/api/v0/equity/metadata/instruments"))
+    }
+}
+
+
+
 // defining structs for json output to be deserialized into (within recursive_api_call)
 #[derive(Debug, Deserialize)]
 pub struct Items {
-    items: Vec<Order>,
+    pub items: Vec<Order>,
 
 }
 
@@ -57,16 +97,22 @@ pub struct Items {
 pub struct Order {                                            // both the struct and fields have to be public to be accessed in main
     pub id: u64,
     pub ticker: String,
-    pub dateModified: String,
+    pub dateModified: String,                                 // RFC 3339, as T212 sends it
+
+    #[serde(skip)]
+    pub date: NaiveDate,                                      // the trading day at the instrument's exchange
+
+    #[serde(skip)]
+    pub executed: Option<DateTime<Tz>>,                       // dateModified in the exchange's time zone
+
+    #[serde(default)]                                         // null for orders that never filled, see Quantity
+    pub filledQuantity: Quantity,                             // exact, so selling everything leaves exactly nothing
 
     #[serde(default, deserialize_with = "deserialize_null_fields")]    // custom deserialize routine to fill occasional nulls.
-    pub filledQuantity: f64,                                  // happens because .json has implementation for null,
-                                                              
-    #[serde(default, deserialize_with = "deserialize_null_fields")]    // but rust doesn't (and doesn't even treat it as a missing field)    <-\\
-    pub fillPrice: f64,
+    pub fillPrice: Decimal,                                   // happens because .json has implementation for null,
 
-    #[serde(default, deserialize_with = "deserialize_null_fields")]    
-    pub filledValue: f64,
+    #[serde(default, deserialize_with = "deserialize_null_fields")]    // but rust doesn't (and doesn't even treat it as a missing field)    <-\\
+    pub filledValue: Decimal,
 
     #[serde(default)]    
     pub taxes: Vec<Fee>,
@@ -82,97 +128,285 @@ pub struct Dividends {
     
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Clone)]
 pub struct Dividend {
     pub ticker: String,
-    pub amount: f64,
-    pub paidOn: String
+    pub amount: Decimal,                                      // in account currency
+    pub paidOn: String,                                       // RFC 3339, as T212 sends it
+
+    #[serde(default)]
+    pub reference: String,
+
+    #[serde(skip)]
+    pub date: NaiveDate                                       // the day it was paid at the instrument's exchange
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Position {
+    pub ticker: String,
+
+    #[serde(default)]
+    pub quantity: Quantity,
+
+    #[serde(default, deserialize_with = "deserialize_null_fields")]    // in the instrument's own currency, like fillPrice
+    pub averagePrice: Decimal,
+
+    #[serde(default, deserialize_with = "deserialize_null_fields")]
+    pub currentPrice: f64
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Instrument {
+    pub ticker: String,
+    pub currencyCode: Option<String>
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct Cash {
+    #[serde(default, deserialize_with = "deserialize_null_fields")]
+    pub free: Decimal,                                        // uninvested cash
+
+    #[serde(default, deserialize_with = "deserialize_null_fields")]
+    pub total: Decimal,                                       // cash plus positions
+
+    #[serde(default, deserialize_with = "deserialize_null_fields")]
+    pub invested: Decimal,
+
+    #[serde(default, deserialize_with = "deserialize_null_fields")]
+    pub pieCash: Decimal,
+
+    #[serde(default, deserialize_with = "deserialize_null_fields")]
+    pub blocked: Decimal
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Transactions {
+    pub items: Vec<Transaction>,
+    pub nextPagePath: Option<String>
+}
+
+// deposits, withdrawals, transfers between accounts, fees and interest
+#[derive(Debug, Deserialize, Clone)]
+pub struct Transaction {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub amount: Decimal,                                      // in account currency
+    pub dateTime: String,                                     // RFC 3339, as T212 sends it
+
+    #[serde(default)]
+    pub reference: String,
+
+    #[serde(skip)]
+    pub date: NaiveDate                                       // the day it was booked, see exchanges::ACCOUNT_TIME_ZONE
 }
 
 // enum to hold the other struct types
 #[derive(Debug)]
 pub enum CallResponse {
     Orders(Items), // orders
-    Divis(Dividends)
+    Divis(Dividends),
+    Positions(Vec<Position>),
+    Cash(Cash),
+    Transactions(Transactions),
+    Instruments(Vec<Instrument>)
 }
 
 // the decider for which struct recursive_api_call should return
 pub enum ResponseType {
     Orders,
-    Divis
+    Divis,
+    Positions,
+    Cash,
+    Transactions,
+    Instruments
 }
 
 #[derive(Debug, Deserialize, Clone)]
 pub struct Fee {
     pub name: String,
-    pub quantity: f32
+    pub quantity: Decimal                                     // in account currency, negative
 }
 
-fn deserialize_null_fields<'de, D>(deserializer: D) -> Result<f64, D::Error> where D: Deserializer<'de> {    // the routine itself  <-||
-    Option::<f64>::deserialize(deserializer).map(|opt| opt.unwrap_or(0.0))
+fn deserialize_null_fields<'de, D, T>(deserializer: D) -> Result<T, D::Error> where D: Deserializer<'de>, T: Deserialize<'de> + Default {    // the routine itself  <-||
+    Option::<T>::deserialize(deserializer).map(|opt| opt.unwrap_or_default())
+}
+
+
+
+// one connection pool for every call to T212, whichever account or environment
+pub fn client() -> &'static reqwest::Client {
+    static CLIENT: std::sync::OnceLock<reqwest::Client> = std::sync::OnceLock::new();
+    CLIENT.get_or_init(|| reqwest::Client::builder().timeout(Duration::from_secs(30)).build().unwrap_or_default())
 }
 
 
 
 // returns a CallResponse which can be either an Orders or a Dividends variant
-pub async fn recursive_call_api(api_key: &str, api_url: &str, current_cursor: &String, response_type: ResponseType) -> Result<CallResponse, Box<dyn Error>>{
- 
+// base_url picks the environment (live or demo), path picks the endpoint. every call waits for its turn in the
+// endpoint's token bucket; 429s, server errors and network failures are retried as ratelimit::retry_policy says,
+// anything else is returned as it is
+pub async fn recursive_call_api(api_key: &str, base_url: &str, path: &str, current_cursor: &String, response_type: ResponseType) -> Result<CallResponse>{
+
 
     let mut headers = HeaderMap::new();
-    headers.insert(AUTHORIZATION, HeaderValue::from_str(&api_key)?);
+    headers.insert(AUTHORIZATION, HeaderValue::from_str(&api_key).map_err(|_| Error::Auth { endpoint: path.to_string() })?);
 
     let params = HashMap::from([
         ("cursor", current_cursor.as_str()),
         ("ticker", ""),
         ("limit", "50")]);
 
-    let client = reqwest::Client::new();
-    let response = client
-        .get(api_url)
-        .headers(headers)
-        .query(&params)
-        .send()
-        .await?;
+    let api_url = format!("{}{}", base_url, path);
+
+    let policy = ratelimit::retry_policy();
+    let mut attempt = 0;
+    let response = loop {
+        ratelimit::acquire(api_key, path).await;
+        let sent = client()
+            .get(&api_url)
+            .headers(headers.clone())
+            .query(&params)
+            .send()
+            .await;
+
+        let (failure, wait) = match sent {
+            Ok(response) => {
+                ratelimit::observe(api_key, path, response.headers());
+                let status = response.status();
+                if status.as_u16() == 429 {              // 429 means too many requests
+                    let wait = ratelimit::retry_after(response.headers(), ratelimit::now_unix()).unwrap_or(Duration::from_secs(60));
+                    (Error::from_status(path, status), wait)
+                } else if status.is_server_error() {
+                    (Error::from_status(path, status), policy.backoff(attempt, rand::random()))
+                } else {
+                    break response
+                }
+            },
+            Err(e) if e.is_builder() => return Err(e.into()),
+            Err(e) => (e.into(), policy.backoff(attempt, rand::random()))
+        };
+
+        if attempt >= policy.max_retries {
+            return Err(failure)
+        }
+        attempt += 1;
+        ratelimit::pause(wait).await;
+    };
 
     let status = response.status();
     // let bytes = response.bytes().await?;
     // println!("Raw response: {}", String::from_utf8_lossy(&bytes));
 
-    if status.is_success() {
-        match response_type {
-            ResponseType::Orders => {let catcher: Items = response.json().await?;
-            return Ok(CallResponse::Orders(catcher))},
-            ResponseType::Divis => {let catcher: Dividends = response.json().await?;
-            return Ok(CallResponse::Divis(catcher))},
+    if !status.is_success() {
+        return Err(Error::from_status(path, status))
+    }
+
+    let body = response.text().await?;
+    let parsed = match response_type {
+        ResponseType::Orders => serde_json::from_str(&body).map(CallResponse::Orders),
+        ResponseType::Divis => serde_json::from_str(&body).map(CallResponse::Divis),
+        ResponseType::Positions => serde_json::from_str(&body).map(CallResponse::Positions),
+        ResponseType::Cash => serde_json::from_str(&body).map(CallResponse::Cash),
+        ResponseType::Transactions => serde_json::from_str(&body).map(CallResponse::Transactions),
+        ResponseType::Instruments => serde_json::from_str(&body).map(CallResponse::Instruments)
+    };
+    parsed.map_err(|e| Error::parse(format!("response from {}", path), e))
+}
 
-        }
 
-    } else {
-        if status.as_str().contains("429"){  // 429 means too many requests
-            countdown(60);
-            let d2_response = Box::pin(recursive_call_api(&api_key, api_url, current_cursor, response_type)).await;  // Box::pin because Rust doesn't allow recursive async funcs that are not boxed
-            return d2_response
-        } else {
-            Err(format!("API call failed: {}", status).into())
-        }
-    }
+
+// an endpoint answered with the wrong kind of response, which only a mix-up of ResponseTypes can cause
+pub fn unexpected(path: &str) -> Error {
+    Error::Api { endpoint: path.to_string(), message: String::from("unexpected kind of response") }
 }
 
 
 
 
-fn process_items(orders: Items) -> (String, Vec<Order>) {
-                                                //vvv if none then none, if some then use in this closure  
-    let timestamp = match orders.items.last().and_then(|order| extract_unix(&order.dateModified)) {
-        Some(v) => v,                       // if it worked, return unix timestamp as cursor 
-        None => String::from("complete")    // it it didn't, return "complete" as cursor 
+// an empty page ends the history. a last record whose time can't be read is an error, since taking it for the end
+// would quietly drop every older page
+fn process_items(orders: Items) -> Result<(String, Vec<Order>)> {
+    let timestamp = match orders.items.last() {
+        Some(order) => cursor_from(&order.dateModified, "/api/v0/equity/history/orders")?,
+        None => String::from("complete")
     };
     eprintln!("processed page: {:?}", timestamp);
-    (timestamp, orders.items)
+    Ok((timestamp, orders.items))
 }
 
 
 
+// a record of a paginated history: the timestamp its page's cursor is taken from, and what tells it apart from
+// other records of the same second
+pub trait Paged {
+    type Key: Eq + Hash;
+    fn timestamp(&self) -> &str;
+    fn key(&self) -> Self::Key;
+}
+
+impl Paged for Order {
+    type Key = (u64, Quantity, Decimal, Decimal);
+    fn timestamp(&self) -> &str {
+        &self.dateModified
+    }
+    fn key(&self) -> Self::Key {
+        (self.id, self.filledQuantity, self.fillPrice, self.filledValue)
+    }
+}
+
+impl Paged for Dividend {
+    type Key = (String, String, Decimal);
+    fn timestamp(&self) -> &str {
+        &self.paidOn
+    }
+    fn key(&self) -> Self::Key {
+        (self.reference.clone(), self.ticker.clone(), self.amount)
+    }
+}
+
+impl Paged for Transaction {
+    type Key = (String, String, Decimal);
+    fn timestamp(&self) -> &str {
+        &self.dateTime
+    }
+    fn key(&self) -> Self::Key {
+        (self.reference.clone(), self.kind.clone(), self.amount)
+    }
+}
+
+// the cursor is the last record's timestamp, so a page can start with records of that same second the page
+// before already ended with. those are dropped, each one only against a record of the previous page, so records
+// that are genuinely identical (same size, price and second) all stay
+pub fn drop_page_overlap<T: Paged>(previous: &[T], page: Vec<T>) -> Vec<T> {
+
+    let boundary = match previous.last() {
+        Some(record) => record.timestamp().to_string(),
+        None => return page
+    };
+    let mut repeats: HashMap<T::Key, usize> = HashMap::new();
+    for record in previous.iter().rev().take_while(|r| r.timestamp() == boundary) {
+        *repeats.entry(record.key()).or_insert(0) += 1;
+    }
+
+    let mut page = page.into_iter().peekable();
+    let mut kept = Vec::new();
+    while let Some(record) = page.next_if(|r| r.timestamp() == boundary) {
+        match repeats.get_mut(&record.key()).filter(|count| **count > 0) {
+            Some(count) => *count -= 1,
+            None => kept.push(record)
+        }
+    }
+    kept.extend(page);
+    kept
+}
+
+
+
+// the next page's cursor from the last record's timestamp
+pub fn cursor_from(timestamp: &String, endpoint: &str) -> Result<String> {
+    extract_unix(timestamp).ok_or_else(|| Error::parse(format!("{} cursor", endpoint), format!("{:?} is not a timestamp", timestamp)))
+}
+
 pub fn extract_unix(timestamp: &String) -> Option<String> {
     // shadowing
     let timestamp = timestamp.as_str();
@@ -184,16 +418,3 @@ pub fn extract_unix(timestamp: &String) -> Option<String> {
 
     Some(timestamp)
 }
-
-
-
-fn countdown(mut seconds: i32){
-    while seconds > 0 {
-        print!("\rAPI rate limit exceeded, further orders fetched automatically in {:2}", seconds);
-        std::io::Write::flush(&mut std::io::stdout()).unwrap(); // flush last line
-        thread::sleep(Duration::from_secs(1));
-        seconds -= 1;
-    }
-    std::io::Write::flush(&mut std::io::stdout()).unwrap();     // flush it again at the end
-    println!("");
-}
@@ -0,0 +1,34 @@
+use std::str::FromStr;
+use chrono::NaiveDate;
+use rust_decimal::Decimal;
+use crate::money::Quantity;
+use crate::t212::Order;
+
+
+
+// what the unit tests of every module build their data from, so a new Order field is added here only
+
+pub fn day(s: &str) -> NaiveDate {
+    NaiveDate::from_str(s).unwrap()
+}
+
+pub fn exact(s: &str) -> Decimal {
+    Decimal::from_str(s).unwrap()
+}
+
+// a filled order of ticker at 15:00 UTC on date, its value the quantity at the price. tests that need another id,
+// time or value set it on the result, e.g. Order { id: 2, ..order("AAPL_US_EQ", "2024-03-05", "1", "170") }
+pub fn order(ticker: &str, date: &str, quantity: &str, price: &str) -> Order {
+    Order {
+        id: 1,
+        ticker: ticker.to_string(),
+        dateModified: format!("{}T15:00:00Z", date),
+        date: day(date),
+        executed: None,
+        filledQuantity: Quantity(exact(quantity)),
+        fillPrice: exact(price),
+        filledValue: (exact(quantity) * exact(price)).abs(),
+        taxes: Vec::new(),
+        status: String::from("FILLED")
+    }
+}
@@ -0,0 +1,46 @@
+#![allow(non_snake_case)]
+use crate::error::Result;
+use crate::exchanges;
+use crate::t212::{recursive_call_api, unexpected, cursor_from, drop_page_overlap, CallResponse, ResponseType, Transaction, Transactions};
+
+
+
+// deposits, withdrawals, transfers, fees and interest; everything that moves cash apart from orders and dividends
+pub async fn get_transactions(api_key: &str, base_url: &str) -> Result<Vec::<Transaction>> {
+
+    let mut data = Vec::<Transaction>::new();
+    let mut cursor = String::from("");    // start with empty cursor
+
+    while cursor != "complete" {    // repeat until process_items() returns cursor as "complete"
+
+        let api_response = recursive_call_api(&api_key, base_url, "/api/v0/history/transactions", &cursor, ResponseType::Transactions).await?;
+
+        let transactions;
+        (cursor, transactions) = match api_response {   // process_items returns a tuple so we catch both cursor
+            CallResponse::Transactions(items) => process_items(items)?,
+            _ => return Err(unexpected("/api/v0/history/transactions"))
+        };
+
+        let mut transactions = drop_page_overlap(&data, transactions);
+        data.append(&mut transactions);
+    };
+
+    // dated like orders and dividends, at the account's time zone rather than by the UTC date T212 sends
+    for item in &mut data {
+        item.date = exchanges::trading_date(&item.dateTime, exchanges::ACCOUNT_TIME_ZONE)?;
+    };
+
+    Ok(data)
+}
+
+
+
+fn process_items(transactions: Transactions) -> Result<(String, Vec<Transaction>)> {
+    // like dividends, the last page or an empty one ends the history and any other needs a readable cursor
+    let timestamp = match transactions.items.last() {
+        Some(transaction) if transactions.nextPagePath.is_some() => cursor_from(&transaction.dateTime, "/api/v0/history/transactions")?,
+        _ => String::from("complete")
+    };
+    eprintln!("Transaction import from Trading212: {}", timestamp);
+    Ok((timestamp, transactions.items))
+}
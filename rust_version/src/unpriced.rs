@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use chrono::{Duration, NaiveDate};
+use serde::{Deserialize, Serialize};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
+use crate::t212::Order;
+
+
+
+// how a position without market data is valued instead, set per ticker in custom_tickers.json
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Fallback {
+    #[default]
+    LastTrade,      // the most recent fill price, so the position is shown at cost-ish rather than dropped
+    Manual,         // <csv_dir>/<TICKER>.csv, even if csv isn't in the ticker's price chain
+    Zero            // written off, e.g. delisted after a bankruptcy
+}
+
+impl Fallback {
+    pub fn describe(&self) -> &'static str {
+        match self {
+            Fallback::LastTrade => "last trade price",
+            Fallback::Manual => "manual prices",
+            Fallback::Zero => "written off"
+        }
+    }
+}
+
+impl FromStr for Fallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Fallback, String> {
+        match s {
+            "last_trade" => Ok(Fallback::LastTrade),
+            "manual" => Ok(Fallback::Manual),
+            "zero" => Ok(Fallback::Zero),
+            _ => Err(format!("unknown fallback {}, use last_trade, manual or zero", s))
+        }
+    }
+}
+
+
+
+// every day from start_date to end_date at the last fill price of ticker on or before it. orders must be converted
+// to yahoo tickers and fx adjusted, so the series is already in account currency; days before the first fill are left out
+pub fn last_trade_series(orders: &Vec<Order>, ticker: &str, start_date: NaiveDate, end_date: NaiveDate) -> HashMap<NaiveDate, f64> {
+
+    let mut fills: Vec<(NaiveDate, f64)> = orders.iter()
+        .filter(|o| o.ticker == ticker && o.status == "FILLED" && o.fillPrice > Decimal::ZERO)
+        .map(|o| (o.date, o.fillPrice.to_f64().unwrap_or(0.0)))
+        .collect();
+    fills.sort_by_key(|(date, _)| *date);     // stable, so the last fill of a day wins
+
+    let mut series: HashMap<NaiveDate, f64> = HashMap::new();
+    let mut next = 0;
+    let mut price: Option<f64> = None;
+    let mut date = start_date;
+
+    while date <= end_date {
+        while next < fills.len() && fills[next].0 <= date {
+            price = Some(fills[next].1);
+            next += 1;
+        }
+        if let Some(p) = price {
+            series.insert(date, p);
+        }
+        date += Duration::days(1);
+    }
+    series
+}
+
+
+
+pub fn zero_series(start_date: NaiveDate, end_date: NaiveDate) -> HashMap<NaiveDate, f64> {
+    let days = (end_date - start_date).num_days();
+    (0..=days).map(|i| (start_date + Duration::days(i), 0.0)).collect()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{day, order};
+
+    #[test]
+    fn last_trade_carries_the_latest_fill_forward() {
+        let orders = vec![
+            order("DLST", "2024-01-03", "1", "10"),
+            order("AAPL", "2024-01-04", "1", "180"),
+            order("DLST", "2024-01-05", "1", "12")
+        ];
+        let series = last_trade_series(&orders, "DLST", day("2024-01-01"), day("2024-01-08"));
+
+        assert!(!series.contains_key(&day("2024-01-02")));
+        assert_eq!(series[&day("2024-01-04")], 10.0);
+        assert_eq!(series[&day("2024-01-05")], 12.0);
+        assert_eq!(series[&day("2024-01-08")], 12.0);
+        assert_eq!(series.len(), 6);
+    }
+
+    #[test]
+    fn zero_covers_every_day() {
+        let series = zero_series(day("2024-01-01"), day("2024-01-31"));
+        assert_eq!(series.len(), 31);
+        assert!(series.values().all(|p| *p == 0.0));
+    }
+}
@@ -1,16 +1,57 @@
 use core::f64;
 use std::collections::HashMap;
 
-use reqwest::{Client, header::USER_AGENT};
+use std::time;
+use futures::future::{FutureExt, LocalBoxFuture};
+use reqwest::header::USER_AGENT;
 use serde_json::Value;
 use chrono::{Datelike, Duration, NaiveDate, TimeZone, Utc};
-use std::io;
-use textwrap::wrap;
-use crate::plotter::clear_last_n_lines;
-// accepts string slice with ticker passed to it from main
+use tokio::sync::Semaphore;
+use crate::error::{Error, Result};
+use crate::money::Ratio;
+use crate::prices::{client, PriceProvider};
+use crate::resolver::Candidate;
 
-#[tokio::main]
-pub async fn get_prices(symbol: &str, start_date: NaiveDate, end_date: NaiveDate, custom_tickers: &mut HashMap<String, String>) -> Result<HashMap<NaiveDate, f64>, Box<dyn std::error::Error>> {
+
+pub const BASE_URL: &str = "https://query1.finance.yahoo.com";
+
+// yahoo starts answering 429 when hammered, so at most this many requests to it at once,
+// each holding its slot a little after the response to space them out
+static HOST: Semaphore = Semaphore::const_new(4);
+const PAUSE: time::Duration = time::Duration::from_millis(150);
+
+// plain closes, or with adjusted set yahoo's adjclose, which has distributions reinvested (and splits, like close).
+// the adjusted source is listed as yahoo-adjusted in price_sources.json
+pub struct Yahoo {
+    pub adjusted: bool,
+    pub base_url: String                  // BASE_URL, or a stand-in serving the same api
+}
+
+impl Yahoo {
+    pub fn new(adjusted: bool, base_url: &str) -> Yahoo {
+        Yahoo { adjusted, base_url: base_url.trim_end_matches('/').to_string() }
+    }
+}
+
+impl PriceProvider for Yahoo {
+
+    fn name(&self) -> &str {
+        if self.adjusted {"yahoo-adjusted"} else {"yahoo"}
+    }
+
+    // adjclose is rescaled back to the first bar with every distribution, so settled bars don't stay settled
+    fn cacheable(&self) -> bool {
+        !self.adjusted
+    }
+
+    fn daily_closes<'a>(&'a self, symbol: &'a str, start_date: NaiveDate, end_date: NaiveDate) -> LocalBoxFuture<'a, Result<HashMap<NaiveDate, f64>>> {
+        fetch_closes(&self.base_url, symbol, start_date, end_date, self.adjusted).boxed_local()
+    }
+}
+
+
+
+async fn fetch_closes(base_url: &str, symbol: &str, start_date: NaiveDate, end_date: NaiveDate, adjusted: bool) -> Result<HashMap<NaiveDate, f64>> {
 
     // Convert dates to UNIX timestamps
 
@@ -21,87 +62,157 @@ pub async fn get_prices(symbol: &str, start_date: NaiveDate, end_date: NaiveDate
         end_timestamp += -86400;                          // move end_date back a day in case start and end are the same day now
     }
 
+    let url = format!(
+        "{}/v8/finance/chart/{}?period1={}&period2={}&interval=1d",
+        base_url, symbol, start_timestamp, end_timestamp
+    );
+
+    let response = fetch(&url).await?;
+
+    let json: Value = serde_json::from_str(&response).map_err(|e| Error::parse(format!("yahoo chart of {}", symbol), e))?;
+
+    Ok(parse_chart(&json, adjusted))
+}
+
+
+
+// grotesque json unpacking; we take only timestamps and closing (or adjusted closing) prices
+// no timestamps means yahoo doesn't know the symbol, which leaves price_range empty
+pub fn parse_chart(json: &Value, adjusted: bool) -> HashMap<NaiveDate, f64> {
+
     let mut price_range: HashMap<NaiveDate, f64> = HashMap::new();
+    let result = &json["chart"]["result"][0];
+    let series = if adjusted {&result["indicators"]["adjclose"][0]["adjclose"]} else {&result["indicators"]["quote"][0]["close"]};
 
-    let mut symbol: Box<str> = Box::from(symbol);
-    
-    // loop until user provides valid ticker or types ignore
-    loop {
-        let url = format!(
-            "https://query1.finance.yahoo.com/v8/finance/chart/{}?period1={}&period2={}&interval=1d",
-            symbol, start_timestamp, end_timestamp
-        );
-
-        let client = Client::new();
-        let response = client.get(&url)
-            .header(USER_AGENT, "Mozilla/5.0") // Prevents blocking by Yahoo
-            .send()
-            .await?
-            .text()
-            .await?;
-
-        let json: Value = serde_json::from_str(&response)?;
-
-            // grotesque json unpacking; we take only timestamps and closing prices
-            if let Some(timestamps) = json["chart"]["result"][0]["timestamp"].as_array() {
-                if let Some(prices) = json["chart"]["result"][0]["indicators"]["quote"][0]["close"].as_array() {
-        
-                    for (count, timestamp) in timestamps.iter().enumerate() {
-        
-                        // looks complicated but its just pairwaise matching of price and date arrays
-                        // returned by yahoo, using tuples
-                        // vvv
-        
-                        if let (Some(timestamp), Some(price)) = (timestamp.as_i64(), prices.get(count).and_then(|p| p.as_f64())) {
-                            let date = unix_to_date(timestamp);
-                            price_range.insert(date, price);
-                        }
-                    };
-                };
-                break
-            } else {
-                if ["", "ignore", "IGNORE"].contains(&symbol.as_ref()) {
-                    // println!("Could not fetch price data for ticker: _{}_", symbol);
-                    break
-                } else {
-                    
-                    let correct_ticker: Box<str> = match custom_tickers.get(symbol.as_ref()) {     // must be a box pointer for persistence
-                        Some(v) => v.clone().into_boxed_str(),
-                        None => {
-                                println!("   __________________________________________");
-                                let message = format!("Ticker {} from Trading 212 could not be found. What is the correct ticker for {} on Yahoo Finance? Please provide a name or type IGNORE to skip it once", &symbol, &symbol);
-                                for line in wrap(&message, 40){
-                                    println!("   {}", line)
-                                }
-                                println!("   ‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾‾ \n \n");
-
-                                let mut input = String::new();
-                                io::stdin().read_line(&mut input).unwrap();
-                                clear_last_n_lines(1);
-                                let command = input.trim().to_string();
-                                if command.to_ascii_lowercase() != "ignore" {
-                                    custom_tickers.insert(symbol.to_string(), command.to_string());
-                                } else {
-                                    // pass
-                                }
-                                command.into_boxed_str()
-                                }
-                        };
-                    symbol = correct_ticker;
-                    };
-                    
+    if let Some(timestamps) = result["timestamp"].as_array() {
+        if let Some(prices) = series.as_array() {
+
+            for (count, timestamp) in timestamps.iter().enumerate() {
+
+                // looks complicated but its just pairwaise matching of price and date arrays
+                // returned by yahoo, using tuples
+                // vvv
+
+                if let (Some(timestamp), Some(price)) = (timestamp.as_i64(), prices.get(count).and_then(|p| p.as_f64())) {
+                    let date = unix_to_date(timestamp);
+                    price_range.insert(date, price);
+                }
+            };
+        };
+    }
+
+    price_range
+}
+
+
+
+
+// what yahoo's chart says about a listing besides prices: splits between start_date and end_date as
+// (date, new shares per old share), and the currency it is quoted in ("GBp" for pence)
+#[derive(Debug, Default, PartialEq)]
+pub struct Listing {
+    pub splits: Vec<(NaiveDate, Ratio)>,
+    pub currency: Option<String>
+}
+
+// never asks the user anything: unknown tickers just have no splits and no currency.
+// symbol is the one prices come from, i.e. after ticker overrides
+pub async fn get_listing(base_url: &str, symbol: &str, start_date: NaiveDate, end_date: NaiveDate) -> Result<Listing> {
+
+    let url = format!(
+        "{}/v8/finance/chart/{}?period1={}&period2={}&interval=1d&events=split",
+        base_url, symbol, to_unix(start_date - Duration::days(1)), to_unix(end_date + Duration::days(1))
+    );
+
+    let response = fetch(&url).await?;
+
+    let json: Value = serde_json::from_str(&response).map_err(|e| Error::parse(format!("yahoo listing of {}", symbol), e))?;
+
+    Ok(parse_listing(&json))
+}
+
+pub fn parse_listing(json: &Value) -> Listing {
+
+    let result = &json["chart"]["result"][0];
+    let mut splits: Vec<(NaiveDate, Ratio)> = Vec::new();
+
+    // splits come as an object keyed by timestamp: {"1598880600": {"date": 1598880600, "numerator": 4, "denominator": 1, ...}}
+    if let Some(events) = result["events"]["splits"].as_object() {
+        for split in events.values() {
+            if let (Some(timestamp), Some(numerator), Some(denominator)) = (split["date"].as_i64(), split["numerator"].as_u64(), split["denominator"].as_u64()) {
+                if numerator > 0 && denominator > 0 {
+                    splits.push((unix_to_date(timestamp), Ratio::new(numerator, denominator)));
                 }
             }
-    
+        }
+    }
+    splits.sort_by(|a, b| a.0.cmp(&b.0));
+
+    Listing { splits, currency: result["meta"]["currency"].as_str().map(|c| c.to_string()) }
+}
+
+
+
+
+// candidates for a ticker, name or ISIN from yahoo's symbol search, in yahoo's order
+pub async fn search(base_url: &str, query: &str) -> Result<Vec<Candidate>> {
+
+    let url = reqwest::Url::parse_with_params(
+        &format!("{}/v1/finance/search", base_url),
+        &[("q", query), ("quotesCount", "20"), ("newsCount", "0")]
+    ).map_err(|e| Error::parse(format!("search for {}", query), e))?;
+    let response = fetch(url.as_str()).await?;
+    let json: Value = serde_json::from_str(&response).map_err(|e| Error::parse(format!("yahoo search for {}", query), e))?;
+
+    Ok(parse_search(&json))
+}
+
+pub fn parse_search(json: &Value) -> Vec<Candidate> {
+
+    let mut candidates: Vec<Candidate> = Vec::new();
+
+    if let Some(quotes) = json["quotes"].as_array() {
+        for quote in quotes {
+            if let Some(symbol) = quote["symbol"].as_str() {
+                candidates.push(Candidate {
+                    symbol: symbol.to_string(),
+                    name: quote["longname"].as_str().or(quote["shortname"].as_str()).unwrap_or("").to_string(),
+                    exchange: quote["exchange"].as_str().unwrap_or("").to_string(),
+                    quote_type: quote["quoteType"].as_str().unwrap_or("").to_string(),
+                    score: 0
+                });
+            }
+        }
+    }
+    candidates
+}
+
 
-    Ok(price_range)
+
+
+// GET through the shared client, waiting for a free slot on the host first
+async fn fetch(url: &str) -> Result<String> {
+
+    let _slot = HOST.acquire().await.map_err(|e| Error::Api { endpoint: url.to_string(), message: e.to_string() })?;
+    let response = client().get(url)
+        .header(USER_AGENT, "Mozilla/5.0") // Prevents blocking by Yahoo
+        .send()
+        .await?;
+    // unknown symbols are a 404 with a json body that says so, which parse_chart understands. a 429 has no json
+    if response.status().as_u16() == 429 {
+        return Err(Error::from_status(url, response.status()))
+    }
+    let response = response.text().await?;
+    tokio::time::sleep(PAUSE).await;
+
+    Ok(response)
 }
 
 
 
 
 // convert NaiveDate to UNIX timestamp
-fn to_unix(date: NaiveDate) -> i64 {
+pub fn to_unix(date: NaiveDate) -> i64 {
     let datetime = Utc.with_ymd_and_hms(date.year(), date.month(), date.day(), 0, 0, 0).single().unwrap();
     datetime.timestamp()
 }
@@ -110,16 +221,17 @@ fn to_unix(date: NaiveDate) -> i64 {
 
 
 // convert UNIX timestamp to NaiveDate
-fn unix_to_date(timestamp: i64) -> NaiveDate {
+pub fn unix_to_date(timestamp: i64) -> NaiveDate {
     Utc.timestamp_opt(timestamp, 0).unwrap().date_naive()
 }
 
 
 
 
+// e.g. VUSAl_EQ -> VUSA.L, AAPL_US_EQ -> AAPL, SHOP_CA_EQ -> SHOP.TO. an exchange we have no code for is a Mapping error
 pub fn convert_to_yahoo_ticker(
     ticker: String,
-    ) -> String {
+    ) -> Result<String> {
     
         let pre_dict_tickers = HashMap::from([       // exchange codes
             ("a", "AS"),
@@ -138,7 +250,8 @@ pub fn convert_to_yahoo_ticker(
             ("CA", "TO")
             ]);
 
-    let mut returnable_ticker: String = String::new();
+    let unknown = |code: &str| Error::Mapping { ticker: ticker.clone(), message: format!("unknown exchange code {}", code) };
+    let returnable_ticker: String;
 
     if let Some(pos) = ticker.rfind("_EQ") {
         let before_eq = &ticker[..pos];                              // take what's before _EQ
@@ -148,11 +261,11 @@ pub fn convert_to_yahoo_ticker(
 
         if parts.len() == 1 {
             let mut pre = parts[0];
-            let borse = pre.chars().last().unwrap().to_string();
+            let borse = pre.chars().last().map(|c| c.to_string()).unwrap_or_default();
 
             let y_borse = match pre_dict_tickers.get(&*borse) {    // the most deranged deref usage I've done
                 Some(v) => v,
-                None => panic!("couldn't find exchange with postfix: {}", &borse)
+                None => return Err(unknown(&borse))
             }.to_owned();
 
             pre = &pre[..pre.len() - 1];
@@ -166,16 +279,95 @@ pub fn convert_to_yahoo_ticker(
 
         } else if parts.len() == 2 {
             let borse = parts[1];
-            if borse == "US" {return parts[0].to_string()}         // if postfix is "US", then no postfix to yahoo ticker is needed
+            if borse == "US" {return Ok(parts[0].to_string())}     // if postfix is "US", then no postfix to yahoo ticker is needed
 
 
             let y_borse = match post_dict_tickers.get(&*borse) {    
                 Some(v) => v,
-                None => panic!("couldn't find exchange with postfix: {}", &borse)
+                None => return Err(unknown(borse))
             };
             returnable_ticker = format!("{}.{}", parts[0], y_borse);
-        } else {}
+        } else {
+            return Err(Error::Mapping { ticker: ticker.clone(), message: String::from("too many parts") })
+        }
+    } else {
+        return Err(Error::Mapping { ticker: ticker.clone(), message: String::from("not an equity ticker (no _EQ)") })
     };
 
-    returnable_ticker
+    Ok(returnable_ticker)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::day;
+    use std::str::FromStr;
+    use crate::stats::{period_return, total_return_index};
+
+    fn chart() -> Value {
+        serde_json::from_str(include_str!("../tests/fixtures/yahoo_chart_vhyl.json")).unwrap()
+    }
+
+    #[test]
+    fn reads_close_or_adjclose() {
+        let close = parse_chart(&chart(), false);
+        let adjusted = parse_chart(&chart(), true);
+
+        // the null bar at the end is skipped in both
+        assert_eq!(close.len(), 4);
+        assert_eq!(adjusted.len(), 4);
+        assert_eq!(close[&day("2024-03-18")], 6280.0);
+        assert_eq!(adjusted[&day("2024-03-18")], 6190.286);
+        // from the ex-dividend day on both agree
+        assert_eq!(close[&day("2024-03-21")], adjusted[&day("2024-03-21")]);
+    }
+
+    #[test]
+    fn listing_currency_comes_from_meta() {
+        // VHYL.L is quoted in pence
+        assert_eq!(parse_listing(&chart()).currency.as_deref(), Some("GBp"));
+
+        let vusa: Value = serde_json::from_str(r#"{"chart": {"result": [{"meta": {"currency": "GBP", "symbol": "VUSA.L"},
+            "events": {"splits": {"1710748800": {"date": 1710748800, "numerator": 2, "denominator": 1}}}}]}}"#).unwrap();
+        let listing = parse_listing(&vusa);
+        assert_eq!(listing.currency.as_deref(), Some("GBP"));
+        assert_eq!(listing.splits, vec![(day("2024-03-18"), Ratio::new(2, 1))]);
+
+        let unknown: Value = serde_json::from_str(r#"{"chart": {"result": null, "error": {"code": "Not Found"}}}"#).unwrap();
+        assert_eq!(parse_listing(&unknown), Listing::default());
+    }
+
+    #[test]
+    fn total_return_includes_the_distribution() {
+        let close = parse_chart(&chart(), false);
+        let adjusted = parse_chart(&chart(), true);
+
+        let price = period_return(&close, day("2024-03-18"), day("2024-03-22")).unwrap();
+        let total = period_return(&adjusted, day("2024-03-18"), day("2024-03-22")).unwrap();
+        assert!((price - (6250.0/6280.0 - 1.0)*100.0).abs() < 1e-3);
+        assert!((total - (6250.0/6190.286 - 1.0)*100.0).abs() < 1e-3);
+        assert!(total > price);
+
+        let index = total_return_index(&adjusted, day("2024-03-19"), day("2024-03-22"));
+        assert_eq!(index.first(), Some(&(day("2024-03-19"), 100.0)));
+        assert_eq!(index.len(), 3);
+    }
+
+    #[test]
+    fn tickers_map_to_yahoo_symbols_or_say_why_not() {
+        assert_eq!(convert_to_yahoo_ticker(String::from("VUSAl_EQ")).unwrap(), "VUSA.L");
+        assert_eq!(convert_to_yahoo_ticker(String::from("AAPL_US_EQ")).unwrap(), "AAPL");
+        assert_eq!(convert_to_yahoo_ticker(String::from("SHOP_CA_EQ")).unwrap(), "SHOP.TO");
+
+        match convert_to_yahoo_ticker(String::from("XYZq_EQ")) {
+            Err(Error::Mapping { ticker, message }) => {
+                assert_eq!(ticker, "XYZq_EQ");
+                assert!(message.contains("q"));
+            },
+            other => panic!("expected a mapping error, got {:?}", other)
+        }
+        assert!(matches!(convert_to_yahoo_ticker(String::from("ABC_JP_EQ")), Err(Error::Mapping { .. })));
+    }
 }
@@ -0,0 +1,9 @@
+// helpers shared by the integration tests, like the library's test_util for its unit tests
+use std::str::FromStr;
+use chrono::NaiveDate;
+
+
+
+pub fn day(s: &str) -> NaiveDate {
+    NaiveDate::from_str(s).unwrap()
+}
@@ -0,0 +1,233 @@
+// the whole pipeline against local stand-ins for T212 and yahoo: paginated history with a 429 in the middle,
+// prices, and the numbers the engine makes of them. a synthetic USD account, so no fx is involved
+mod common;
+mod mock;
+
+use std::collections::HashMap;
+use rust_decimal::Decimal;
+use common::day;
+use mock::{MockServer, Reply};
+use t212::{dividends, prices::PriceChain, t212 as api, transactions, yahoo::Yahoo, Error, Granularity, Portfolio};
+
+
+
+fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!("{}/tests/fixtures/e2e/{}", env!("CARGO_MANIFEST_DIR"), name)).unwrap()
+}
+
+async fn t212_server() -> MockServer {
+    let server = MockServer::start().await;
+    server.route("/api/v0/equity/history/orders", vec![
+        Reply::json(&fixture("orders_page1.json")),
+        Reply::status(429).header("retry-after", "0"),
+        Reply::json(&fixture("orders_page2.json")),
+        Reply::json(&fixture("orders_empty.json"))
+    ]);
+    server.route("/api/v0/history/dividends", vec![
+        Reply::json(&fixture("dividends_page1.json")),
+        Reply::json(&fixture("dividends_page2.json"))
+    ]);
+    server.route("/api/v0/history/transactions", vec![Reply::json(&fixture("transactions.json"))]);
+    server
+}
+
+
+
+#[tokio::test]
+async fn history_and_prices_from_the_apis_make_the_expected_portfolio() {
+    let t212 = t212_server().await;
+    let key = "e2e-portfolio";         // buckets are per key, and tests run side by side
+
+    let mut orders = api::get_orders(key, &t212.url).await.unwrap();
+    let mut dividends = dividends::get_dividends(key, &t212.url).await.unwrap();
+    let mut transactions = transactions::get_transactions(key, &t212.url).await.unwrap();
+    orders.reverse();
+    dividends.reverse();
+    transactions.reverse();
+
+    // the 429 was retried rather than ending the history there, and each page asked for what came after the last
+    assert_eq!(orders.len(), 4);
+    assert_eq!(t212.queries("/api/v0/equity/history/orders").iter()
+        .map(|query| query.split('&').find(|p| p.starts_with("cursor=")).unwrap_or("").to_string())
+        .collect::<Vec<_>>(),
+        vec!["cursor=", "cursor=1704470400000", "cursor=1704470400000", "cursor=1704207600000"]);
+    assert_eq!(dividends.len(), 2);
+    assert_eq!(transactions.len(), 1);
+
+    let yahoo = MockServer::start().await;
+    yahoo.route("/v8/finance/chart/AAPL", vec![Reply::json(&fixture("yahoo_chart_aapl.json"))]);
+    yahoo.route("/v8/finance/chart/MSFT", vec![Reply::json(&fixture("yahoo_chart_msft.json"))]);
+    let chain = PriceChain::new(vec![Box::new(Yahoo::new(false, &yahoo.url))], vec![String::from("yahoo")], HashMap::new());
+
+    let mut prices = HashMap::new();
+    for symbol in ["AAPL", "MSFT"] {
+        prices.insert(symbol.to_string(), chain.daily_closes(symbol, day("2024-01-02"), day("2024-01-12")).await.unwrap());
+    }
+    assert_eq!(prices["AAPL"][&day("2024-01-12")], 112.0);
+
+    let portfolio = Portfolio::builder("e2e", "USD")
+        .orders(orders)
+        .dividends(dividends)
+        .transactions(transactions)
+        .prices(prices)
+        .end_date(day("2024-01-12"))
+        .build()
+        .unwrap();
+
+    let holdings: HashMap<String, _> = portfolio.holdings().into_iter().map(|h| (h.ticker.clone(), h)).collect();
+    assert_eq!(holdings["AAPL"].quantity.to_string(), "6");
+    assert_eq!(holdings["AAPL"].average_price, Decimal::from(100));
+    assert_eq!(holdings["AAPL"].market_value, Some(672.0));
+    assert_eq!(holdings["MSFT"].quantity.to_string(), "5");
+    assert_eq!(holdings["MSFT"].market_value, Some(1050.0));
+
+    assert_eq!(portfolio.realised_pnl().last().unwrap().1, 40.0);
+    assert_eq!(portfolio.dividends()["AAPL_US_EQ"].to_string(), "1.50 USD");
+    assert_eq!(portfolio.dividends()["MSFT_US_EQ"].to_string(), "1.00 USD");
+    assert_eq!(portfolio.total_fees().to_string(), "-0.02 USD");
+
+    // 2000 in, 440 back from the sale and 2.50 of dividends less the fee as cash, 1722 in shares: 8.224% over
+    // the ten days, which is 1.08224^36.5 - 1 a year
+    let mwrr = portfolio.account_mwrr();
+    assert!((mwrr.cumulative().unwrap() - 0.08224).abs() < 1e-9, "mwrr was {:?}", mwrr);
+    assert!((mwrr.annual.unwrap() - 16.898257).abs() < 1e-5, "mwrr was {:?}", mwrr);
+
+    // all of it happened in January, so the month, quarter and year say the same
+    for granularity in [Granularity::Month, Granularity::Quarter, Granularity::Year] {
+        let periods = portfolio.periods(granularity);
+        assert_eq!(periods.len(), 1);
+        let period = &periods[0];
+        assert_eq!((period.start, period.end), (day("2024-01-02"), day("2024-01-12")));
+        assert_eq!((period.net_contributions, period.realised), (2000.0, 40.0));
+        assert!((period.dividends - 2.5).abs() < 1e-9 && (period.fees + 0.02).abs() < 1e-9);
+        assert!((period.ending_value - 2164.48).abs() < 1e-9);
+        assert!((period.mwrr.unwrap() - 8.224).abs() < 1e-7, "period was {:?}", period);
+    }
+    assert_eq!(portfolio.periods(Granularity::Quarter)[0].label, "2024-Q1");
+}
+
+
+
+#[tokio::test]
+async fn reports_cover_a_window_as_of_a_past_day() {
+    let t212 = t212_server().await;
+    let key = "e2e-window";
+    let mut orders = api::get_orders(key, &t212.url).await.unwrap();
+    let mut dividends = dividends::get_dividends(key, &t212.url).await.unwrap();
+    let mut transactions = transactions::get_transactions(key, &t212.url).await.unwrap();
+    orders.reverse();
+    dividends.reverse();
+    transactions.reverse();
+
+    let yahoo = MockServer::start().await;
+    yahoo.route("/v8/finance/chart/AAPL", vec![Reply::json(&fixture("yahoo_chart_aapl.json"))]);
+    yahoo.route("/v8/finance/chart/MSFT", vec![Reply::json(&fixture("yahoo_chart_msft.json"))]);
+    let chain = PriceChain::new(vec![Box::new(Yahoo::new(false, &yahoo.url))], vec![String::from("yahoo")], HashMap::new());
+    let mut prices = HashMap::new();
+    for symbol in ["AAPL", "MSFT"] {
+        prices.insert(symbol.to_string(), chain.daily_closes(symbol, day("2024-01-02"), day("2024-01-12")).await.unwrap());
+    }
+    let builder = || Portfolio::builder("e2e", "USD")
+        .orders(orders.clone())
+        .dividends(dividends.clone())
+        .transactions(transactions.clone())
+        .prices(prices.clone());
+
+    // as of the 10th the sale and the dividends haven't happened
+    let as_of = builder().end_date(day("2024-01-10")).build().unwrap();
+    let holdings: HashMap<String, _> = as_of.holdings().into_iter().map(|h| (h.ticker.clone(), h)).collect();
+    assert_eq!(holdings["AAPL"].quantity.to_string(), "10");
+    assert_eq!(holdings["AAPL"].market_value, Some(1070.0));
+    assert_eq!(as_of.realised_pnl().last().unwrap(), &(day("2024-01-10"), 0.0));
+    assert!(as_of.dividends().is_empty());
+    assert!(as_of.fees().is_empty());
+
+    // from the 9th, everything is measured from the close of the 8th: 10 AAPL at 105 and 5 MSFT at 205, no cash
+    let window = builder().start_date(day("2024-01-09")).end_date(day("2024-01-12")).build().unwrap();
+    assert_eq!(window.report().start_date, day("2024-01-09"));
+    assert_eq!(window.return_series().first().unwrap().0, day("2024-01-09"));
+    assert_eq!(window.realised_pnl().first().unwrap(), &(day("2024-01-09"), 0.0));
+    assert_eq!(window.realised_pnl().last().unwrap().1, 40.0);
+    assert_eq!(window.total_fees().to_string(), "-0.02 USD");
+    assert_eq!(window.dividends()["AAPL_US_EQ"].to_string(), "1.50 USD");
+
+    // 3.75% unrealised on the 8th, 4.5% on the 9th: 104.5 / 103.75 as a level
+    let first = window.return_series().first().unwrap().1 as f64;
+    assert!((first - (104.5 / 103.75 - 1.0) * 100.0).abs() < 1e-4, "first day returned {}", first);
+
+    // the account was worth 2075 on the 8th and 2164.48 on the 12th, with nothing paid in or out in between
+    let mwrr = window.account_mwrr();
+    assert!((mwrr.cumulative().unwrap() - (2164.48 / 2075.0 - 1.0)).abs() < 1e-9, "mwrr was {:?}", mwrr);
+    // with nothing paid in, the time-weighted return of the window's month is the same
+    let month = &window.periods(Granularity::Month)[0];
+    assert_eq!((month.start, month.net_contributions), (day("2024-01-09"), 0.0));
+    assert!((month.twr.unwrap() - (2164.48 / 2075.0 - 1.0) * 100.0).abs() < 1e-7, "month was {:?}", month);
+    assert!((month.mwrr.unwrap() - month.twr.unwrap()).abs() < 1e-7, "month was {:?}", month);
+
+    assert!(matches!(builder().start_date(day("2024-01-13")).end_date(day("2024-01-12")).build(), Err(Error::Parse { .. })));
+}
+
+
+
+#[tokio::test]
+async fn a_single_day_has_no_annual_figures() {
+    let t212 = t212_server().await;
+    let mut orders = api::get_orders("e2e-one-day", &t212.url).await.unwrap();
+    orders.reverse();
+
+    let yahoo = MockServer::start().await;
+    yahoo.route("/v8/finance/chart/AAPL", vec![Reply::json(&fixture("yahoo_chart_aapl.json"))]);
+    yahoo.route("/v8/finance/chart/MSFT", vec![Reply::json(&fixture("yahoo_chart_msft.json"))]);
+    let chain = PriceChain::new(vec![Box::new(Yahoo::new(false, &yahoo.url))], vec![String::from("yahoo")], HashMap::new());
+    let mut prices = HashMap::new();
+    for symbol in ["AAPL", "MSFT"] {
+        prices.insert(symbol.to_string(), chain.daily_closes(symbol, day("2024-01-02"), day("2024-01-12")).await.unwrap());
+    }
+
+    // as of the day of the first trades, which is no time at all to annualise over
+    let portfolio = Portfolio::builder("e2e", "USD").orders(orders).prices(prices).end_date(day("2024-01-02")).build().unwrap();
+    let summary = portfolio.summary();
+    assert_eq!((summary.annual_return, summary.dividend_yield), (None, None));
+    assert!(summary.current_return.is_finite());
+    assert_eq!(portfolio.report().cash_drag, None);
+}
+
+
+
+#[tokio::test]
+async fn a_rejected_page_fails_the_import_instead_of_cutting_it_short() {
+    let t212 = MockServer::start().await;
+    t212.route("/api/v0/equity/history/orders", vec![
+        Reply::json(&fixture("orders_page1.json")),
+        Reply::status(401)
+    ]);
+
+    match api::get_orders("e2e-revoked", &t212.url).await {
+        Err(Error::Auth { endpoint }) => assert_eq!(endpoint, "/api/v0/equity/history/orders"),
+        other => panic!("expected the key to be rejected, got {:?}", other.map(|orders| orders.len()))
+    }
+}
+
+
+
+#[tokio::test]
+async fn records_repeated_across_a_page_boundary_are_counted_once() {
+    let t212 = MockServer::start().await;
+    t212.route("/api/v0/history/transactions", vec![
+        Reply::json(&fixture("transactions_page1.json")),
+        Reply::json(&fixture("transactions_page2.json"))
+    ]);
+    t212.route("/api/v0/history/dividends", vec![
+        Reply::json(&fixture("dividends_page1.json")),
+        Reply::json(&fixture("dividends_page2_repeat.json"))
+    ]);
+
+    // the deposit the first page ended with comes again on the second, next to another of the same second and amount
+    let transactions = transactions::get_transactions("e2e-overlap", &t212.url).await.unwrap();
+    let references: Vec<&str> = transactions.iter().map(|t| t.reference.as_str()).collect();
+    assert_eq!(references, vec!["wd-1", "dep-2", "dep-3", "dep-1"]);
+    assert_eq!(transactions.iter().map(|t| t.amount).sum::<Decimal>(), Decimal::from(2900));
+
+    let dividends = dividends::get_dividends("e2e-overlap", &t212.url).await.unwrap();
+    assert_eq!(dividends.iter().map(|d| d.reference.as_str()).collect::<Vec<_>>(), vec!["div-2", "div-1"]);
+}
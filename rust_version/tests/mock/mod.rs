@@ -0,0 +1,117 @@
+// a local stand-in for the T212 and yahoo APIs. each path answers with its recorded replies in turn, repeating the
+// last one when they run out; every request is kept so tests can check what was asked, e.g. pagination cursors
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use reqwest::StatusCode;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+
+
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String
+}
+
+impl Reply {
+
+    pub fn json(body: &str) -> Reply {
+        Reply { status: 200, headers: Vec::new(), body: body.to_string() }
+    }
+
+    pub fn status(status: u16) -> Reply {
+        Reply { status, headers: Vec::new(), body: String::new() }
+    }
+
+    pub fn header(mut self, name: &str, value: &str) -> Reply {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+
+
+#[derive(Default)]
+struct State {
+    routes: HashMap<String, Vec<Reply>>,
+    served: HashMap<String, usize>,
+    requests: Vec<(String, String)>          // (path, query)
+}
+
+pub struct MockServer {
+    pub url: String,
+    state: Arc<Mutex<State>>
+}
+
+impl MockServer {
+
+    // listens on a free local port until the test's runtime ends
+    pub async fn start() -> MockServer {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let shared = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve(stream, shared.clone()));
+            }
+        });
+        MockServer { url, state }
+    }
+
+    pub fn route(&self, path: &str, replies: Vec<Reply>) {
+        self.state.lock().unwrap().routes.insert(path.to_string(), replies);
+    }
+
+    // query strings of every request to path, in the order they came
+    pub fn queries(&self, path: &str) -> Vec<String> {
+        self.state.lock().unwrap().requests.iter()
+            .filter(|(p, _)| p == path)
+            .map(|(_, query)| query.clone())
+            .collect()
+    }
+}
+
+
+
+// one request per connection, which is closed after the reply so clients never reuse it across tests
+async fn serve(mut stream: TcpStream, state: Arc<Mutex<State>>) {
+
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        match stream.read(&mut buffer).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => head.extend_from_slice(&buffer[..n])
+        }
+    }
+    let head = String::from_utf8_lossy(&head);
+    let target = head.split_whitespace().nth(1).unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let reply = {
+        let mut state = state.lock().unwrap();
+        state.requests.push((path.to_string(), query.to_string()));
+        let count = state.served.get(path).copied().unwrap_or(0);
+        state.served.insert(path.to_string(), count + 1);
+        match state.routes.get(path) {
+            Some(replies) if !replies.is_empty() => replies[count.min(replies.len() - 1)].clone(),
+            _ => Reply::status(404)
+        }
+    };
+
+    let reason = StatusCode::from_u16(reply.status).ok().and_then(|s| s.canonical_reason()).unwrap_or("");
+    let mut response = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n",
+        reply.status, reason, reply.body.len());
+    for (name, value) in &reply.headers {
+        response.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    response.push_str("\r\n");
+    response.push_str(&reply.body);
+
+    let _ = stream.write_all(response.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
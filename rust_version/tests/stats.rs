@@ -0,0 +1,289 @@
+// golden-file regression tests for the statistics engine. every case is small enough to check by hand, and the
+// hand-worked figures are asserted next to it; the golden files in tests/fixtures/golden pin the full output, so a
+// refactor that moves any number fails here. after an intended change, rerun with UPDATE_GOLDEN=1 and review the diff
+mod common;
+
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+use t212::stats::{self, Convergence, Xirr};
+use rust_decimal::Decimal;
+use rust_decimal::prelude::FromPrimitive;
+use t212::{Currency, Money, Quantity};
+use common::day;
+
+
+
+fn golden(name: &str, actual: Value) {
+    let path = format!("{}/tests/fixtures/golden/{}.json", env!("CARGO_MANIFEST_DIR"), name);
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, serde_json::to_string_pretty(&actual).unwrap() + "\n").unwrap();
+        return
+    }
+    let expected: Value = match std::fs::read_to_string(&path) {
+        Ok(text) => serde_json::from_str(&text).unwrap(),
+        Err(_) => panic!("no golden file {}, run with UPDATE_GOLDEN=1 to write it", path)
+    };
+    assert!(same(&expected, &actual),
+        "{} no longer matches {}\nexpected: {}\n  actual: {}\nrerun with UPDATE_GOLDEN=1 if the change is intended",
+        name, path, expected, actual);
+}
+
+// numbers only need to agree to float noise, anything else exactly
+fn same(expected: &Value, actual: &Value) -> bool {
+    match (expected, actual) {
+        (Value::Number(e), Value::Number(a)) => {
+            let (e, a) = (e.as_f64().unwrap(), a.as_f64().unwrap());
+            (e - a).abs() <= 1e-9 * e.abs().max(1.0)
+        },
+        (Value::Array(e), Value::Array(a)) => e.len() == a.len() && e.iter().zip(a).all(|(e, a)| same(e, a)),
+        (Value::Object(e), Value::Object(a)) => e.len() == a.len() && e.iter().all(|(k, e)| a.get(k).is_some_and(|a| same(e, a))),
+        _ => expected == actual
+    }
+}
+
+// a day's holdings as the book keeps them, quantity and cost basis by ticker, from quantity and average price
+fn holdings(positions: &[(&str, f64, f64)]) -> HashMap<String, (Quantity, Decimal)> {
+    positions.iter().map(|(ticker, q, p)| (ticker.to_string(), (Quantity::from_f64(*q), Decimal::from_f64(q*p).unwrap()))).collect()
+}
+
+// dividends paid by day, in pounds
+fn dividends(paid: &[(&str, f64)]) -> BTreeMap<NaiveDate, Money> {
+    paid.iter().map(|(d, amount)| (day(d), Money::from_f64(*amount, Currency::from_str("GBP").unwrap()))).collect()
+}
+
+fn series(ticker: &str, closes: &[(&str, f64)]) -> (String, HashMap<NaiveDate, f64>) {
+    (ticker.to_string(), closes.iter().map(|(d, p)| (day(d), *p)).collect())
+}
+
+// calc_unreal_returns' two maps, date-sorted
+fn unreal_json(returns: &HashMap<NaiveDate, f64>, cb_mv: &HashMap<NaiveDate, (f64, f64)>) -> Value {
+    let returns: BTreeMap<String, f64> = returns.iter().map(|(d, v)| (d.to_string(), *v)).collect();
+    let cb_mv: BTreeMap<String, [f64; 2]> = cb_mv.iter().map(|(d, (cb, mv))| (d.to_string(), [*cb, *mv])).collect();
+    json!({ "returns": returns, "cost_basis_market_value": cb_mv })
+}
+
+fn assert_near(actual: f64, expected: f64, tolerance: f64) {
+    assert!((actual - expected).abs() <= tolerance, "{} is not within {} of {}", actual, tolerance, expected);
+}
+
+
+
+#[test]
+fn xirr_over_regular_and_irregular_flows() {
+    // rates a year of 365 days, checked against bisection on the npv
+    let cases = [
+        // one deposit, 10% more back a year later
+        ("single_cash_flow", vec![(day("2023-01-01"), -1000.0), (day("2024-01-01"), 1100.0)]),
+        // 21% over two years is 10% a year
+        ("two_years", vec![(day("2022-01-01"), -1000.0), (day("2024-01-01"), 1210.0)]),
+        ("negative", vec![(day("2023-01-01"), -1000.0), (day("2024-01-01"), 800.0)]),
+        // almost everything lost, which used to be clamped at -99%
+        ("near_total_loss", vec![(day("2023-01-01"), -1000.0), (day("2024-01-01"), 1.0)]),
+        // a top-up in April, a withdrawal in September
+        ("irregular", vec![(day("2023-01-01"), -1000.0), (day("2023-04-15"), -500.0), (day("2023-09-10"), 300.0), (day("2024-01-01"), 1400.0)]),
+        // -80.63% over 425 days; Newton from 0.1 runs off below -100%, used to need a flipped guess
+        ("newton_runs_off", vec![(day("2023-01-01"), -1000.0), (day("2023-12-22"), -2206.0), (day("2024-03-01"), 1877.0)]),
+        // 1% in two days is 1.01^182.5 - 1 a year
+        ("two_days", vec![(day("2024-01-02"), -1000.0), (day("2024-01-04"), 1010.0)]),
+        ("deposits_only", vec![(day("2024-01-02"), -1000.0), (day("2024-02-01"), -500.0)]),
+        ("one_day", vec![(day("2024-01-02"), -1000.0), (day("2024-01-02"), 1000.0)])
+    ];
+
+    let results: BTreeMap<&str, Xirr> = cases.iter().map(|(name, flows)| (*name, stats::xirr(flows, 0.1))).collect();
+    let annual = |name: &str| results[name].annual.unwrap();
+    let cumulative = |name: &str| results[name].cumulative().unwrap();
+
+    assert_near(annual("single_cash_flow"), 0.10, 1e-9);
+    assert_near(cumulative("single_cash_flow"), 0.10, 1e-9);
+    assert_near(annual("two_years"), 0.10, 1e-9);
+    assert_near(cumulative("two_years"), 0.21, 1e-9);
+    assert_near(annual("negative"), -0.20, 1e-9);
+    assert_near(annual("near_total_loss"), -0.999, 1e-9);
+    assert_near(annual("irregular"), 0.158508962, 1e-8);
+    assert_near(annual("newton_runs_off"), -0.755838082, 1e-8);
+    assert_near(cumulative("newton_runs_off"), -0.806347885, 1e-8);
+    assert!(matches!(results["newton_runs_off"].convergence, Convergence::Bisection(_)));
+    assert_near(annual("two_days"), 5.146823109, 1e-8);
+    assert_near(cumulative("two_days"), 0.01, 1e-9);
+    assert_eq!(results["deposits_only"].convergence, Convergence::NoRoot);
+    assert_eq!(results["deposits_only"].annual, None);
+    assert_eq!(results["one_day"].convergence, Convergence::TooShort);
+    assert_eq!(stats::xirr(&[], 0.1).convergence, Convergence::TooShort);
+
+    golden("xirr", json!(results.iter()
+        .map(|(name, xirr)| (*name, json!({
+            "annual": xirr.annual,
+            "cumulative": xirr.cumulative(),
+            "convergence": format!("{:?}", xirr.convergence)
+        })))
+        .collect::<BTreeMap<&str, Value>>()));
+}
+
+
+
+#[test]
+fn partial_sell_and_dividends() {
+    // 10 bought at 100, 4 of them sold on the 10th, a 6.00 dividend on the 11th
+    let history = vec![
+        (day("2024-01-08"), holdings(&[("AAPL", 10.0, 100.0)])),
+        (day("2024-01-09"), HashMap::new()),
+        (day("2024-01-10"), holdings(&[("AAPL", 6.0, 100.0)])),
+        (day("2024-01-11"), HashMap::new()),
+        (day("2024-01-12"), HashMap::new())
+    ];
+    let prices = HashMap::from([series("AAPL", &[
+        ("2024-01-08", 100.0), ("2024-01-09", 110.0), ("2024-01-10", 120.0), ("2024-01-11", 90.0), ("2024-01-12", 100.0)])]);
+    let dividends = dividends(&[("2024-01-11", 6.0)]);
+
+    let (returns, cb_mv) = stats::calc_unreal_returns(&history, &prices, &dividends);
+    // 100/1000 * 10*10, then 100/600 * 6*20, 100/600 * (6*-10 + 6), 100/600 * (0 + 6)
+    assert_near(returns[&day("2024-01-09")], 10.0, 1e-12);
+    assert_near(returns[&day("2024-01-10")], 20.0, 1e-12);
+    assert_near(returns[&day("2024-01-11")], -9.0, 1e-12);
+    assert_near(returns[&day("2024-01-12")], 1.0, 1e-12);
+    assert_eq!(cb_mv[&day("2024-01-11")], (600.0, 540.0));
+
+    golden("unreal_returns_partial_sell", unreal_json(&returns, &cb_mv));
+}
+
+
+
+#[test]
+fn full_sell_of_one_holding() {
+    // MSFT sold out on the 10th drops out of the cost basis, whatever its price does afterwards
+    let history = vec![
+        (day("2024-01-08"), holdings(&[("AAPL", 10.0, 100.0), ("MSFT", 5.0, 200.0)])),
+        (day("2024-01-09"), HashMap::new()),
+        (day("2024-01-10"), holdings(&[("AAPL", 10.0, 100.0)])),
+        (day("2024-01-11"), HashMap::new())
+    ];
+    let prices = HashMap::from([
+        series("AAPL", &[("2024-01-08", 100.0), ("2024-01-09", 105.0), ("2024-01-10", 110.0), ("2024-01-11", 108.0)]),
+        series("MSFT", &[("2024-01-08", 200.0), ("2024-01-09", 190.0), ("2024-01-10", 150.0), ("2024-01-11", 100.0)])
+    ]);
+
+    let (returns, cb_mv) = stats::calc_unreal_returns(&history, &prices, &BTreeMap::new());
+    // +50 on AAPL and -50 on MSFT, then AAPL alone: 100/1000 * 10*10 and 100/1000 * 10*8
+    assert_eq!(returns[&day("2024-01-09")], 0.0);
+    assert_near(returns[&day("2024-01-10")], 10.0, 1e-12);
+    assert_near(returns[&day("2024-01-11")], 8.0, 1e-12);
+    assert_eq!(cb_mv[&day("2024-01-09")], (2000.0, 2000.0));
+    assert_eq!(cb_mv[&day("2024-01-11")], (1000.0, 1080.0));
+
+    golden("unreal_returns_full_sell", unreal_json(&returns, &cb_mv));
+}
+
+
+
+#[test]
+fn dividends_only_period() {
+    // a flat price, so all of the return is the dividends paid so far over the 1000 cost basis
+    let history = vec![
+        (day("2024-02-05"), holdings(&[("VHYL.L", 20.0, 50.0)])),
+        (day("2024-02-06"), HashMap::new()),
+        (day("2024-02-07"), HashMap::new()),
+        (day("2024-02-08"), HashMap::new())
+    ];
+    let prices = HashMap::from([series("VHYL.L", &[
+        ("2024-02-05", 50.0), ("2024-02-06", 50.0), ("2024-02-07", 50.0), ("2024-02-08", 50.0)])]);
+    let dividends = dividends(&[("2024-02-06", 10.0), ("2024-02-08", 5.0)]);
+
+    let (returns, cb_mv) = stats::calc_unreal_returns(&history, &prices, &dividends);
+    for ((_, actual), expected) in stats::hashmap_to_sorted_vec(returns.clone()).into_iter().zip([0.0, 1.0, 1.0, 1.5]) {
+        assert_near(actual, expected, 1e-12);
+    }
+
+    golden("unreal_returns_dividends_only", unreal_json(&returns, &cb_mv));
+}
+
+
+
+#[test]
+fn days_without_cost_basis_have_no_return() {
+    // bought on a Saturday with no close until Monday, and a window with nothing held at all
+    let history = vec![
+        (day("2024-01-13"), holdings(&[("AAPL", 10.0, 100.0)])),
+        (day("2024-01-14"), HashMap::new()),
+        (day("2024-01-15"), HashMap::new())
+    ];
+    let prices = HashMap::from([series("AAPL", &[("2024-01-15", 104.0)])]);
+
+    let (returns, cb_mv) = stats::calc_unreal_returns(&history, &prices, &dividends(&[("2024-01-14", 3.0)]));
+    assert_eq!(returns.len(), 1);
+    assert_near(returns[&day("2024-01-15")], 4.3, 1e-12);
+    assert!(returns.values().all(|r| r.is_finite()));
+    assert_eq!(cb_mv[&day("2024-01-13")], (0.0, 0.0));
+
+    let (returns, cb_mv) = stats::calc_unreal_returns(&vec![(day("2024-01-13"), HashMap::new())], &prices, &BTreeMap::new());
+    assert!(returns.is_empty());
+    assert_eq!(cb_mv[&day("2024-01-13")], (0.0, 0.0));
+}
+
+
+
+#[test]
+fn weekend_gaps() {
+    // bought on a Friday, next closes on Monday and Tuesday
+    let mut history = vec![(day("2024-01-12"), holdings(&[("AAPL", 10.0, 100.0)]))];
+    history.extend(["2024-01-13", "2024-01-14", "2024-01-15", "2024-01-16"].iter().map(|d| (day(d), HashMap::new())));
+    let mut prices = HashMap::from([series("AAPL", &[("2024-01-12", 100.0), ("2024-01-15", 104.0), ("2024-01-16", 103.0)])]);
+
+    // the weekend is carried forward from Friday, and marked as such
+    let filled = stats::forward_fill_all(&mut prices, day("2024-01-16"));
+    let (returns, cb_mv) = stats::calc_unreal_returns(&history, &prices, &BTreeMap::new());
+    assert_eq!(returns[&day("2024-01-14")], 0.0);
+    assert_near(returns[&day("2024-01-16")], 3.0, 1e-12);
+
+    // daily returns over trading days only, as the report does, and over every day for comparison:
+    // the flat weekend would otherwise count as two days without risk
+    let cumulative: Vec<(NaiveDate, f32)> = stats::hashmap_to_sorted_vec(returns.clone()).into_iter().map(|(d, v)| (d, v as f32)).collect();
+    let ranges = HashMap::from([(String::from("AAPL"), (day("2024-01-12"), day("2024-01-16")))]);
+    let trading = stats::trading_days(&ranges, &prices, &filled);
+    let on_trading_days: Vec<f32> = cumulative.iter().filter(|(d, _)| trading.contains(d)).map(|(_, v)| *v).collect();
+    let daily_trading = stats::get_daily_returns(on_trading_days);
+    let daily_all = stats::get_daily_returns(stats::strip_dates(cumulative));
+    assert_eq!(daily_trading.len(), 3);
+    assert_near(daily_trading[2] as f64, -100.0/104.0, 1e-5);
+
+    // realised P&L only has entries on the days something was sold; the weekend takes Friday's
+    let mut realised = vec![(day("2024-01-12"), 40.0), (day("2024-01-15"), 55.0)];
+    stats::interpolate(&mut realised);
+
+    golden("weekend_gaps", json!({
+        "unreal": unreal_json(&returns, &cb_mv),
+        "filled": filled["AAPL"].iter().map(|d| d.to_string()).collect::<Vec<String>>(),
+        "daily_returns_trading_days": daily_trading,
+        "daily_returns_all_days": daily_all,
+        "stats_trading_days": stats::mean_sd_sharpe(&daily_trading),
+        "stats_all_days": stats::mean_sd_sharpe(&daily_all),
+        "realised": realised.iter().map(|(d, v)| (d.to_string(), *v)).collect::<BTreeMap<String, f64>>()
+    }));
+}
+
+
+
+#[test]
+fn daily_returns_and_sharpe() {
+    // cumulative returns in percent. the level each day is measured against is the one before plus that day's return
+    // in points, not the compounded index, so after the first day these drift from the index's own changes
+    // (110 -> 120 -> 90 -> 100 would be 10, 9.09, -25, 11.1)
+    let daily = stats::get_daily_returns(vec![10.0, 20.0, -10.0, 0.0]);
+    let expected = [10.0, 100.0/11.0, (90.0 - 1310.0/11.0) / (1310.0/11.0) * 100.0, 5.637417];
+    for (actual, expected) in daily.iter().zip(expected) {
+        assert_near(*actual as f64, expected, 1e-4);
+    }
+
+    // geometric mean, sample sd, and the excess over a 3% a year risk-free rate spread over 252 trading days
+    let (mean, sd, sharpe) = stats::mean_sd_sharpe(&daily);
+    let growth: f64 = expected.iter().map(|r| 1.0 + r/100.0).product();
+    let expected_mean = (growth.powf(0.25) - 1.0) * 100.0;
+    let expected_sd = (expected.iter().map(|r| (r - expected_mean).powi(2)).sum::<f64>() / 3.0).sqrt();
+    let risk_free = (1.03f64.powf(1.0/252.0) - 1.0) * 100.0;
+    assert_near(mean as f64, expected_mean, 1e-4);
+    assert_near(sd as f64, expected_sd, 1e-3);
+    assert_near(sharpe as f64, (expected_mean - risk_free) / expected_sd, 1e-4);
+
+    golden("daily_returns_and_sharpe", json!({ "daily": daily, "mean_sd_sharpe": [mean, sd, sharpe] }));
+}